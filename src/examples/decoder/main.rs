@@ -29,20 +29,11 @@ fn main() {
           name = "Product 2"
     "###;
 
-    let value = match toml::parse_from_bytes(toml.as_bytes()) {
+    let value = match toml::parse_from_str(toml) {
         Ok(v) => v,
-        Err(toml::ParseError) => {
-            println!("parse error");
-            os::set_exit_status(1);
-            return;
-        },
-        Err(toml::ParseErrorInField(field)) => {
-            println!("parse error in field `{}`", field);
-            os::set_exit_status(1);
-            return;
-        },
-        Err(toml::IOError(e)) => {
-            println!("I/O error: {}", e);
+        Err(e) => {
+            // shows the offending line along with the message
+            println!("{}", e.render(toml));
             os::set_exit_status(1);
             return;
         }
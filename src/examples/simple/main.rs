@@ -10,18 +10,14 @@ fn main() {
   }
   let value = match toml::parse_from_file(os::args().get(1).as_slice()) {
     Ok(v) => v,
-    Err(toml::ParseError) => {
-      println!("parse error");
+    Err(toml::IOErrorInFile(path, e)) => {
+      println!("I/O error reading {}: {}", path.display(), e);
       os::set_exit_status(1);
       return;
     },
-    Err(toml::ParseErrorInField(field)) => {
-      println!("parse error in field `{}`", field);
-      os::set_exit_status(1);
-      return;
-    },
-    Err(toml::IOError(e)) => {
-      println!("I/O error: {}", e);
+    // syntax errors print as e.g. "line 3, column 7: expected `=` after key"
+    Err(e) => {
+      println!("error: {}", e);
       os::set_exit_status(1);
       return;
     },
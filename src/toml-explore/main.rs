@@ -0,0 +1,176 @@
+extern crate toml = "github.com/mneumann/rust-toml#toml";
+
+use std::io;
+use std::io::IoResult;
+use std::os;
+
+// An interactive explorer for TOML documents:
+//
+//     ./toml-explore config.toml
+//     > ls server
+//     > get server.port
+//
+// Paths are those of `Value::lookup`, the empty path denotes the root.
+
+static HELP: &'static str = "commands:
+  ls [path]     list the keys of a table, or the indices of an array
+  get <path>    print a value
+  type <path>   print the type of a value
+  find <word>   list all paths whose last key contains <word>
+  help          show this help
+  quit          exit";
+
+fn lookup<'a>(root: &'a toml::Value, path: &'a str) -> Option<&'a toml::Value> {
+    if path.is_empty() { Some(root) } else { root.lookup(path) }
+}
+
+fn ls(out: &mut Writer, value: &toml::Value) -> IoResult<()> {
+    match *value {
+        toml::Table(ref map) | toml::TableInner(ref map) => {
+            let mut keys: Vec<&String> = map.keys().collect();
+            keys.sort();
+            for key in keys.iter() {
+                try!(write!(out, "{}\t{}\n", key, map.get(*key).type_name()));
+            }
+            Ok(())
+        }
+        toml::Array(ref vec) | toml::TableArray(ref vec) => {
+            for (i, elm) in vec.iter().enumerate() {
+                try!(write!(out, "{}\t{}\n", i, elm.type_name()));
+            }
+            Ok(())
+        }
+        _ => out.write_str("not a table or array\n")
+    }
+}
+
+fn get(out: &mut Writer, value: &toml::Value) -> IoResult<()> {
+    match *value {
+        toml::Table(..) | toml::TableInner(..) => out.write_str(toml::encode(value).as_slice()),
+        toml::TableArray(ref vec) => {
+            for elm in vec.iter() {
+                try!(out.write_str("---\n"));
+                try!(out.write_str(toml::encode(elm).as_slice()));
+            }
+            Ok(())
+        }
+        _ => write!(out, "{}\n", toml::encode(value))
+    }
+}
+
+fn find(out: &mut Writer, root: &toml::Value, word: &str) -> IoResult<()> {
+    for path in root.paths().iter() {
+        let last = path.as_slice().split('.').last().unwrap();
+        if last.contains(word) {
+            try!(write!(out, "{}\n", path));
+        }
+    }
+    Ok(())
+}
+
+// Runs the command on `line`, writing its output to `out`. Returns `false`
+// if the explorer should exit.
+fn run(out: &mut Writer, root: &toml::Value, line: &str) -> IoResult<bool> {
+    let mut words = line.words();
+    let cmd = match words.next() {
+        Some(cmd) => cmd,
+        None => return Ok(true)
+    };
+    let arg = words.next().unwrap_or("");
+
+    match cmd {
+        "ls" | "get" | "type" => {
+            match lookup(root, arg) {
+                Some(value) => {
+                    match cmd {
+                        "ls" => try!(ls(out, value)),
+                        "get" => try!(get(out, value)),
+                        _ => try!(write!(out, "{}\n", value.type_name()))
+                    }
+                }
+                None => {
+                    match root.lookup_checked(arg) {
+                        Err(e) => try!(write!(out, "{}\n", e)),
+                        Ok(_) => try!(out.write_str("not found\n"))
+                    }
+                }
+            }
+        }
+        "find" if !arg.is_empty() => try!(find(out, root, arg)),
+        "help" => try!(write!(out, "{}\n", HELP)),
+        "quit" | "exit" => return Ok(false),
+        _ => try!(out.write_str("unknown command, try `help`\n"))
+    }
+    Ok(true)
+}
+
+fn main() {
+    let args = os::args();
+    if args.len() < 2 {
+        println!("usage: ./toml-explore input-file");
+        os::set_exit_status(1);
+        return;
+    }
+    let root = match toml::parse_from_file(args.get(1).as_slice()) {
+        Ok(v) => v,
+        Err(e) => {
+            println!("error: {}", e);
+            os::set_exit_status(1);
+            return;
+        }
+    };
+
+    let mut stdin = io::stdin();
+    let mut stdout = io::stdout();
+    loop {
+        print!("> ");
+        io::stdio::flush();
+        let line = match stdin.read_line() {
+            Ok(line) => line,
+            Err(_) => break
+        };
+        match run(&mut stdout, &root, line.as_slice()) {
+            Ok(true) => {}
+            Ok(false) | Err(_) => break
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::run;
+
+    use std::io::MemWriter;
+
+    static DOC: &'static str = "[server]\nport = 80\nhost = \"a\"\n[[db]]\npassword = \"x\"\n";
+
+    // The output of `line`, run on `DOC`
+    fn output(line: &str) -> String {
+        let root = ::toml::parse_from_str(DOC).unwrap();
+        let mut out = MemWriter::new();
+        assert!(run(&mut out, &root, line).unwrap());
+        String::from_utf8(out.unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_commands() {
+        assert_eq!(output("ls").as_slice(), "db\ttable array\nserver\ttable\n");
+        assert_eq!(output("ls server").as_slice(), "host\tstring\nport\tinteger\n");
+        assert_eq!(output("get server.port").as_slice(), "80\n");
+        assert_eq!(output("get db").as_slice(), "---\npassword = \"x\"\n");
+        assert_eq!(output("type server.host").as_slice(), "string\n");
+        assert_eq!(output("find pass").as_slice(), "db.0.password\n");
+        assert_eq!(output("").as_slice(), "");
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(output("ls server.port").as_slice(), "not a table or array\n");
+        assert_eq!(output("frobnicate").as_slice(), "unknown command, try `help`\n");
+        assert_eq!(output("find").as_slice(), "unknown command, try `help`\n");
+        assert!(output("get server.prot").as_slice().starts_with("key `prot` not found"));
+
+        let root = ::toml::parse_from_str(DOC).unwrap();
+        assert_eq!(run(&mut MemWriter::new(), &root, "quit"), Ok(false));
+    }
+}
@@ -0,0 +1,118 @@
+// Memoized parsing of files.
+//
+// A file is parsed again only if its modification time or size changed since
+// it was last loaded. Documents are shared through an `Arc`, so loading a
+// cached file is cheap. There is no process-wide cache: callers create a
+// `Cache` and keep it for as long as its documents should be reused.
+
+use super::{Value, Error, IOErrorInFile, parse_from_path};
+
+use std::collections::hashmap::HashMap;
+use std::io::fs;
+use std::os;
+use std::path::Path;
+use sync::{Arc, Mutex};
+
+struct Entry {
+    modified: u64,
+    size: u64,
+    value: Arc<Value>
+}
+
+/// A cache of parsed files, keyed by absolute path
+pub struct Cache {
+    entries: Mutex<HashMap<Path, Entry>>
+}
+
+impl Cache {
+    pub fn new() -> Cache {
+        Cache { entries: Mutex::new(HashMap::new()) }
+    }
+
+    /// Returns the document in the file at `path`, parsing it only if it is
+    /// not cached or has been modified since. Failures are not cached.
+    pub fn load(&self, path: &Path) -> Result<Arc<Value>, Error> {
+        let path = os::make_absolute(path);
+        let stat = match fs::stat(&path) {
+            Ok(stat) => stat,
+            Err(e) => return Err(IOErrorInFile(path.clone(), e))
+        };
+
+        {
+            let entries = self.entries.lock();
+            match entries.find(&path) {
+                Some(entry) if entry.modified == stat.modified && entry.size == stat.size => {
+                    return Ok(entry.value.clone());
+                }
+                _ => {}
+            }
+        }
+
+        // parse without holding the lock, so other files can be loaded meanwhile
+        let value = Arc::new(try!(parse_from_path(&path)));
+        let mut entries = self.entries.lock();
+        entries.insert(path, Entry { modified: stat.modified, size: stat.size, value: value.clone() });
+        Ok(value)
+    }
+
+    /// Drops all cached documents
+    pub fn clear(&self) {
+        self.entries.lock().clear();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::{Value, Integer, IOErrorInFile};
+    use super::Cache;
+
+    use std::io::{File, TempDir};
+    use std::path::Path;
+
+    // Writes `contents` to file `name` in `dir`, returning its path
+    fn write(dir: &Path, name: &str, contents: &str) -> Path {
+        let path = dir.join(name);
+        File::create(&path).write_str(contents).unwrap();
+        path
+    }
+
+    fn same(a: &Value, b: &Value) -> bool {
+        a as *const Value == b as *const Value
+    }
+
+    #[test]
+    fn test_cache() {
+        let dir = TempDir::new("toml-cached").unwrap();
+        let path = write(dir.path(), "a.toml", "port = 80");
+        let cache = Cache::new();
+        let first = cache.load(&path).unwrap();
+        assert_eq!(first.lookup("port"), Some(&Integer(80)));
+        assert!(same(&*first, &*cache.load(&path).unwrap()));
+
+        // a change of size is noticed even within the same second
+        write(dir.path(), "a.toml", "port = 8080");
+        let second = cache.load(&path).unwrap();
+        assert_eq!(second.lookup("port"), Some(&Integer(8080)));
+        assert!(!same(&*first, &*second));
+
+        cache.clear();
+        assert!(!same(&*second, &*cache.load(&path).unwrap()));
+    }
+
+    #[test]
+    fn test_cache_errors() {
+        let dir = TempDir::new("toml-cached").unwrap();
+        let cache = Cache::new();
+        let missing = dir.path().join("missing.toml");
+        match cache.load(&missing).err() {
+            Some(IOErrorInFile(path, _)) => assert!(path == missing),
+            other => fail!("unexpected {}", other)
+        }
+
+        // failures are not cached
+        let path = write(dir.path(), "b.toml", "port = ");
+        assert!(cache.load(&path).is_err());
+        write(dir.path(), "b.toml", "port = 1");
+        assert_eq!(cache.load(&path).unwrap().lookup("port"), Some(&Integer(1)));
+    }
+}
@@ -0,0 +1,119 @@
+// Structural comparison of documents.
+//
+// Two documents are equal if they hold the same data, regardless of
+// formatting, comments and key order. Tables are compared key by key, no
+// matter whether they had a section header (`Table`) or were created
+// implicitly (`TableInner`).
+
+use super::{Value, Error, Float, Array, TableArray, Append, Table, TableInner};
+use super::parse_from_path;
+
+use std::path::Path;
+
+/// How far two floats may differ and still be considered equal. The default
+/// of zero for both requires exact equality.
+#[deriving(Show,Clone,PartialEq,Default)]
+pub struct FloatTolerance {
+    /// Maximum absolute difference
+    pub absolute: f64,
+    /// Maximum difference relative to the larger magnitude of the two
+    pub relative: f64
+}
+
+fn floats_equal(a: f64, b: f64, tol: &FloatTolerance) -> bool {
+    if a.is_nan() || b.is_nan() { return a.is_nan() && b.is_nan() }
+    if a == b { return true }
+    let diff = (a - b).abs();
+    diff <= tol.absolute || diff <= tol.relative * a.abs().max(b.abs())
+}
+
+/// Returns `true` if `a` and `b` hold the same data, with floats compared
+/// according to `tol`. NaN equals NaN.
+pub fn values_equal(a: &Value, b: &Value, tol: &FloatTolerance) -> bool {
+    match (a, b) {
+        (&Float(x), &Float(y)) => floats_equal(x, y, tol),
+        (&Array(ref x), &Array(ref y)) | (&TableArray(ref x), &TableArray(ref y)) |
+        (&Append(ref x), &Append(ref y)) => {
+            x.len() == y.len() &&
+                x.iter().zip(y.iter()).all(|(x, y)| values_equal(x, y, tol))
+        }
+        (&Table(ref x), &Table(ref y)) | (&Table(ref x), &TableInner(ref y)) |
+        (&TableInner(ref x), &Table(ref y)) | (&TableInner(ref x), &TableInner(ref y)) => {
+            x.len() == y.len() &&
+                x.iter().all(|(k, v)| y.find(k).map_or(false, |w| values_equal(v, w, tol)))
+        }
+        (&Float(_), _) | (&Array(_), _) | (&TableArray(_), _) | (&Append(_), _) |
+        (&Table(_), _) | (&TableInner(_), _) => false,
+        _ => a == b
+    }
+}
+
+/// Parses the files at `a` and `b` and returns whether they hold the same
+/// data (see `values_equal`), comparing floats exactly.
+pub fn files_equal(a: &Path, b: &Path) -> Result<bool, Error> {
+    let a = try!(parse_from_path(a));
+    let b = try!(parse_from_path(b));
+    Ok(values_equal(&a, &b, &Default::default()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::{Float, Array, IOErrorInFile, parse_from_str};
+    use super::{FloatTolerance, values_equal, files_equal};
+
+    use std::io::{File, TempDir};
+    use std::path::Path;
+
+    // Writes `contents` to file `name` in `dir`, returning its path
+    fn write(dir: &Path, name: &str, contents: &str) -> Path {
+        let path = dir.join(name);
+        File::create(&path).write_str(contents).unwrap();
+        path
+    }
+
+    fn equal(a: &str, b: &str) -> bool {
+        values_equal(&parse_from_str(a).unwrap(), &parse_from_str(b).unwrap(), &Default::default())
+    }
+
+    #[test]
+    fn test_values_equal() {
+        assert!(equal("a = 1 # one\n[t]\nb = [1, 2]\nc = \"x\"", "[t]\nc = \"x\"\nb = [ 1, 2 ]\n\na = 1"));
+        assert!(equal("[t.u]\nk = 1", "[t]\n[t.u]\nk = 1"));
+        assert!(!equal("a = 1", "a = 2"));
+        assert!(!equal("a = 1", "a = 1\nb = 2"));
+        assert!(!equal("a = [1, 2]", "a = [2, 1]"));
+        assert!(!equal("a = [1.0]", "a = 1.0"));
+        assert!(!equal("[a]", "[[a]]"));
+    }
+
+    #[test]
+    fn test_values_equal_floats() {
+        let exact: FloatTolerance = Default::default();
+        assert!(values_equal(&Float(::std::f64::NAN), &Float(::std::f64::NAN), &exact));
+        assert!(!values_equal(&Float(1.0), &Float(1.001), &exact));
+
+        let absolute = FloatTolerance { absolute: 0.01, relative: 0.0 };
+        assert!(values_equal(&Array(vec!(Float(1.0))), &Array(vec!(Float(1.001))), &absolute));
+        assert!(!values_equal(&Float(1.0), &Float(1.1), &absolute));
+
+        let relative = FloatTolerance { absolute: 0.0, relative: 0.01 };
+        assert!(values_equal(&Float(1000.0), &Float(1005.0), &relative));
+        assert!(!values_equal(&Float(1.0), &Float(1.05), &relative));
+    }
+
+    #[test]
+    fn test_files_equal() {
+        let dir = TempDir::new("toml-compare").unwrap();
+        let a = write(dir.path(), "a.toml", "x = 1\ny = 2");
+        let b = write(dir.path(), "b.toml", "# reordered\ny = 2\nx = 1\n");
+        let c = write(dir.path(), "c.toml", "x = 1");
+        assert_eq!(files_equal(&a, &b), Ok(true));
+        assert_eq!(files_equal(&a, &c), Ok(false));
+
+        let missing = dir.path().join("missing.toml");
+        match files_equal(&a, &missing) {
+            Err(IOErrorInFile(path, _)) => assert!(path == missing),
+            other => fail!("unexpected {}", other)
+        }
+    }
+}
@@ -0,0 +1,492 @@
+// Loading configuration from the usual places.
+//
+// A `ConfigLoader` holds a list of candidate files, ordered from lowest to
+// highest precedence. All existing files are parsed and merged, so that keys
+// of later files override those of earlier ones, while tables are merged
+// key by key.
+//
+// `Layers` generalizes this to other sources of settings, like environment
+// variables and command line flags, and records for every key which layer
+// supplied its final value.
+//
+// `SharedConfig` makes the result available to many tasks at once.
+
+use super::{Value, Error, ParseErrorInField, IOError, IOErrorInFile, String, Table, TableInner, MergeStrategy};
+use super::{ParserOptions, parse_from_path, parse_from_buffer_with, value_from_str, in_file};
+
+use std::ascii::StrAsciiExt;
+use std::collections::hashmap::HashMap;
+use std::io::{fs, File, BufferedReader};
+use std::mem;
+use std::os;
+use std::path::Path;
+use sync::{Arc, RWLock};
+
+pub struct ConfigLoader {
+    paths: Vec<Path>
+}
+
+impl ConfigLoader {
+    /// Creates a loader for application `name` searching, in order of
+    /// increasing precedence, `/etc/<name>/config.toml`,
+    /// `$HOME/.config/<name>/config.toml` and `./<name>.toml`.
+    pub fn new(name: &str) -> ConfigLoader {
+        let mut loader = ConfigLoader::empty();
+        loader.add_path(Path::new("/etc").join(name).join("config.toml"));
+        match os::homedir() {
+            Some(home) => loader.add_path(home.join(".config").join(name).join("config.toml")),
+            None => {}
+        }
+        loader.add_path(Path::new(format!("{}.toml", name)));
+        loader
+    }
+
+    /// Creates a loader without any search paths
+    pub fn empty() -> ConfigLoader {
+        ConfigLoader { paths: vec!() }
+    }
+
+    /// Adds `path` with a higher precedence than all paths added before
+    pub fn add_path(&mut self, path: Path) {
+        self.paths.push(path);
+    }
+
+    /// The search paths, in order of increasing precedence
+    pub fn paths<'a>(&'a self) -> &'a [Path] {
+        self.paths.as_slice()
+    }
+
+    /// Parses and merges all existing files. Returns the merged document
+    /// together with the files actually loaded. Missing files are skipped,
+    /// while a file failing to parse aborts loading.
+    pub fn load(&self) -> Result<(Value, Vec<Path>), Error> {
+        let mut root = TableInner(box HashMap::new());
+        let mut loaded = vec!();
+        for path in self.paths.iter() {
+            if !path.is_file() { continue }
+            let value = try!(parse_from_path(path));
+            try!(merge(&mut root, value));
+            loaded.push(path.clone());
+        }
+        Ok((root, loaded))
+    }
+}
+
+enum Source {
+    Defaults(Value),
+    File(Path),
+    Overrides(Path),
+    Env(String),
+    Flags(Vec<String>)
+}
+
+/// Configuration assembled from several layers, each overriding the ones
+/// added before it, e.g. `Layers::new().defaults(value).file(path)
+/// .env("MYAPP").flags(assignments).resolve()`.
+pub struct Layers {
+    sources: Vec<Source>
+}
+
+/// The result of `Layers::resolve`
+pub struct Resolved {
+    /// The merged document
+    pub value: Value,
+    /// Maps the dotted path of every leaf of `value` to the name of the
+    /// layer which supplied it
+    pub origins: HashMap<String, String>
+}
+
+impl Resolved {
+    /// Returns the name of the layer which supplied the value at `path`,
+    /// e.g. `defaults`, `file /etc/myapp.toml`, `env MYAPP_SERVER__PORT` or
+    /// `flag server.port=80`.
+    pub fn origin<'a>(&'a self, path: &str) -> Option<&'a str> {
+        self.origins.find_equiv(&path).map(|s| s.as_slice())
+    }
+
+    fn add(&mut self, origin: String, value: Value) -> Result<(), Error> {
+        let mut leaves = vec!();
+        leaf_paths(&value, String::new(), &mut leaves);
+        // a leaf replaces the tables below it, and a table the leaves above
+        // it, along with their origins
+        let replaced: Vec<String> = self.origins.keys().filter(|old| {
+            leaves.iter().any(|new| is_below(old.as_slice(), new.as_slice()) ||
+                                    is_below(new.as_slice(), old.as_slice()))
+        }).map(|old| old.clone()).collect();
+        for old in replaced.iter() {
+            self.origins.pop(old);
+        }
+        for path in leaves.move_iter() {
+            self.origins.insert(path, origin.clone());
+        }
+        merge(&mut self.value, value)
+    }
+}
+
+impl Layers {
+    pub fn new() -> Layers {
+        Layers { sources: vec!() }
+    }
+
+    /// Adds a layer of built-in default values
+    pub fn defaults(mut self, value: Value) -> Layers {
+        self.sources.push(Defaults(value));
+        self
+    }
+
+    /// Adds a layer parsed from the file at `path`. The layer is skipped if
+    /// the file does not exist.
+    pub fn file(mut self, path: Path) -> Layers {
+        self.sources.push(File(path));
+        self
+    }
+
+    /// Like `file`, but the file may use `key += [...]` to append elements to
+    /// the array `key` of the layers below. Resolving fails with
+    /// `AppendNotArray` if `key` is set to anything but an array there.
+    pub fn overrides(mut self, path: Path) -> Layers {
+        self.sources.push(Overrides(path));
+        self
+    }
+
+    /// Adds a layer from the environment variables starting with
+    /// `<prefix>_`. The rest of the variable name, lowercased, is the key,
+    /// with `__` separating the segments of a dotted path, so that
+    /// `MYAPP_SERVER__PORT=80` sets `server.port`. Values are parsed as
+    /// TOML values, falling back to strings.
+    pub fn env(mut self, prefix: &str) -> Layers {
+        self.sources.push(Env(prefix.to_string()));
+        self
+    }
+
+    /// Adds a layer from `key=value` assignments, e.g. as collected from
+    /// repeated `--set` command line options. Keys are dotted paths, values
+    /// are parsed as TOML values, falling back to strings.
+    pub fn flags(mut self, assignments: &[String]) -> Layers {
+        self.sources.push(Flags(assignments.iter().map(|a| a.clone()).collect()));
+        self
+    }
+
+    /// Merges all layers. Fails with `ParseErrorInField(assignment)` on a
+    /// flag without `=`.
+    pub fn resolve(self) -> Result<Resolved, Error> {
+        let mut resolved = Resolved { value: TableInner(box HashMap::new()),
+                                      origins: HashMap::new() };
+        for source in self.sources.move_iter() {
+            match source {
+                Defaults(value) => try!(resolved.add("defaults".to_string(), value)),
+                File(path) => {
+                    if !path.is_file() { continue }
+                    let value = try!(parse_from_path(&path));
+                    try!(resolved.add(format!("file {}", path.display()), value));
+                }
+                Overrides(path) => {
+                    if !path.is_file() { continue }
+                    let file = match File::open(&path) {
+                        Ok(file) => file,
+                        Err(e) => return Err(IOErrorInFile(path.clone(), e))
+                    };
+                    let mut rd = BufferedReader::new(file);
+                    let value = try!(parse_from_buffer_with(&mut rd, ParserOptions::new().allow_append(true))
+                                     .map_err(|e| in_file(&path, e)));
+                    try!(resolved.add(format!("file {}", path.display()), value));
+                }
+                Env(prefix) => {
+                    let prefix = format!("{}_", prefix);
+                    let mut vars = os::env();
+                    vars.sort();
+                    for &(ref name, ref val) in vars.iter() {
+                        if !name.as_slice().starts_with(prefix.as_slice()) { continue }
+                        let key = name.as_slice().slice_from(prefix.len())
+                                      .to_ascii_lower().replace("__", ".");
+                        try!(resolved.add(format!("env {}", name), assignment(key.as_slice(), val.as_slice())));
+                    }
+                }
+                Flags(assignments) => {
+                    for a in assignments.iter() {
+                        let pos = match a.as_slice().find('=') {
+                            Some(pos) => pos,
+                            None => return Err(ParseErrorInField(a.clone()))
+                        };
+                        let key = a.as_slice().slice_to(pos).trim();
+                        let val = a.as_slice().slice_from(pos + 1).trim();
+                        try!(resolved.add(format!("flag {}", a), assignment(key, val)));
+                    }
+                }
+            }
+        }
+        Ok(resolved)
+    }
+}
+
+// Builds a document consisting of the single setting `path = val`
+fn assignment(path: &str, val: &str) -> Value {
+    let mut value = value_from_str(val).unwrap_or(String(val.to_string()));
+    for key in path.split('.').rev() {
+        let mut map = box HashMap::new();
+        map.insert(key.to_string(), value);
+        value = TableInner(map);
+    }
+    value
+}
+
+// Whether the dotted `path` lies within the table at `table`
+fn is_below(path: &str, table: &str) -> bool {
+    path.len() > table.len() && path.starts_with(table) && path.char_at(table.len()) == '.'
+}
+
+// Collects the dotted paths of all non-table values of `value`. Arrays are
+// leaves, as merging replaces them as a whole.
+fn leaf_paths(value: &Value, prefix: String, out: &mut Vec<String>) {
+    match *value {
+        Table(ref map) | TableInner(ref map) => {
+            for (key, val) in map.iter() {
+                let path = if prefix.is_empty() { key.clone() }
+                           else { format!("{}.{}", prefix, key) };
+                leaf_paths(val, path, out);
+            }
+        }
+        _ => out.push(prefix)
+    }
+}
+
+/// A read-only configuration shared between tasks. Cloning the handle is
+/// cheap, and all clones see the document installed by the latest `swap`,
+/// e.g. after reloading the configuration.
+#[deriving(Clone)]
+pub struct SharedConfig {
+    current: Arc<RWLock<Arc<Value>>>
+}
+
+impl SharedConfig {
+    pub fn new(value: Value) -> SharedConfig {
+        SharedConfig { current: Arc::new(RWLock::new(Arc::new(value))) }
+    }
+
+    /// Returns the current document. It stays valid (and unchanged) even if
+    /// another document is swapped in meanwhile.
+    pub fn get(&self) -> Arc<Value> {
+        self.current.read().clone()
+    }
+
+    /// Replaces the document seen by all handles, returning the old one
+    pub fn swap(&self, value: Value) -> Arc<Value> {
+        let mut current = self.current.write();
+        mem::replace(&mut *current, Arc::new(value))
+    }
+}
+
+/// Parses every `*.toml` file in directory `dir`, in sorted filename order,
+/// and merges them into one document, so that later files override earlier
+/// ones (the `conf.d` convention). On failure, returns the offending path
+/// along with the error.
+pub fn parse_dir(dir: &Path) -> Result<Value, (Path, Error)> {
+    let mut files = match fs::readdir(dir) {
+        Ok(files) => files,
+        Err(e) => return Err((dir.clone(), IOError(e)))
+    };
+    files.retain(|f| f.extension_str() == Some("toml") && f.is_file());
+    files.sort_by(|a, b| a.filename().cmp(&b.filename()));
+
+    let mut root = TableInner(box HashMap::new());
+    for file in files.iter() {
+        match parse_from_path(file).and_then(|value| merge(&mut root, value)) {
+            Ok(()) => {}
+            Err(e) => return Err((file.clone(), e))
+        }
+    }
+    Ok(root)
+}
+
+impl Value {
+    /// Returns the effective configuration for profile `name`, following the
+    /// `[profile.<name>]` convention: the root document without its
+    /// `profile` table, overridden by `[profile.default]` if present, in
+    /// turn overridden by `[profile.<name>]`. Returns `None` if there is no
+    /// profile `name`, or if a profile appends (`key += [...]`) to a value
+    /// other than an array.
+    pub fn select_profile(&self, name: &str) -> Option<Value> {
+        let profile = match self.lookup_elm(&"profile").and_then(|p| p.lookup_elm(&name)) {
+            Some(profile) => profile.clone(),
+            None => return None
+        };
+        let mut effective = self.clone();
+        match effective {
+            Table(ref mut map) | TableInner(ref mut map) => {
+                map.pop(&"profile".to_string());
+            }
+            _ => return None
+        }
+        if name != "default" {
+            match self.lookup_elm(&"profile").and_then(|p| p.lookup_elm(&"default")) {
+                Some(default) => {
+                    if merge(&mut effective, default.clone()).is_err() { return None }
+                }
+                None => {}
+            }
+        }
+        match merge(&mut effective, profile) {
+            Ok(()) => Some(effective),
+            Err(_) => None
+        }
+    }
+}
+
+// Merges `src` into `dst`, so that its values take precedence. The elements
+// of an `Append` are appended to an array in `dst`. The result contains no
+// `Append` values. Overwriting never fails, but appending to anything other
+// than an array does, with `AppendNotArray`.
+fn merge(dst: &mut Value, src: Value) -> Result<(), Error> {
+    dst.merge(src, MergeStrategy::new())
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::{Integer, String, Array, TableInner, ParserOptions, ParseErrorInField};
+    use super::super::{parse_from_str, parse_from_buffer_with};
+    use super::{ConfigLoader, Layers, SharedConfig, parse_dir};
+
+    use std::collections::hashmap::HashMap;
+    use std::io::{BufReader, File, TempDir};
+    use std::path::Path;
+
+    // Writes `contents` to file `name` in `dir`, returning its path
+    fn write(dir: &Path, name: &str, contents: &str) -> Path {
+        let path = dir.join(name);
+        File::create(&path).write_str(contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_loader() {
+        let dir = TempDir::new("toml-config").unwrap();
+        let system = write(dir.path(), "system.toml", "[server]\nport = 80\nhost = \"a\"");
+        let user = write(dir.path(), "user.toml", "[server]\nport = 8080");
+        let mut loader = ConfigLoader::empty();
+        loader.add_path(system.clone());
+        loader.add_path(dir.path().join("missing.toml"));
+        loader.add_path(user.clone());
+        assert_eq!(loader.paths().len(), 3);
+
+        let (value, loaded) = loader.load().unwrap();
+        assert_eq!(value.lookup("server.port"), Some(&Integer(8080)));
+        assert_eq!(value.lookup("server.host"), Some(&String("a".to_string())));
+        assert!(loaded == vec!(system, user));
+
+        write(dir.path(), "missing.toml", "port = ");
+        assert!(loader.load().is_err());
+    }
+
+    #[test]
+    fn test_loader_paths() {
+        let loader = ConfigLoader::new("app");
+        let paths = loader.paths();
+        assert!(paths[0] == Path::new("/etc/app/config.toml"));
+        assert!(paths[paths.len() - 1] == Path::new("app.toml"));
+
+        let (value, loaded) = ConfigLoader::empty().load().unwrap();
+        assert_eq!(value, TableInner(box HashMap::new()));
+        assert!(loaded.is_empty());
+    }
+
+    #[test]
+    fn test_layers() {
+        let defaults = parse_from_str("[server]\nport = 80\nhost = \"a\"").unwrap();
+        let flags = ["server.port=8080".to_string()];
+        let resolved = Layers::new().defaults(defaults).flags(flags.as_slice()).resolve().unwrap();
+        assert_eq!(resolved.value.lookup("server.port"), Some(&Integer(8080)));
+        assert_eq!(resolved.origin("server.port"), Some("flag server.port=8080"));
+        assert_eq!(resolved.origin("server.host"), Some("defaults"));
+    }
+
+    #[test]
+    fn test_origins_of_replaced_tables() {
+        let defaults = parse_from_str("[server]\nport = 80\nlog = \"off\"").unwrap();
+        let flags = ["server.log.level=3".to_string(), "server=off".to_string()];
+        let resolved = Layers::new().defaults(defaults.clone()).flags(flags.slice_to(1)).resolve().unwrap();
+        assert_eq!(resolved.origin("server.log"), None);
+        assert_eq!(resolved.origin("server.log.level"), Some("flag server.log.level=3"));
+
+        let resolved = Layers::new().defaults(defaults).flags(flags.as_slice()).resolve().unwrap();
+        assert_eq!(resolved.value.lookup("server"), Some(&String("off".to_string())));
+        assert_eq!(resolved.origin("server"), Some("flag server=off"));
+        assert_eq!(resolved.origin("server.port"), None);
+        assert_eq!(resolved.origin("server.log.level"), None);
+        assert_eq!(resolved.origins.len(), 1);
+    }
+
+    #[test]
+    fn test_flag_without_value() {
+        let flags = ["port=80".to_string(), "port".to_string()];
+        match Layers::new().flags(flags.as_slice()).resolve() {
+            Err(err) => {
+                assert_eq!(err, ParseErrorInField("port".to_string()));
+                assert_eq!(format!("{}", err).as_slice(), "parse error in field `port`");
+            }
+            Ok(_) => fail!("a flag without value was accepted")
+        }
+    }
+
+    #[test]
+    fn test_select_profile() {
+        let root = parse_from_str("port = 80\nhost = \"a\"\n\
+                                   [profile.default]\nport = 81\n\
+                                   [profile.dev]\nhost = \"localhost\"\n").unwrap();
+        let dev = root.select_profile("dev").unwrap();
+        assert_eq!(dev.lookup("port"), Some(&Integer(81)));
+        assert_eq!(dev.lookup("host"), Some(&String("localhost".to_string())));
+        assert_eq!(dev.lookup("profile"), None);
+
+        let default = root.select_profile("default").unwrap();
+        assert_eq!(default.lookup("port"), Some(&Integer(81)));
+        assert_eq!(default.lookup("host"), Some(&String("a".to_string())));
+
+        assert_eq!(root.select_profile("prod"), None);
+        assert_eq!(parse_from_str("port = 80").unwrap().select_profile("dev"), None);
+    }
+
+    #[test]
+    fn test_select_profile_appending() {
+        let doc = "xs = [1]\nport = 80\n[profile.dev]\nxs += [2]\n[profile.bad]\nport += [81]\n";
+        let mut rd = BufReader::new(doc.as_bytes());
+        let root = parse_from_buffer_with(&mut rd, ParserOptions::new().allow_append(true)).unwrap();
+        assert_eq!(root.select_profile("dev").unwrap().lookup("xs"), Some(&Array(vec!(Integer(1), Integer(2)))));
+        assert_eq!(root.select_profile("bad"), None);
+    }
+
+    #[test]
+    fn test_parse_dir() {
+        let dir = TempDir::new("toml-conf.d").unwrap();
+        write(dir.path(), "20-local.toml", "[server]\nport = 8080");
+        write(dir.path(), "10-base.toml", "[server]\nport = 80\nhost = \"a\"");
+        write(dir.path(), "30-notes.txt", "not toml");
+        let value = parse_dir(dir.path()).unwrap();
+        assert_eq!(value.lookup("server.port"), Some(&Integer(8080)));
+        assert_eq!(value.lookup("server.host"), Some(&String("a".to_string())));
+
+        let broken = write(dir.path(), "15-broken.toml", "[server");
+        match parse_dir(dir.path()) {
+            Err((path, _)) => assert!(path == broken),
+            Ok(_) => fail!("parsed a broken file")
+        }
+
+        assert!(parse_dir(&dir.path().join("missing")).is_err());
+    }
+
+    #[test]
+    fn test_shared_config() {
+        let config = SharedConfig::new(parse_from_str("port = 80").unwrap());
+        let handle = config.clone();
+        let before = config.get();
+        let old = handle.swap(parse_from_str("port = 8080").unwrap());
+        assert_eq!(old.lookup("port"), Some(&Integer(80)));
+        assert_eq!(before.lookup("port"), Some(&Integer(80)));
+        assert_eq!(config.get().lookup("port"), Some(&Integer(8080)));
+
+        let (tx, rx) = channel();
+        spawn(proc() {
+            tx.send(handle.get().lookup("port").and_then(|p| p.get_int()));
+        });
+        assert_eq!(rx.recv(), Some(8080));
+    }
+}
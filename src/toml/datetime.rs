@@ -0,0 +1,417 @@
+// Date and time values.
+//
+// TOML datetimes are RFC 3339 timestamps, e.g. `1979-05-27T07:32:00Z`.
+// `Datetime` is formatted in and parsed from that notation by its `Show`
+// and `FromStr` implementations.
+
+use std::fmt;
+use std::from_str::FromStr;
+use time::Tm;
+
+/// A date and time of day, optionally with a fraction of a second and an
+/// offset from UTC.
+#[deriving(Clone,PartialEq,Eq)]
+pub struct Datetime {
+    year: u16,
+    month: u8,
+    day: u8,
+    hour: u8,
+    minute: u8,
+    second: u8,
+    nanosecond: u32,
+    // offset from UTC in minutes, `None` for a local time
+    offset: Option<i16>
+}
+
+impl Datetime {
+    /// Creates a UTC datetime from its positional parts, the fields of the
+    /// former `Datetime(year, month, day, hour, minute, second)` value.
+    pub fn new(year: u16, month: u8, day: u8, hour: u8, minute: u8, second: u8) -> Datetime {
+        Datetime {
+            year: year, month: month, day: day,
+            hour: hour, minute: minute, second: second,
+            nanosecond: 0, offset: Some(0)
+        }
+    }
+
+    /// Returns a copy with the fraction of the second set to `nanosecond`
+    pub fn with_nanosecond(&self, nanosecond: u32) -> Datetime {
+        Datetime { nanosecond: nanosecond, ..*self }
+    }
+
+    /// Returns a copy with the offset from UTC set to `offset` minutes, or
+    /// without offset (a local time) for `None`.
+    pub fn with_offset(&self, offset: Option<i16>) -> Datetime {
+        Datetime { offset: offset, ..*self }
+    }
+
+    pub fn year(&self) -> u16 { self.year }
+    pub fn month(&self) -> u8 { self.month }
+    pub fn day(&self) -> u8 { self.day }
+    pub fn hour(&self) -> u8 { self.hour }
+    pub fn minute(&self) -> u8 { self.minute }
+    pub fn second(&self) -> u8 { self.second }
+    pub fn nanosecond(&self) -> u32 { self.nanosecond }
+
+    /// The offset from UTC in minutes, `None` for a local time
+    pub fn offset(&self) -> Option<i16> { self.offset }
+
+    /// Creates a UTC datetime from seconds since the Unix epoch
+    pub fn from_timestamp(secs: i64) -> Datetime {
+        let days = div_floor(secs, 86400);
+        let secs_of_day = secs - days * 86400;
+        let (year, month, day) = civil_from_days(days);
+        Datetime::new(year as u16, month, day, (secs_of_day / 3600) as u8,
+                      (secs_of_day / 60 % 60) as u8, (secs_of_day % 60) as u8)
+    }
+
+    /// Creates a datetime from a broken-down `time::Tm`, keeping its UTC
+    /// offset.
+    pub fn from_tm(tm: &Tm) -> Datetime {
+        Datetime {
+            year: (tm.tm_year + 1900) as u16,
+            month: (tm.tm_mon + 1) as u8,
+            day: tm.tm_mday as u8,
+            hour: tm.tm_hour as u8,
+            minute: tm.tm_min as u8,
+            // a leap second is folded into the last regular one
+            second: ::std::cmp::min(tm.tm_sec, 59) as u8,
+            nanosecond: tm.tm_nsec as u32,
+            offset: Some((tm.tm_gmtoff / 60) as i16)
+        }
+    }
+
+    /// Returns the seconds since the Unix epoch. A local time (without
+    /// offset) is taken to be UTC.
+    pub fn to_timestamp(&self) -> i64 {
+        let days = days_from_civil(self.year as i64, self.month as i64, self.day as i64);
+        let secs = days * 86400 + self.hour as i64 * 3600 +
+                   self.minute as i64 * 60 + self.second as i64;
+        secs - self.offset.unwrap_or(0) as i64 * 60
+    }
+
+    /// Returns the time elapsed from `other` to `self` as whole seconds plus
+    /// nanoseconds, both negative if `self` is earlier than `other`. Offsets
+    /// are taken into account, local times are taken to be UTC.
+    pub fn duration_since(&self, other: &Datetime) -> (i64, i32) {
+        let mut secs = self.to_timestamp() - other.to_timestamp();
+        let mut nanos = self.nanosecond as i32 - other.nanosecond as i32;
+        if secs > 0 && nanos < 0 {
+            secs -= 1;
+            nanos += 1_000_000_000;
+        } else if secs < 0 && nanos > 0 {
+            secs += 1;
+            nanos -= 1_000_000_000;
+        }
+        (secs, nanos)
+    }
+
+    /// Converts into a broken-down `time::Tm` in the datetime's own offset
+    pub fn to_tm(&self) -> Tm {
+        let days = days_from_civil(self.year as i64, self.month as i64, self.day as i64);
+        let jan1 = days_from_civil(self.year as i64, 1, 1);
+        Tm {
+            tm_sec: self.second as i32,
+            tm_min: self.minute as i32,
+            tm_hour: self.hour as i32,
+            tm_mday: self.day as i32,
+            tm_mon: self.month as i32 - 1,
+            tm_year: self.year as i32 - 1900,
+            // 1970-01-01 was a Thursday
+            tm_wday: ((days % 7 + 11) % 7) as i32,
+            tm_yday: (days - jan1) as i32,
+            tm_isdst: 0,
+            tm_gmtoff: self.offset.unwrap_or(0) as i32 * 60,
+            tm_nsec: self.nanosecond as i32
+        }
+    }
+
+    /// Returns the positional parts `(year, month, day, hour, minute, second)`
+    pub fn to_tuple(&self) -> (u16, u8, u8, u8, u8, u8) {
+        (self.year, self.month, self.day, self.hour, self.minute, self.second)
+    }
+}
+
+impl fmt::Show for Datetime {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(fmt, "{:04u}-{:02u}-{:02u}T{:02u}:{:02u}:{:02u}",
+                    self.year, self.month, self.day, self.hour, self.minute, self.second));
+        if self.nanosecond > 0 {
+            let frac = format!("{:09u}", self.nanosecond);
+            try!(write!(fmt, ".{}", frac.as_slice().trim_right_chars('0')));
+        }
+        match self.offset {
+            None => Ok(()),
+            Some(0) => write!(fmt, "Z"),
+            Some(off) => {
+                let sign = if off < 0 { '-' } else { '+' };
+                let off = if off < 0 { -off } else { off };
+                write!(fmt, "{}{:02d}:{:02d}", sign, off / 60, off % 60)
+            }
+        }
+    }
+}
+
+impl FromStr for Datetime {
+    /// Parses an RFC 3339 timestamp such as `1979-05-27T07:32:00.5-07:00`.
+    /// The offset may be omitted for a local time.
+    fn from_str(s: &str) -> Option<Datetime> {
+        let mut rd = Reader { bytes: s.as_bytes(), pos: 0 };
+
+        let year = rd.digits(4);
+        if !rd.expect('-') { return None }
+        let month = rd.digits(2);
+        if !rd.expect('-') { return None }
+        let day = rd.digits(2);
+        if !(rd.expect('T') || rd.expect('t')) { return None }
+        let hour = rd.digits(2);
+        if !rd.expect(':') { return None }
+        let minute = rd.digits(2);
+        if !rd.expect(':') { return None }
+        let second = rd.digits(2);
+
+        let mut nanosecond = 0u32;
+        if rd.expect('.') {
+            let mut ndigits = 0u;
+            loop {
+                match rd.digits(1) {
+                    Some(d) => {
+                        // digits beyond nanosecond precision are dropped
+                        if ndigits < 9 { nanosecond = nanosecond * 10 + d }
+                        ndigits += 1;
+                    }
+                    None => break
+                }
+            }
+            if ndigits == 0 { return None }
+            for _ in range(ndigits, 9) { nanosecond *= 10 }
+        }
+
+        let offset = if rd.eos() {
+            None
+        } else if rd.expect('Z') || rd.expect('z') {
+            Some(0i16)
+        } else {
+            let sign = if rd.expect('+') { 1 } else if rd.expect('-') { -1 } else { return None };
+            let h = rd.digits(2);
+            if !rd.expect(':') { return None }
+            let m = rd.digits(2);
+            match (h, m) {
+                (Some(h), Some(m)) if h < 24 && m < 60 => Some(sign * (h * 60 + m) as i16),
+                _ => return None
+            }
+        };
+        if !rd.eos() { return None }
+
+        match (year, month, day, hour, minute, second) {
+            (Some(y), Some(m), Some(d), Some(h), Some(mi), Some(s))
+                if m >= 1 && m <= 12 && d >= 1 && d <= days_in_month(y as i64, m as u8) as u32 &&
+                   h < 24 && mi < 60 && s <= 60 => {
+                Some(Datetime {
+                    year: y as u16, month: m as u8, day: d as u8,
+                    hour: h as u8, minute: mi as u8, second: s as u8,
+                    nanosecond: nanosecond, offset: offset
+                })
+            }
+            _ => None
+        }
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: uint
+}
+
+impl<'a> Reader<'a> {
+    fn eos(&self) -> bool { self.pos >= self.bytes.len() }
+
+    fn expect(&mut self, c: char) -> bool {
+        if !self.eos() && self.bytes[self.pos] == c as u8 {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    // Reads exactly `n` decimal digits
+    fn digits(&mut self, n: uint) -> Option<u32> {
+        let mut num = 0u32;
+        for _ in range(0, n) {
+            if self.eos() { return None }
+            let b = self.bytes[self.pos];
+            if b < '0' as u8 || b > '9' as u8 { return None }
+            num = num * 10 + (b - '0' as u8) as u32;
+            self.pos += 1;
+        }
+        Some(num)
+    }
+}
+
+fn is_leap_year(year: i64) -> bool {
+    (year % 4 == 0 && year % 100 != 0) || year % 400 == 0
+}
+
+fn days_in_month(year: i64, month: u8) -> u8 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31
+    }
+}
+
+fn div_floor(a: i64, b: i64) -> i64 {
+    if a >= 0 { a / b } else { (a - b + 1) / b }
+}
+
+// Number of days since 1970-01-01 of the given proleptic Gregorian date.
+// See http://howardhinnant.github.io/date_algorithms.html
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = div_floor(y, 400);
+    let yoe = y - era * 400;
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+// Inverse of `days_from_civil`
+fn civil_from_days(days: i64) -> (i64, u8, u8) {
+    let z = days + 719468;
+    let era = div_floor(z, 146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month as u8, day as u8)
+}
+
+#[cfg(test)]
+mod test {
+    use super::Datetime;
+    use super::super::{parse_from_str, encode};
+
+    #[test]
+    fn test_parts() {
+        let dt = Datetime::new(1979, 5, 27, 7, 32, 0);
+        assert_eq!((dt.year(), dt.month(), dt.day()), (1979, 5, 27));
+        assert_eq!((dt.hour(), dt.minute(), dt.second(), dt.nanosecond()), (7, 32, 0, 0));
+        assert_eq!(dt.offset(), Some(0));
+        assert_eq!(dt.to_tuple(), (1979, 5, 27, 7, 32, 0));
+
+        let local = dt.with_nanosecond(500).with_offset(None);
+        assert_eq!((local.nanosecond(), local.offset()), (500, None));
+        assert!(local != dt);
+        assert_eq!(local.with_nanosecond(0).with_offset(Some(0)), dt);
+    }
+
+    #[test]
+    fn test_parsed_value() {
+        let value = parse_from_str("dt = 1979-05-27T07:32:00Z").unwrap();
+        match value.lookup("dt") {
+            Some(&super::super::Datetime(ref dt)) => assert_eq!(*dt, Datetime::new(1979, 5, 27, 7, 32, 0)),
+            found => fail!("unexpected value {}", found)
+        }
+    }
+
+    #[test]
+    fn test_get_datetime() {
+        let doc = "dt = 1979-05-27T07:32:00.5Z\ns = \"1979-05-27T07:32:00Z\"";
+        let value = parse_from_str(doc).unwrap();
+        let dt = value.lookup("dt").and_then(|dt| dt.get_datetime()).unwrap();
+        assert_eq!((dt.year(), dt.second(), dt.nanosecond()), (1979, 0, 500000000));
+        // strings are not parsed as datetimes
+        assert!(value.lookup("s").and_then(|s| s.get_datetime()).is_none());
+    }
+
+    #[test]
+    fn test_timestamps() {
+        let dt = Datetime::new(1979, 5, 27, 7, 32, 0);
+        assert_eq!(dt.to_timestamp(), 296638320);
+        assert_eq!(Datetime::from_timestamp(296638320), dt);
+        assert_eq!(Datetime::from_timestamp(0), Datetime::new(1970, 1, 1, 0, 0, 0));
+        assert_eq!(Datetime::from_timestamp(-1), Datetime::new(1969, 12, 31, 23, 59, 59));
+        assert_eq!(Datetime::from_timestamp(951825600), Datetime::new(2000, 2, 29, 12, 0, 0));
+        // the same instant, seven hours behind UTC
+        assert_eq!(Datetime::new(1979, 5, 27, 0, 32, 0).with_offset(Some(-420)).to_timestamp(), 296638320);
+        // local times are taken to be UTC
+        assert_eq!(dt.with_offset(None).to_timestamp(), 296638320);
+    }
+
+    #[test]
+    fn test_tm() {
+        let dt = Datetime::new(1979, 5, 27, 7, 32, 0).with_nanosecond(5).with_offset(Some(60));
+        let tm = dt.to_tm();
+        assert_eq!((tm.tm_year, tm.tm_mon, tm.tm_mday), (79, 4, 27));
+        assert_eq!((tm.tm_hour, tm.tm_min, tm.tm_sec, tm.tm_nsec), (7, 32, 0, 5));
+        // a Sunday, the 147th day of the year
+        assert_eq!((tm.tm_wday, tm.tm_yday), (0, 146));
+        assert_eq!(tm.tm_gmtoff, 3600);
+        assert_eq!(Datetime::from_tm(&tm), dt);
+    }
+
+    #[test]
+    fn test_rfc3339() {
+        let dt = Datetime::new(1979, 5, 27, 7, 32, 0);
+        assert_eq!(format!("{}", dt).as_slice(), "1979-05-27T07:32:00Z");
+        let offset = dt.with_nanosecond(500000000).with_offset(Some(-420));
+        assert_eq!(format!("{}", offset).as_slice(), "1979-05-27T07:32:00.5-07:00");
+        assert_eq!(format!("{}", dt.with_offset(None)).as_slice(), "1979-05-27T07:32:00");
+
+        assert_eq!(from_str::<Datetime>("1979-05-27T07:32:00Z"), Some(dt));
+        assert_eq!(from_str::<Datetime>("1979-05-27t07:32:00z"), Some(dt));
+        assert_eq!(from_str::<Datetime>("1979-05-27T07:32:00.5-07:00"), Some(offset));
+        assert_eq!(from_str::<Datetime>("1979-05-27T07:32:00"), Some(dt.with_offset(None)));
+        // digits beyond nanoseconds are dropped
+        assert_eq!(from_str::<Datetime>("1979-05-27T07:32:00.1234567891Z"), Some(dt.with_nanosecond(123456789)));
+        assert_eq!(from_str::<Datetime>("2000-02-29T00:00:00Z"), Some(Datetime::new(2000, 2, 29, 0, 0, 0)));
+
+        for s in ["1979-13-01T00:00:00Z", "1979-02-29T00:00:00Z", "1979-05-27 07:32:00Z",
+                  "1979-05-27T24:00:00Z", "1979-05-27T07:32:00.Z", "1979-05-27T07:32:00+25:00",
+                  "1979-05-27T07:32:00Zjunk", "79-05-27T07:32:00Z", ""].iter() {
+            assert_eq!(from_str::<Datetime>(*s), None);
+        }
+    }
+
+    #[test]
+    fn test_duration_since() {
+        let start = Datetime::new(1979, 5, 27, 7, 32, 0).with_nanosecond(750000000);
+        let end = Datetime::new(1979, 5, 27, 7, 33, 0).with_nanosecond(250000000);
+        assert_eq!(end.duration_since(&start), (59, 500000000));
+        assert_eq!(start.duration_since(&end), (-59, -500000000));
+        assert_eq!(start.duration_since(&start), (0, 0));
+        // the same instant in another offset
+        let shifted = Datetime::new(1979, 5, 27, 8, 32, 0).with_nanosecond(750000000).with_offset(Some(60));
+        assert_eq!(shifted.duration_since(&start), (0, 0));
+    }
+
+    #[test]
+    fn test_parse_encoded() {
+        let dt = Datetime::new(1979, 5, 27, 7, 32, 0);
+        let cases = [dt.clone(), dt.with_nanosecond(999999000), dt.with_offset(Some(-420)),
+                     dt.with_nanosecond(500000000).with_offset(Some(330)), dt.with_offset(None)];
+        for dt in cases.iter() {
+            let mut value = parse_from_str("").unwrap();
+            assert!(value.set("dt", super::super::Datetime(dt.clone())));
+            let parsed = parse_from_str(encode(&value).as_slice()).unwrap();
+            assert_eq!(parsed.lookup("dt").and_then(|v| v.get_datetime()), Some(dt));
+        }
+    }
+
+    #[test]
+    fn test_parse_validates_dates() {
+        assert!(parse_from_str("dt = 1980-02-29T23:59:60Z").is_ok());
+        for doc in ["dt = 1979-02-29T00:00:00Z", "dt = 1979-04-31T00:00:00Z",
+                    "dt = 1979-05-27T24:00:00Z", "dt = 1979-05-27T07:60:00Z",
+                    "dt = 1979-05-27T07:32:00+24:00", "dt = 1979-05-27T07:32:00.Z",
+                    "dt = 1979-05-27T07:32Z"].iter() {
+            assert!(parse_from_str(*doc).is_err(), "{} parsed", doc);
+        }
+    }
+}
@@ -0,0 +1,168 @@
+// Colored terminal output of Value trees.
+//
+// The tree is printed in TOML notation, with keys in sorted order: first the
+// scalar values of a table, then its sub-tables and table arrays under
+// `[section]` headers. Keys, strings, numbers, booleans and datetimes are
+// highlighted with distinct ANSI colors.
+
+use super::{Value, NoValue, Boolean, Integer, Float, String, Datetime,
+            Array, TableArray, Append, TableInner, Table};
+
+use std::collections::hashmap::HashMap;
+use std::io::{IoResult, Writer};
+
+static RESET: &'static str = "\x1b[0m";
+static BOLD: &'static str = "\x1b[1m";
+static DIM: &'static str = "\x1b[2m";
+static GREEN: &'static str = "\x1b[32m";
+static YELLOW: &'static str = "\x1b[33m";
+static BLUE: &'static str = "\x1b[34m";
+static MAGENTA: &'static str = "\x1b[35m";
+static CYAN: &'static str = "\x1b[36m";
+
+/// Pretty-prints `value` to `w` using ANSI colors
+pub fn dump_colored(value: &Value, w: &mut Writer) -> IoResult<()> {
+    dump_colored_to_depth(value, w, None)
+}
+
+/// Like `dump_colored`, but omits the contents of tables nested deeper than
+/// `max_depth`, where the tables directly below the root are at depth 1.
+pub fn dump_colored_to_depth(value: &Value, w: &mut Writer, max_depth: Option<uint>) -> IoResult<()> {
+    match *value {
+        Table(ref map) | TableInner(ref map) => write_table(w, &**map, "", 0, max_depth),
+        _ => {
+            try!(write_value(w, value));
+            w.write_str("\n")
+        }
+    }
+}
+
+fn is_table(value: &Value) -> bool {
+    match *value {
+        Table(_) | TableInner(_) | TableArray(_) => true,
+        _ => false
+    }
+}
+
+fn write_value(w: &mut Writer, value: &Value) -> IoResult<()> {
+    match *value {
+        NoValue => Ok(()),
+        Boolean(b) => write!(w, "{}{}{}", MAGENTA, b, RESET),
+        Integer(n) => write!(w, "{}{}{}", YELLOW, n, RESET),
+        Float(f) => write!(w, "{}{}{}", YELLOW, f, RESET),
+        String(ref s) => write!(w, "{}\"{}\"{}", GREEN, s.as_slice().escape_default(), RESET),
+        Datetime(ref dt) => write!(w, "{}{}{}", CYAN, dt, RESET),
+        Array(ref vec) | Append(ref vec) => {
+            try!(w.write_str("["));
+            for (i, elm) in vec.iter().enumerate() {
+                if i > 0 { try!(w.write_str(", ")) }
+                try!(write_value(w, elm));
+            }
+            w.write_str("]")
+        }
+        Table(_) | TableInner(_) | TableArray(_) => {
+            write!(w, "{}\\{...\\}{}", DIM, RESET)
+        }
+    }
+}
+
+fn write_table(w: &mut Writer, map: &HashMap<String, Value>, path: &str, depth: uint,
+               max_depth: Option<uint>) -> IoResult<()> {
+    let mut keys: Vec<&String> = map.keys().collect();
+    keys.sort();
+
+    for key in keys.iter() {
+        let val = map.get(*key);
+        if is_table(val) { continue }
+        let op = match *val { Append(_) => "+=", _ => "=" };
+        try!(write!(w, "{}{}{} {} ", BLUE, key, RESET, op));
+        try!(write_value(w, val));
+        try!(w.write_str("\n"));
+    }
+
+    for key in keys.iter() {
+        let child = if path.is_empty() { key.to_string() } else { format!("{}.{}", path, key) };
+        match *map.get(*key) {
+            Table(ref sub) | TableInner(ref sub) => {
+                try!(write!(w, "\n{}[{}]{}\n", BOLD, child, RESET));
+                try!(write_body(w, &**sub, child.as_slice(), depth + 1, max_depth));
+            }
+            TableArray(ref vec) => {
+                for elm in vec.iter() {
+                    try!(write!(w, "\n{}[[{}]]{}\n", BOLD, child, RESET));
+                    match *elm {
+                        Table(ref sub) | TableInner(ref sub) => {
+                            try!(write_body(w, &**sub, child.as_slice(), depth + 1, max_depth));
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+// Writes the contents of a table below its header, unless it is too deep
+fn write_body(w: &mut Writer, map: &HashMap<String, Value>, path: &str, depth: uint,
+              max_depth: Option<uint>) -> IoResult<()> {
+    match max_depth {
+        Some(max) if depth > max => {
+            if map.is_empty() { Ok(()) } else { write!(w, "{}\\# ...{}\n", DIM, RESET) }
+        }
+        _ => write_table(w, map, path, depth, max_depth)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::{Value, Integer, String, parse_from_str};
+    use super::{dump_colored, dump_colored_to_depth, YELLOW, RESET};
+
+    use std::io::MemWriter;
+
+    fn dump(value: &Value, max_depth: Option<uint>) -> String {
+        let mut w = MemWriter::new();
+        dump_colored_to_depth(value, &mut w, max_depth).unwrap();
+        String::from_utf8(w.unwrap()).unwrap()
+    }
+
+    // `s` without ANSI escape sequences
+    fn plain(s: String) -> String {
+        let mut out = String::new();
+        let mut in_escape = false;
+        for c in s.as_slice().chars() {
+            match c {
+                '\x1b' => in_escape = true,
+                'm' if in_escape => in_escape = false,
+                _ if !in_escape => out.push_char(c),
+                _ => {}
+            }
+        }
+        out
+    }
+
+    fn dump_plain(doc: &str, max_depth: Option<uint>) -> String {
+        plain(dump(&parse_from_str(doc).unwrap(), max_depth))
+    }
+
+    #[test]
+    fn test_dump_colored() {
+        let doc = "z = true\na = 1979-05-27T07:32:00Z\n[t]\nb = \"x\"\n[[t.h]]\nn = [1, 2]\n";
+        assert_eq!(dump_plain(doc, None).as_slice(),
+                   "a = 1979-05-27T07:32:00Z\nz = true\n\n[t]\nb = \"x\"\n\n[[t.h]]\nn = [1, 2]\n");
+
+        let mut w = MemWriter::new();
+        dump_colored(&Integer(1), &mut w).unwrap();
+        assert_eq!(String::from_utf8(w.unwrap()).unwrap(), format!("{}1{}\n", YELLOW, RESET));
+        assert_eq!(plain(dump(&String("say \"hi\"".to_string()), None)).as_slice(), "\"say \\\"hi\\\"\"\n");
+    }
+
+    #[test]
+    fn test_dump_colored_to_depth() {
+        let doc = "[a]\nx = 1\n[a.b]\ny = 2\n[a.b.c]\n[d]\n";
+        assert_eq!(dump_plain(doc, Some(1)).as_slice(), "\n[a]\nx = 1\n\n[a.b]\n# ...\n\n[d]\n");
+        assert_eq!(dump_plain(doc, Some(0)).as_slice(), "\n[a]\n# ...\n\n[d]\n");
+    }
+}
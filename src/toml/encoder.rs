@@ -0,0 +1,348 @@
+// Emitting Value trees as TOML documents.
+//
+// Keys are emitted in sorted order, or in the order of the source document
+// given its spans. Within a table, plain values come first, followed by
+// sub-tables and table arrays under their `[section]` and `[[section]]`
+// headers. Tables created implicitly (`TableInner`) only get a header if they
+// directly contain plain values. Tables within arrays are emitted as inline
+// tables. Keys which are not bare words are quoted. `Append` values are
+// written as plain arrays, as `key += [...]` is only accepted with
+// `ParserOptions::allow_append`.
+
+use super::{Value, NoValue, Boolean, Integer, Float, String, Datetime,
+            Array, TableArray, Append, TableInner, Table};
+use super::compare::values_equal;
+use super::{SpanMap, join_path};
+
+use std::collections::hashmap::HashMap;
+use std::uint;
+
+fn is_section(value: &Value) -> bool {
+    match *value {
+        Table(_) | TableInner(_) | TableArray(_) => true,
+        _ => false
+    }
+}
+
+fn write_str(out: &mut String, s: &str) {
+    out.push_char('"');
+    for c in s.chars() {
+        match c {
+            '\u0008' => out.push_str("\\b"),
+            '\t' => out.push_str("\\t"),
+            '\n' => out.push_str("\\n"),
+            '\u000C' => out.push_str("\\f"),
+            '\r' => out.push_str("\\r"),
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if (c as u32) < 0x20 || c as u32 == 0x7f => {
+                out.push_str("\\u");
+                out.push_str(format!("{:04X}", c as u32).as_slice());
+            }
+            c => out.push_char(c)
+        }
+    }
+    out.push_char('"');
+}
+
+// Bare keys consist of ASCII letters, digits, `_` and `-`
+fn is_bare_key_char(c: char) -> bool {
+    match c {
+        'a' .. 'z' | 'A' .. 'Z' | '0' .. '9' | '_' | '-' => true,
+        _ => false
+    }
+}
+
+fn write_key(out: &mut String, key: &str) {
+    if !key.is_empty() && key.chars().all(is_bare_key_char) {
+        out.push_str(key);
+    } else {
+        write_str(out, key);
+    }
+}
+
+// Returns the path of the section `key` within the section at `path`
+fn child_path(path: &str, key: &str) -> String {
+    let mut child = path.to_string();
+    if !child.is_empty() { child.push_char('.') }
+    write_key(&mut child, key);
+    child
+}
+
+fn write_float(out: &mut String, f: f64) {
+    // `to_str` spells NaN as `NaN`, TOML as `nan`
+    let s = if f.is_nan() { "nan".to_string() } else { f.to_str() };
+    out.push_str(s.as_slice());
+    // TOML floats need a fractional part
+    if f.is_finite() && !s.as_slice().contains_char('.') && !s.as_slice().contains_char('e') {
+        out.push_str(".0");
+    }
+}
+
+fn write_value(out: &mut String, value: &Value) {
+    match *value {
+        NoValue => {}
+        Boolean(b) => out.push_str(b.to_str().as_slice()),
+        Integer(n) => out.push_str(n.to_str().as_slice()),
+        Float(f) => write_float(out, f),
+        String(ref s) => write_str(out, s.as_slice()),
+        Datetime(ref dt) => out.push_str(dt.to_str().as_slice()),
+        Array(ref vec) | Append(ref vec) | TableArray(ref vec) => {
+            out.push_char('[');
+            for (i, elm) in vec.iter().enumerate() {
+                if i > 0 { out.push_str(", ") }
+                write_value(out, elm);
+            }
+            out.push_char(']');
+        }
+        Table(ref map) | TableInner(ref map) => write_inline_table(out, &**map)
+    }
+}
+
+fn write_inline_table(out: &mut String, map: &HashMap<String, Value>) {
+    let mut keys: Vec<&String> = map.keys().collect();
+    keys.sort();
+
+    out.push_char('{');
+    for (i, key) in keys.iter().enumerate() {
+        out.push_str(if i > 0 { ", " } else { " " });
+        write_key(out, key.as_slice());
+        out.push_str(" = ");
+        write_value(out, map.get(*key));
+    }
+    out.push_str(if keys.is_empty() { "}" } else { " }" });
+}
+
+// Records the offset in the source document of `value` at span path `at`
+// and of all values below it in `positions`, by span path. Tables without a
+// header get that of their first descendant, values added after parsing
+// none. Returns the offset of `value`.
+fn collect_positions(value: &Value, spans: &SpanMap, at: &str,
+                     positions: &mut HashMap<String, uint>) -> Option<uint> {
+    let first = match *value {
+        Table(ref map) | TableInner(ref map) => {
+            map.iter().filter_map(|(k, v)| {
+                collect_positions(v, spans, join_path(at, k.as_slice()).as_slice(), positions)
+            }).min()
+        }
+        TableArray(ref vec) => {
+            vec.iter().enumerate().filter_map(|(i, v)| {
+                collect_positions(v, spans, join_path(at, i.to_str().as_slice()).as_slice(), positions)
+            }).min()
+        }
+        _ => None
+    };
+    let pos = spans.get(at).map(|span| span.start.offset).or(first);
+    match pos {
+        Some(pos) => { positions.insert(at.to_string(), pos); }
+        None => {}
+    }
+    pos
+}
+
+// Returns the keys of `map`, the table at span path `at`. Given `positions`,
+// keys are in document order, followed by keys added after parsing, in
+// sorted order. Otherwise, all keys are sorted.
+fn ordered_keys<'a>(map: &'a HashMap<String, Value>, positions: Option<&HashMap<String, uint>>,
+                    at: &str) -> Vec<&'a String> {
+    let mut keys: Vec<(uint, &String)> = map.keys().map(|k| {
+        let pos = positions.and_then(|positions| positions.find_copy(&join_path(at, k.as_slice())));
+        (pos.unwrap_or(uint::MAX), k)
+    }).collect();
+    keys.sort();
+    keys.move_iter().map(|(_, k)| k).collect()
+}
+
+// Writes the plain values of `map`, then its sections below `path`. `at` is
+// the span path of `map`, see `join_path`.
+fn write_table(out: &mut String, map: &HashMap<String, Value>, path: &str,
+               positions: Option<&HashMap<String, uint>>, at: &str) {
+    let keys = ordered_keys(map, positions, at);
+
+    for key in keys.iter() {
+        let val = map.get(*key);
+        if is_section(val) { continue }
+        write_key(out, key.as_slice());
+        out.push_str(" = ");
+        write_value(out, val);
+        out.push_char('\n');
+    }
+
+    for key in keys.iter() {
+        let child = child_path(path, key.as_slice());
+        let child_at = join_path(at, key.as_slice());
+        match *map.get(*key) {
+            Table(ref sub) => write_section(out, &**sub, child.as_slice(), true, positions, child_at.as_slice()),
+            TableInner(ref sub) => write_section(out, &**sub, child.as_slice(), false, positions, child_at.as_slice()),
+            TableArray(ref vec) => {
+                for (idx, elm) in vec.iter().enumerate() {
+                    match *elm {
+                        Table(ref sub) | TableInner(ref sub) => {
+                            if !out.is_empty() { out.push_char('\n') }
+                            out.push_str(format!("[[{}]]\n", child).as_slice());
+                            let elm_at = join_path(child_at.as_slice(), idx.to_str().as_slice());
+                            write_table(out, &**sub, child.as_slice(), positions, elm_at.as_slice());
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn write_section(out: &mut String, map: &HashMap<String, Value>, path: &str, explicit: bool,
+                 positions: Option<&HashMap<String, uint>>, at: &str) {
+    if explicit || map.values().any(|v| !is_section(v)) {
+        if !out.is_empty() { out.push_char('\n') }
+        out.push_str(format!("[{}]\n", path).as_slice());
+    }
+    write_table(out, map, path, positions, at);
+}
+
+fn encode_with(value: &Value, spans: Option<&SpanMap>) -> String {
+    let mut out = String::new();
+    let positions = spans.map(|spans| {
+        let mut positions = HashMap::new();
+        collect_positions(value, spans, "", &mut positions);
+        positions
+    });
+    match *value {
+        Table(ref map) | TableInner(ref map) => write_table(&mut out, &**map, "", positions.as_ref(), ""),
+        _ => write_value(&mut out, value)
+    }
+    out
+}
+
+/// Returns `value` as a TOML document. `value` should be a table, as only
+/// tables form documents; for other values, the literal is returned.
+pub fn encode(value: &Value) -> String {
+    encode_with(value, None)
+}
+
+/// Returns `value` as a TOML literal on a single line, with tables as inline
+/// tables, e.g. to mention a value in a diagnostic
+pub fn encode_inline(value: &Value) -> String {
+    let mut out = String::new();
+    write_value(&mut out, value);
+    out
+}
+
+/// Like `encode`, but keeps the order of keys and sections of the document
+/// `value` was parsed from, given its `spans` (see
+/// `ParserOptions::spans`), so that a document can be parsed, modified
+/// and written back without being reordered. Keys added after parsing are
+/// emitted after the others of their table, in sorted order. Within a table,
+/// plain values still precede sections, and comments are not preserved.
+pub fn encode_in_order(value: &Value, spans: &SpanMap) -> String {
+    encode_with(value, Some(spans))
+}
+
+// Returns the parts of `effective` which differ from `defaults`, or `None`
+// if there are none. Tables are compared key by key, other values as a
+// whole.
+fn diff(effective: &Value, defaults: &Value) -> Option<Value> {
+    match (effective, defaults) {
+        (&Table(ref eff), &Table(ref def)) | (&Table(ref eff), &TableInner(ref def)) |
+        (&TableInner(ref eff), &Table(ref def)) | (&TableInner(ref eff), &TableInner(ref def)) => {
+            let mut changed = box HashMap::new();
+            for (key, val) in eff.iter() {
+                let d = match def.find(key) {
+                    Some(default) => diff(val, default),
+                    None => Some(val.clone())
+                };
+                match d {
+                    Some(d) => { changed.insert(key.clone(), d); }
+                    None => {}
+                }
+            }
+            if changed.is_empty() { None } else { Some(TableInner(changed)) }
+        }
+        _ if values_equal(effective, defaults, &Default::default()) => None,
+        _ => Some(effective.clone())
+    }
+}
+
+/// Returns a TOML document containing only those settings of `effective`
+/// which differ from (or are missing in) `defaults`, e.g. to write a minimal
+/// override file. Settings removed relative to `defaults` cannot be
+/// expressed and are ignored.
+pub fn encode_diff(effective: &Value, defaults: &Value) -> String {
+    match diff(effective, defaults) {
+        Some(changed) => encode(&changed),
+        None => String::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::{Integer, String, Append, Array, TableInner, ParserOptions};
+    use super::super::{parse_from_str, parse_from_buffer_with, parse_from_buffer_detailed};
+    use super::{encode, encode_inline, encode_in_order, encode_diff};
+
+    use std::collections::hashmap::HashMap;
+
+    use std::io::BufReader;
+
+    #[test]
+    fn test_encode_append_as_array() {
+        let mut rd = BufReader::new("a += [1, 2]".as_bytes());
+        let value = parse_from_buffer_with(&mut rd, ParserOptions::new().allow_append(true)).unwrap();
+        assert_eq!(value.lookup("a"), Some(&Append(vec!(Integer(1), Integer(2)))));
+        let encoded = encode(&value);
+        assert_eq!(encoded.as_slice(), "a = [1, 2]\n");
+        assert!(parse_from_str(encoded.as_slice()).is_ok());
+    }
+
+    #[test]
+    fn test_encode_keys() {
+        let mut map = box HashMap::new();
+        map.insert("plain-key_1".to_string(), Integer(1));
+        map.insert("caf\u00e9".to_string(), Integer(2));
+        map.insert("a b".to_string(), Integer(3));
+        let encoded = encode(&TableInner(map));
+        assert_eq!(encoded.as_slice(), "\"a b\" = 3\n\"caf\u00e9\" = 2\nplain-key_1 = 1\n");
+        assert!(parse_from_str(encoded.as_slice()).is_ok());
+    }
+
+    #[test]
+    fn test_encode_inline() {
+        let value = parse_from_str("a = 1\n[t]\nxs = [\"x\"]\n").unwrap();
+        assert_eq!(encode_inline(&value).as_slice(), "{ a = 1, t = { xs = [\"x\"] } }");
+        assert_eq!(encode_inline(&Array(vec!(Integer(1), String("s".to_string())))).as_slice(), "[1, \"s\"]");
+        assert_eq!(format!("{}", value).as_slice(), "a = 1\n\n[t]\nxs = [\"x\"]\n");
+    }
+
+    #[test]
+    fn test_encode_in_order_with_dotted_keys() {
+        let doc = "\"x.y\" = 1\nz = 2\n\n[x]\ny = 3\n";
+        let mut rd = BufReader::new(doc.as_bytes());
+        let output = parse_from_buffer_detailed(&mut rd, ParserOptions::new().spans(true)).unwrap();
+        let spans = output.spans.unwrap();
+        let encoded = encode_in_order(&output.value, &spans);
+        assert_eq!(encoded.as_slice(), doc);
+    }
+
+    #[test]
+    fn test_encode() {
+        let doc = "ratio = 2.0\nname = \"a\\tb\"\n[x.y]\nz = true\n\
+                   [server]\nport = 80\n[[h]]\nn = 1\n[[h]]\nn = 2\n";
+        let value = parse_from_str(doc).unwrap();
+        let encoded = encode(&value);
+        assert_eq!(encoded.as_slice(), "name = \"a\\tb\"\nratio = 2.0\n\n[[h]]\nn = 1\n\n[[h]]\nn = 2\n\n\
+                                        [server]\nport = 80\n\n[x.y]\nz = true\n");
+        assert!(parse_from_str(encoded.as_slice()).unwrap() == value);
+        assert_eq!(encode(&Integer(1)).as_slice(), "1");
+    }
+
+    #[test]
+    fn test_encode_diff() {
+        let defaults = parse_from_str("[server]\nport = 80\nhost = \"a\"\n[log]\nlevel = \"info\"").unwrap();
+        let effective = parse_from_str("extra = [1]\n[server]\nport = 8080\n[log]\nlevel = \"info\"")
+            .unwrap();
+        // `server.host` was removed, which cannot be expressed
+        assert_eq!(encode_diff(&effective, &defaults).as_slice(), "extra = [1]\n\n[server]\nport = 8080\n");
+        assert_eq!(encode_diff(&defaults, &defaults).as_slice(), "");
+    }
+}
@@ -0,0 +1,146 @@
+// In-place access to a key of a table.
+//
+// `Value::entry` returns a handle on a key, which either holds a value
+// (`Occupied`) or not (`Vacant`), so that callers can get, insert or replace
+// the value without looking it up again themselves.
+
+use super::{Value, Table, TableInner};
+
+use std::collections::hashmap::HashMap;
+use std::mem;
+
+/// A key of a table, see `Value::entry`
+pub enum Entry<'a> {
+    Occupied(OccupiedEntry<'a>),
+    Vacant(VacantEntry<'a>)
+}
+
+/// A key of a table which holds a value
+pub struct OccupiedEntry<'a> {
+    map: &'a mut HashMap<String, Value>,
+    key: String
+}
+
+/// A key of a table which holds no value
+pub struct VacantEntry<'a> {
+    map: &'a mut HashMap<String, Value>,
+    key: String
+}
+
+impl<'a> Entry<'a> {
+    pub fn key<'b>(&'b self) -> &'b str {
+        match *self {
+            Occupied(ref e) => e.key.as_slice(),
+            Vacant(ref e) => e.key.as_slice()
+        }
+    }
+
+    /// Returns the value, inserting `default` first if there is none
+    pub fn or_insert(self, default: Value) -> &'a mut Value {
+        match self {
+            Occupied(e) => e.into_mut(),
+            Vacant(e) => e.insert(default)
+        }
+    }
+
+    /// Like `or_insert`, but only computes the value to insert if needed
+    pub fn or_insert_with(self, default: || -> Value) -> &'a mut Value {
+        match self {
+            Occupied(e) => e.into_mut(),
+            Vacant(e) => e.insert(default())
+        }
+    }
+}
+
+// The key of an `OccupiedEntry` is in the table, as the entry borrows the
+// table mutably from the time it was found there.
+impl<'a> OccupiedEntry<'a> {
+    pub fn get<'b>(&'b self) -> &'b Value {
+        self.map.find(&self.key).unwrap()
+    }
+
+    pub fn get_mut<'b>(&'b mut self) -> &'b mut Value {
+        self.map.find_mut(&self.key).unwrap()
+    }
+
+    /// Returns the value, with the lifetime of the table
+    pub fn into_mut(self) -> &'a mut Value {
+        let OccupiedEntry { map, key } = self;
+        map.find_mut(&key).unwrap()
+    }
+
+    /// Replaces the value, returning the former one
+    pub fn insert(&mut self, val: Value) -> Value {
+        mem::replace(self.get_mut(), val)
+    }
+
+    /// Removes the key from the table, returning its value
+    pub fn remove(self) -> Value {
+        let OccupiedEntry { map, key } = self;
+        map.pop(&key).unwrap()
+    }
+}
+
+impl<'a> VacantEntry<'a> {
+    /// Inserts `val` at the key, returning it with the lifetime of the table
+    pub fn insert(self, val: Value) -> &'a mut Value {
+        let VacantEntry { map, key } = self;
+        map.find_or_insert(key, val)
+    }
+}
+
+impl Value {
+    /// Returns the entry of `key` of a table, `None` for other values
+    pub fn entry<'a>(&'a mut self, key: &str) -> Option<Entry<'a>> {
+        match *self {
+            Table(ref mut map) | TableInner(ref mut map) => {
+                let key = key.to_string();
+                let map: &'a mut HashMap<String, Value> = &mut **map;
+                if map.contains_key(&key) {
+                    Some(Occupied(OccupiedEntry { map: map, key: key }))
+                } else {
+                    Some(Vacant(VacantEntry { map: map, key: key }))
+                }
+            }
+            _ => None
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::{Integer, Boolean, parse_from_str};
+    use super::{Occupied, Vacant};
+
+    #[test]
+    fn test_entry() {
+        let mut value = parse_from_str("a = 1").unwrap();
+        match value.entry("a") {
+            Some(Occupied(mut e)) => {
+                assert_eq!(e.get(), &Integer(1));
+                assert_eq!(e.insert(Integer(2)), Integer(1));
+            }
+            _ => fail!("expected an occupied entry")
+        }
+        assert_eq!(value.lookup("a"), Some(&Integer(2)));
+
+        *value.entry("b").unwrap().or_insert(Integer(0)) = Integer(3);
+        assert_eq!(value.lookup("b"), Some(&Integer(3)));
+        assert_eq!(value.entry("b").unwrap().or_insert_with(|| fail!("not needed")), &Integer(3));
+    }
+
+    #[test]
+    fn test_entry_remove() {
+        let mut value = parse_from_str("a = true\nb = 1").unwrap();
+        match value.entry("a") {
+            Some(Occupied(e)) => assert_eq!(e.remove(), Boolean(true)),
+            _ => fail!("expected an occupied entry")
+        }
+        assert_eq!(value.lookup("a"), None);
+        match value.entry("a") {
+            Some(Vacant(e)) => assert_eq!(e.key.as_slice(), "a"),
+            _ => fail!("expected a vacant entry")
+        }
+        assert!(Integer(1).entry("a").is_none());
+    }
+}
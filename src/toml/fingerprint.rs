@@ -0,0 +1,143 @@
+// Stable fingerprints of Value trees.
+//
+// The fingerprint is a 64-bit FNV-1a hash over a canonical encoding of the
+// tree, in which table keys are sorted, `Table`/`TableInner` are not
+// distinguished, and all zeros and all NaNs are encoded alike. It therefore
+// depends only on the data, not on formatting, comments or key order of the
+// source document, and is stable across runs and platforms.
+
+use super::{Value, NoValue, Boolean, Integer, Float, String, Datetime,
+            Array, TableArray, Append, TableInner, Table};
+
+static FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+static FNV_PRIME: u64 = 0x100000001b3;
+
+struct Fnv {
+    hash: u64
+}
+
+impl Fnv {
+    fn write(&mut self, bytes: &[u8]) {
+        for b in bytes.iter() {
+            self.hash = (self.hash ^ (*b as u64)) * FNV_PRIME;
+        }
+    }
+
+    fn write_u64(&mut self, n: u64) {
+        let mut bytes = [0u8, ..8];
+        for i in range(0u, 8) {
+            bytes[i] = (n >> (8 * i)) as u8;
+        }
+        self.write(bytes.as_slice());
+    }
+
+    // Floats are encoded by the mantissa, exponent and sign returned by
+    // `integer_decode`, except that both zeros are encoded as `(0, 0, 1)`,
+    // and all NaNs, whatever their sign and payload, as `N`.
+    fn write_float(&mut self, f: f64) {
+        if f.is_nan() {
+            self.write("N".as_bytes());
+            return;
+        }
+        let (mantissa, exponent, sign) = if f == 0.0 { (0, 0, 1) } else { f.integer_decode() };
+        self.write_u64(mantissa);
+        self.write_u64(exponent as u64);
+        self.write_u64(sign as u64);
+    }
+
+    fn write_str(&mut self, s: &str) {
+        self.write_u64(s.len() as u64);
+        self.write(s.as_bytes());
+    }
+
+    fn write_value(&mut self, value: &Value) {
+        match *value {
+            NoValue => self.write("n".as_bytes()),
+            Boolean(b) => self.write(if b { "T" } else { "F" }.as_bytes()),
+            Integer(n) => {
+                self.write("i".as_bytes());
+                self.write_u64(n as u64);
+            }
+            Float(f) => {
+                // all zeros and all NaNs compare equal
+                self.write("f".as_bytes());
+                self.write_float(f);
+            }
+            String(ref s) => {
+                self.write("s".as_bytes());
+                self.write_str(s.as_slice());
+            }
+            Datetime(ref dt) => {
+                self.write("d".as_bytes());
+                self.write_str(dt.to_str().as_slice());
+            }
+            Array(ref vec) | TableArray(ref vec) | Append(ref vec) => {
+                let tag = match *value { Array(_) => "a", TableArray(_) => "A", _ => "+" };
+                self.write(tag.as_bytes());
+                self.write_u64(vec.len() as u64);
+                for elm in vec.iter() {
+                    self.write_value(elm);
+                }
+            }
+            Table(ref map) | TableInner(ref map) => {
+                let mut keys: Vec<&String> = map.keys().collect();
+                keys.sort();
+                self.write("t".as_bytes());
+                self.write_u64(keys.len() as u64);
+                for key in keys.iter() {
+                    self.write_str(key.as_slice());
+                    self.write_value(map.get(*key));
+                }
+            }
+        }
+    }
+}
+
+impl Value {
+    /// Returns a hash of the data in this tree, independent of formatting,
+    /// comments and key order of the document it was parsed from. Two trees
+    /// with the same fingerprint are, with very high probability, equal.
+    pub fn fingerprint(&self) -> u64 {
+        let mut fnv = Fnv { hash: FNV_OFFSET_BASIS };
+        fnv.write_value(self);
+        fnv.hash
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::{Value, Integer, Float, String, Array, parse_from_str};
+
+    fn fingerprint(doc: &str) -> u64 {
+        parse_from_str(doc).unwrap().fingerprint()
+    }
+
+    fn strings(strs: &[&str]) -> Value {
+        Array(strs.iter().map(|s| String(s.to_string())).collect())
+    }
+
+    #[test]
+    fn test_fingerprint_ignores_formatting() {
+        assert_eq!(fingerprint("x = 1\n[t]\ny = \"s\"\nz = [1, 2]"),
+                   fingerprint("x = 1 # one\n\n[t]\nz = [ 1, 2 ]\ny = \"s\"\n"));
+        // `t` is a `TableInner` in the first document, a `Table` in the second
+        assert_eq!(fingerprint("[t.u]\nk = 1"), fingerprint("[t]\n[t.u]\nk = 1"));
+        assert_eq!(Float(0.0).fingerprint(), Float(-0.0).fingerprint());
+        assert_eq!(Float(::std::f64::NAN).fingerprint(), Float(-::std::f64::NAN).fingerprint());
+        assert_eq!(Float(::std::f64::NAN).fingerprint(), Float(0.0 / 0.0).fingerprint());
+    }
+
+    #[test]
+    fn test_fingerprint_tells_data_apart() {
+        assert!(fingerprint("x = 1") != fingerprint("x = 2"));
+        assert!(fingerprint("x = 1") != fingerprint("y = 1"));
+        assert!(fingerprint("x = [1]") != fingerprint("[[x]]"));
+        assert!(Integer(1).fingerprint() != Float(1.0).fingerprint());
+        assert!(Float(1.0).fingerprint() != Float(-1.0).fingerprint());
+        assert!(Float(1.0).fingerprint() != Float(2.0).fingerprint());
+        assert!(Float(0.0).fingerprint() != Float(::std::f64::MIN_POS_VALUE).fingerprint());
+        assert!(Float(::std::f64::INFINITY).fingerprint() != Float(::std::f64::NAN).fingerprint());
+        assert!(Integer(1).fingerprint() != String("1".to_string()).fingerprint());
+        assert!(strings(&["ab", "c"]).fingerprint() != strings(&["a", "bc"]).fingerprint());
+    }
+}
@@ -0,0 +1,127 @@
+// Conversion of Value trees to flat string maps.
+//
+// Every scalar is keyed by its dotted path. Elements of arrays and table
+// arrays are addressed by index, so `ports = [80, 443]` flattens into
+// `ports.0 = "80"` and `ports.1 = "443"`. Empty arrays and tables produce no
+// entries.
+//
+// `Value::from_flat_map` goes the other way, building nested tables from
+// keys like `server.tls.enabled`.
+
+use super::{Error, ParseErrorInField, ValueBuilder, value_from_str};
+use super::{Value, NoValue, Boolean, Integer, Float, String, Datetime,
+            Array, TableArray, Append, TableInner, Table};
+
+use std::collections::hashmap::HashMap;
+
+fn join(path: &str, key: &str) -> String {
+    if path.is_empty() { key.to_string() } else { format!("{}.{}", path, key) }
+}
+
+// Adds the scalars of `value` and everything below it to `out`
+fn flatten(value: &Value, path: &str, out: &mut HashMap<String, String>) {
+    let scalar = match *value {
+        NoValue => return,
+        Boolean(b) => b.to_str(),
+        Integer(n) => n.to_str(),
+        Float(f) => f.to_str(),
+        String(ref s) => s.clone(),
+        Datetime(ref dt) => dt.to_str(),
+        Array(ref vec) | TableArray(ref vec) | Append(ref vec) => {
+            for (idx, elm) in vec.iter().enumerate() {
+                flatten(elm, join(path, idx.to_str().as_slice()).as_slice(), out);
+            }
+            return;
+        }
+        Table(ref map) | TableInner(ref map) => {
+            for (key, val) in map.iter() {
+                flatten(val, join(path, key.as_slice()).as_slice(), out);
+            }
+            return;
+        }
+    };
+    out.insert(path.to_string(), scalar);
+}
+
+impl Value {
+    /// Returns the scalars of this tree as strings, keyed by dotted path.
+    /// Strings are taken verbatim, other scalars in their TOML notation,
+    /// and array elements are keyed by index (`ports.0`, `ports.1`, ...).
+    pub fn flatten_strings(&self) -> HashMap<String, String> {
+        let mut out = HashMap::new();
+        flatten(self, "", &mut out);
+        out
+    }
+
+    /// Builds a document from a flat map of dotted paths to values, e.g. as
+    /// read from environment variables or a property file. Values are
+    /// parsed as TOML values, falling back to strings, so `true` becomes a
+    /// boolean and `localhost` a string. Array elements flattened by
+    /// `flatten_strings` come back as tables with numeric keys, which
+    /// `lookup` takes for indices; use `get_table` to reach them.
+    ///
+    /// Fails with `ParseErrorInField(key)` if `key` is empty, has an empty
+    /// segment, or conflicts with another key (as `a = 1` and `a.b = 2` do).
+    pub fn from_flat_map(map: &HashMap<String, String>) -> Result<Value, Error> {
+        let mut keys: Vec<&String> = map.keys().collect();
+        keys.sort();
+
+        let mut root = box HashMap::new();
+        for key in keys.iter() {
+            let raw = map.get(*key).as_slice();
+            let val = value_from_str(raw).unwrap_or(String(raw.to_string()));
+            let path: Vec<String> = key.as_slice().split('.').map(|s| s.to_string()).collect();
+            if !ValueBuilder::insert_path(path.as_slice(), &mut root, val) {
+                return Err(ParseErrorInField(key.to_string()));
+            }
+        }
+        Ok(TableInner(root))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::{Value, Error, Boolean, Integer, String, ParseErrorInField, parse_from_str};
+
+    use std::collections::hashmap::HashMap;
+
+    fn flat_map(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs.iter().map(|&(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn test_flatten_strings() {
+        let doc = "name = \"web\"\nports = [80, 443]\nempty = []\n[tls]\nenabled = true\n[[h]]\nn = 1.5\n";
+        let flat = parse_from_str(doc).unwrap().flatten_strings();
+        let expected = flat_map(&[("name", "web"), ("ports.0", "80"), ("ports.1", "443"),
+                                  ("tls.enabled", "true"), ("h.0.n", "1.5")]);
+        assert!(flat == expected);
+    }
+
+    #[test]
+    fn test_from_flat_map() {
+        let map = flat_map(&[("server.tls.enabled", "true"), ("server.port", "80"),
+                             ("server.host", "localhost")]);
+        let root = Value::from_flat_map(&map).unwrap();
+        assert_eq!(root.lookup("server.tls.enabled"), Some(&Boolean(true)));
+        assert_eq!(root.lookup("server.port"), Some(&Integer(80)));
+        assert_eq!(root.lookup("server.host"), Some(&String("localhost".to_string())));
+
+        // flattened arrays come back as tables with numeric keys, which
+        // `lookup` takes for indices
+        let root = Value::from_flat_map(&parse_from_str("ports = [80]").unwrap().flatten_strings()).unwrap();
+        let ports = root.lookup("ports").and_then(|ports| ports.get_table()).unwrap();
+        assert_eq!(ports.find_equiv(&"0"), Some(&Integer(80)));
+        assert_eq!(root.lookup("ports.0"), None);
+    }
+
+    #[test]
+    fn test_from_flat_map_invalid_keys() {
+        fn invalid(pairs: &[(&str, &str)]) -> Option<Error> {
+            Value::from_flat_map(&flat_map(pairs)).err()
+        }
+        assert_eq!(invalid(&[("a", "1"), ("a.b", "2")]), Some(ParseErrorInField("a.b".to_string())));
+        assert_eq!(invalid(&[("a..b", "1")]), Some(ParseErrorInField("a..b".to_string())));
+        assert_eq!(invalid(&[("", "1")]), Some(ParseErrorInField("".to_string())));
+    }
+}
@@ -0,0 +1,107 @@
+// Reading gzip compressed documents (RFC 1952).
+//
+// The whole stream is read and inflated in memory before parsing. Only a
+// single gzip member is supported, and its checksum is not verified.
+
+use super::{Value, Error, IOError, parse_from_bytes};
+
+use flate;
+use std::io::{IoError, InvalidInput, Reader};
+
+static FTEXT: u8 = 1;
+static FHCRC: u8 = 2;
+static FEXTRA: u8 = 4;
+static FNAME: u8 = 8;
+static FCOMMENT: u8 = 16;
+
+fn invalid() -> Error {
+    IOError(IoError { kind: InvalidInput, desc: "invalid gzip data", detail: None })
+}
+
+// Returns the offset of the deflate stream following the gzip header
+fn skip_header(data: &[u8]) -> Option<uint> {
+    if data.len() < 18 || data[0] != 0x1f || data[1] != 0x8b || data[2] != 8 { return None }
+    let flags = data[3] & !FTEXT;
+    let mut pos = 10;
+    if flags & FEXTRA != 0 {
+        if pos + 2 > data.len() { return None }
+        pos += 2 + (data[pos] as uint | data[pos + 1] as uint << 8);
+    }
+    // zero-terminated file name and comment
+    for &flag in [FNAME, FCOMMENT].iter() {
+        if flags & flag != 0 {
+            while pos < data.len() && data[pos] != 0 { pos += 1 }
+            pos += 1;
+        }
+    }
+    if flags & FHCRC != 0 { pos += 2 }
+    // the stream is followed by 8 bytes of checksum and size
+    if pos + 8 > data.len() { None } else { Some(pos) }
+}
+
+/// Parses a gzip compressed document read from `rd`. Read errors and
+/// invalid compressed data are returned as `IOError`.
+pub fn parse_from_gzip_reader(rd: &mut Reader) -> Result<Value, Error> {
+    let data = match rd.read_to_end() {
+        Ok(data) => data,
+        Err(e) => return Err(IOError(e))
+    };
+    let start = match skip_header(data.as_slice()) {
+        Some(start) => start,
+        None => return Err(invalid())
+    };
+    match flate::inflate_bytes(data.slice(start, data.len() - 8)) {
+        Some(bytes) => parse_from_bytes(bytes.as_slice()),
+        None => Err(invalid())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::{Integer, IOError, parse_from_path};
+    use super::{parse_from_gzip_reader, FNAME};
+
+    use flate;
+    use std::io::{BufReader, File, InvalidInput, TempDir};
+
+    // Compresses `doc` into a gzip stream with the file name `name`
+    fn gzip(doc: &str, name: &str) -> Vec<u8> {
+        let mut data = vec!(0x1f, 0x8b, 8, FNAME, 0, 0, 0, 0, 0, 255);
+        data.push_all(name.as_bytes());
+        data.push(0);
+        data.push_all(flate::deflate_bytes(doc.as_bytes()).unwrap().as_slice());
+        // checksum and size, which are not verified
+        data.push_all(&[0u8, ..8]);
+        data
+    }
+
+    #[test]
+    fn test_parse_from_gzip_reader() {
+        let data = gzip("[server]\nport = 80\n", "config.toml");
+        let value = parse_from_gzip_reader(&mut BufReader::new(data.as_slice())).unwrap();
+        assert_eq!(value.lookup("server.port"), Some(&Integer(80)));
+
+        // `parse_from_path` inflates files ending in `.gz`
+        let dir = TempDir::new("toml-gzip").unwrap();
+        let path = dir.path().join("config.toml.gz");
+        File::create(&path).write(data.as_slice()).unwrap();
+        assert!(parse_from_path(&path).unwrap() == value);
+    }
+
+    #[test]
+    fn test_parse_from_gzip_reader_invalid() {
+        fn invalid(data: &[u8]) -> bool {
+            match parse_from_gzip_reader(&mut BufReader::new(data)) {
+                Err(IOError(e)) => e.kind == InvalidInput,
+                _ => false
+            }
+        }
+        assert!(invalid("port = 80".as_bytes()));
+        let data = gzip("port = 80", "");
+        assert!(invalid(data.slice_to(12)));
+        // a corrupted deflate stream
+        let mut data = gzip("port = 80", "");
+        *data.get_mut(11) = 0xff;
+        assert!(invalid(data.as_slice()));
+    }
+}
@@ -12,34 +12,81 @@
 
 extern crate serialize;
 extern crate collections;
+extern crate flate;
+extern crate regex;
+extern crate sync;
+extern crate time;
 #[phase(syntax, link)] extern crate log;
 
 use std::char;
 use std::mem;
+use std::cmp::min;
 
-use std::collections::hashmap::{HashMap,MoveEntries};
+use std::collections::hashmap::{HashMap,HashSet,MoveEntries};
 use std::vec::MoveItems;
 
-use std::io::{File,IoError,IoResult,EndOfFile};
+use std::io::{File,IoError,IoResult,EndOfFile,InvalidInput,OtherIoError};
 use std::io::{Buffer,BufReader,BufferedReader};
 use std::path::Path;
+use std::rc::Rc;
 
 use serialize::Decodable;
+use serialize::base64::FromBase64;
 
 use std::fmt;
 
-#[deriving(Clone)]
+pub use compare::{FloatTolerance, files_equal, values_equal};
+pub use config::parse_dir;
+pub use datetime::Datetime;
+pub use dump::{dump_colored, dump_colored_to_depth};
+pub use encoder::{encode, encode_inline, encode_in_order, encode_diff};
+pub use entry::{Entry, Occupied, Vacant, OccupiedEntry, VacantEntry};
+pub use gzip::parse_from_gzip_reader;
+pub use merge::{MergeStrategy, ArrayMerge, ReplaceArrays, AppendArrays,
+                ConflictPolicy, Overwrite, KeepExisting, FailOnConflict};
+pub use outline::{SectionInfo, outline};
+pub use span::{Pos, Span, Spanned, SpanMap, SpanRecorder, join_path};
+pub use transform::{Filter, Rename, Lowercase};
+pub use walk::Walk;
+pub use query::{LookupError, LookupErrorKind, KeyNotFound, IndexOutOfBounds, NotATable, NotAnArray};
+
+pub mod schema;
+pub mod config;
+pub mod cached;
+mod compare;
+mod datetime;
+mod dump;
+mod encoder;
+mod entry;
+mod fingerprint;
+mod flat;
+mod gzip;
+mod merge;
+mod outline;
+mod prune;
+mod query;
+mod size;
+mod span;
+mod transform;
+mod walk;
+#[cfg(test)]
+mod tests;
+
+#[deriving(Clone,PartialEq)]
 pub enum Value {
     NoValue,
     Boolean(bool),
-    PosInt(u64),
-    NegInt(u64),
+    Integer(i64),
     Float(f64),
     String(String),
-    Datetime(u16,u8,u8,u8,u8,u8),
+    Datetime(datetime::Datetime),
     Array(Vec<Value>),
     TableArray(Vec<Value>),
 
+    // Append is produced by `key += [...]` (see `ParserOptions::allow_append`)
+    // and extends the array it is merged into, instead of replacing it.
+    Append(Vec<Value>),
+
     // TableInner is used to create inner nodes for which no toml [section]
     // exists. For example in case of [a.b.c], `a` and `b` would be TableInner
     // while `c` would be a Table.
@@ -47,37 +94,245 @@ pub enum Value {
     Table(Box<HashMap<String, Value>>)
 }
 
+/// Formats a table as a TOML document (see `encode`), and other values as
+/// TOML literals. Use `encode_inline` to mention a value in a message.
 impl fmt::Show for Value {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
-        match *self {
-            NoValue       => write!(fmt, "NoValue"),
-            Boolean(b)    => write!(fmt, "Boolean({:b})", b),
-            PosInt(n)     => write!(fmt, "PosInt({:u})", n),
-            NegInt(n)     => write!(fmt, "NegInt({:u})", n),
-            Float(f)      => write!(fmt, "Float({:f})", f),
-            String(ref s) => write!(fmt, "String({:s})", s.as_slice()),
-            Datetime(a,b,c,d,e,f) =>  {
-                write!(fmt, "Datetime({},{},{},{},{},{})", a,b,c,d,e,f)
-            }
-            Array(ref arr) => write!(fmt, "Array({})", arr.as_slice()),
-            TableArray(ref arr) => write!(fmt, "TableArray({})", arr.as_slice()),
-            TableInner(ref hm) => write!(fmt, "TableInner({})", **hm),
-            Table(ref hm) => write!(fmt, "Table({})", **hm)
-        }
+        write!(fmt, "{:s}", encode(self).as_slice())
     }
 }
 
 
 
 /// Possible errors returned from the parse functions
-#[deriving(Show,Clone,PartialEq)]
+#[deriving(Clone,PartialEq)]
 pub enum Error {
     /// A parser error occurred during parsing
     ParseError,
-    /// A parser error with some human-readable context
+    /// A field could not be decoded. The dotted path of the field is given,
+    /// e.g. `servers.0.port`.
     ParseErrorInField(String),
     /// An I/O error occurred during parsing
-    IOError(IoError)
+    IOError(IoError),
+    /// An I/O error occurred opening or reading the file at the given path
+    IOErrorInFile(Path, IoError),
+    /// Parsing was aborted by the caller
+    Cancelled,
+    /// An integer literal lies outside the range of a 64-bit signed integer
+    IntegerOverflow(Span),
+    /// A bare word where a value was expected, possibly along with the
+    /// keyword it is a misspelling of
+    UnknownValue(String, Option<String>),
+    /// An array of flag names names an unregistered flag (see
+    /// `Decoder::register_flags`). The dotted path of the name, the name and
+    /// possibly the registered flag it is a misspelling of are given.
+    UnknownFlag(String, String, Option<String>),
+    /// A field to decode is missing from the document. The dotted path of
+    /// the field is given, possibly along with an unused key it is a
+    /// misspelling of.
+    MissingField(String, Option<String>),
+    /// A unicode escape denotes a surrogate or lies beyond U+10FFFF. The
+    /// code point and the span of the escape are given.
+    InvalidEscape(u32, Span),
+    /// A carriage return which is not followed by a line feed
+    BareCarriageReturn(Span),
+    /// The input is not valid UTF-8. The span of the first offending byte
+    /// is given.
+    InvalidUtf8(Span),
+    /// A control character other than tab or newline within a string, where
+    /// it has to be escaped. The code point and its span are given.
+    ControlCharacter(u32, Span),
+    /// A key is defined twice, or a key is used as a table but is not one.
+    /// The dotted path of the key, the span of its value and the span of
+    /// the earlier definition are given, the spans if known.
+    DuplicateKey(String, Option<Span>, Option<Span>),
+    /// A section is defined twice, or its name is taken by a value. The
+    /// dotted path of the section, the span of its header and the span of
+    /// the earlier definition are given, the spans if known.
+    DuplicateSection(String, Option<Span>, Option<Span>),
+    /// Something other than a comment follows a section header or a
+    /// key/value pair on the same line. The span of the rest of the line is
+    /// given.
+    ExpectedEndOfLine(Span),
+    /// A character which cannot start or continue the construct at hand.
+    /// The character, what was expected instead (e.g. "`]` to close
+    /// array") and the span of the character are given.
+    UnexpectedChar(char, &'static str, Span),
+    /// The input ends in the middle of a construct. What was expected
+    /// instead is given, along with the span.
+    UnexpectedEof(&'static str, Span),
+    /// A string is not closed before the end of its line, or of the input
+    UnterminatedString(Span),
+    /// A backslash is followed by an unknown escape character, or by too
+    /// few hexadecimal digits
+    UnknownEscape(char, Span),
+    /// A malformed number, like `1__000` or `1.`
+    InvalidNumber(Span),
+    /// A malformed or out of range datetime
+    InvalidDatetime(Span),
+    /// The elements of an array are of different types
+    MixedArrayTypes(Span),
+    /// A value which the TOML version selected with `ParserOptions::version`
+    /// does not support, like `inf` before 1.0. The value, the version that
+    /// introduced it and its span are given.
+    UnsupportedInVersion(&'static str, TomlVersion, Span),
+    /// A key is not followed by `=`. The span of the character found instead
+    /// is given.
+    ExpectedEquals(Span),
+    /// `key += ...` either has a value other than an array, or, when
+    /// merging, appends to a value other than an array. The key, or the
+    /// dotted path when merging, and the span of the value are given.
+    AppendNotArray(String, Option<Span>),
+    /// Trees merged with `FailOnConflict` hold different values at the
+    /// given dotted path
+    MergeConflict(String),
+    /// Arrays and inline tables are nested deeper, or a section name has
+    /// more keys, than allowed. The span of the offending array, table or
+    /// section header is given.
+    NestingTooDeep(Span),
+    /// A limit on the size of the document is exceeded (see `Limits`). The
+    /// limit, e.g. "number of keys", and the span where it was exceeded are
+    /// given.
+    LimitExceeded(&'static str, Span),
+    /// A value cannot be decoded into the requested type. The dotted path
+    /// of the value, the expected type and a description of the value found
+    /// (like `string "8080"`) are given.
+    TypeMismatch(String, &'static str, String),
+    /// A number does not fit the type it is decoded into. The dotted path of
+    /// the value and the type (like `u8`) are given.
+    OutOfRange(String, &'static str),
+    /// The decoder is asked for something TOML cannot represent, like an
+    /// enum, or for an element outside of a sequence. The dotted path of
+    /// the value and what was asked for are given.
+    Unsupported(String, &'static str),
+    /// A `Visitor` rejected a section or key/value pair without giving a
+    /// reason (see `Visitor::take_error`). The span of the section header
+    /// or of the value is given.
+    Rejected(Span)
+}
+
+impl Error {
+    /// Returns the region of the document the error refers to, if known
+    pub fn span(&self) -> Option<Span> {
+        match *self {
+            IntegerOverflow(span) | InvalidEscape(_, span) | BareCarriageReturn(span) | InvalidUtf8(span) |
+            ControlCharacter(_, span) | ExpectedEndOfLine(span) | UnexpectedChar(_, _, span) |
+            UnexpectedEof(_, span) | UnterminatedString(span) | UnknownEscape(_, span) |
+            InvalidNumber(span) | InvalidDatetime(span) | MixedArrayTypes(span) |
+            UnsupportedInVersion(_, _, span) |
+            ExpectedEquals(span) | NestingTooDeep(span) |
+            LimitExceeded(_, span) | Rejected(span) => Some(span),
+            DuplicateKey(_, span, _) | DuplicateSection(_, span, _) | AppendNotArray(_, span) => span,
+            _ => None
+        }
+    }
+
+    /// Renders the error along with the offending line of `source`, the
+    /// document it was returned for, and carets under the erroneous part:
+    ///
+    /// ```text
+    /// line 2, column 6: expected `=` after key
+    ///   |
+    /// 2 | port 8080
+    ///   |      ^
+    /// ```
+    ///
+    /// Errors without a span are rendered on a single line.
+    pub fn render(&self, source: &str) -> String {
+        let span = match self.span() {
+            Some(span) => span,
+            None => return format!("{}", self)
+        };
+        let mut out = format!("{}\n", self);
+        let line = match source.lines_any().nth(span.start.line - 1) {
+            Some(line) => line,
+            None => return out
+        };
+
+        let number = span.start.line.to_str();
+        let margin = " ".repeat(number.len());
+        out.push_str(format!("{} |\n{} | {}\n{} | ", margin, number, line, margin).as_slice());
+
+        // keep tabs, so that the carets line up with the line above
+        for c in line.chars().take(span.start.col - 1) {
+            out.push_char(if c == '\t' { '\t' } else { ' ' });
+        }
+        // underline up to the end of the span, or of the line
+        let len = if span.end.line == span.start.line && span.end.col > span.start.col {
+            span.end.col - span.start.col
+        } else {
+            1
+        };
+        out.push_str("^".repeat(len).as_slice());
+        out
+    }
+}
+
+// Describes a character found in place of something else
+fn describe_char(c: char) -> String {
+    match c {
+        '\n' => "end of line".to_string(),
+        c if c < ' ' || c == '\u007F' => format!("U+{:04X}", c as u32),
+        c => format!("`{}`", c)
+    }
+}
+
+/// Formats the error for users, e.g. "line 3, column 7: expected `=` after
+/// key"
+impl fmt::Show for Error {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self.span() {
+            Some(span) => try!(write!(fmt, "line {}, column {}: ", span.start.line, span.start.col)),
+            None => {}
+        }
+        match *self {
+            ParseError => write!(fmt, "parse error"),
+            ParseErrorInField(ref field) => write!(fmt, "parse error in field `{}`", field),
+            IOError(ref e) => write!(fmt, "I/O error: {}", e),
+            IOErrorInFile(ref path, ref e) => write!(fmt, "I/O error reading {}: {}", path.display(), e),
+            Cancelled => write!(fmt, "parsing cancelled"),
+            IntegerOverflow(_) => write!(fmt, "integer out of range"),
+            UnknownValue(ref word, Some(ref s)) => write!(fmt, "unknown value `{}`, did you mean `{}`?", word, s),
+            UnknownValue(ref word, None) => write!(fmt, "unknown value `{}`", word),
+            UnknownFlag(ref path, ref name, Some(ref s)) =>
+                write!(fmt, "unknown flag `{}` for field `{}`, did you mean `{}`?", name, path, s),
+            UnknownFlag(ref path, ref name, None) => write!(fmt, "unknown flag `{}` for field `{}`", name, path),
+            MissingField(ref field, Some(ref s)) => write!(fmt, "missing field `{}`, did you mean `{}`?", field, s),
+            MissingField(ref field, None) => write!(fmt, "missing field `{}`", field),
+            InvalidEscape(code, _) => write!(fmt, "escape of U+{:X}, which is not a character", code),
+            BareCarriageReturn(_) => write!(fmt, "carriage return without line feed"),
+            InvalidUtf8(_) => write!(fmt, "invalid UTF-8"),
+            ControlCharacter(code, _) => write!(fmt, "unescaped control character U+{:04X} in string", code),
+            DuplicateKey(ref path, _, Some(first)) =>
+                write!(fmt, "duplicate key `{}`, first defined at line {}", path, first.start.line),
+            DuplicateKey(ref path, _, None) => write!(fmt, "duplicate key `{}`", path),
+            DuplicateSection(ref path, _, Some(first)) =>
+                write!(fmt, "duplicate section `{}`, first defined at line {}", path, first.start.line),
+            DuplicateSection(ref path, _, None) => write!(fmt, "duplicate section `{}`", path),
+            ExpectedEndOfLine(_) => write!(fmt, "expected end of line"),
+            UnexpectedChar(c, expected, _) => write!(fmt, "expected {}, found {}", expected, describe_char(c)),
+            UnexpectedEof(expected, _) => write!(fmt, "expected {}, found end of input", expected),
+            UnterminatedString(_) => write!(fmt, "unterminated string"),
+            UnknownEscape(c, _) => write!(fmt, "unknown escape sequence `{}{}`", '\\', c),
+            InvalidNumber(_) => write!(fmt, "invalid number"),
+            InvalidDatetime(_) => write!(fmt, "invalid datetime"),
+            MixedArrayTypes(_) => write!(fmt, "array elements of different types"),
+            UnsupportedInVersion(what, version, _) =>
+                write!(fmt, "`{}` requires TOML {}", what, version.number()),
+            ExpectedEquals(_) => write!(fmt, "expected `=` after key"),
+            AppendNotArray(ref key, _) => write!(fmt, "`+=` on `{}` requires arrays", key),
+            MergeConflict(ref path) => write!(fmt, "conflicting values for `{}`", path),
+            NestingTooDeep(_) => write!(fmt, "nested too deeply"),
+            LimitExceeded(what, _) => write!(fmt, "{} exceeds the limit", what),
+            TypeMismatch(ref path, expected, ref found) if path.is_empty() => write!(fmt, "expected {}, found {}", expected, found),
+            TypeMismatch(ref path, expected, ref found) => write!(fmt, "expected {}, found {} for field `{}`", expected, found, path),
+            OutOfRange(ref path, ty) if path.is_empty() => write!(fmt, "value out of range for `{}`", ty),
+            OutOfRange(ref path, ty) => write!(fmt, "value out of range for `{}` for field `{}`", ty, path),
+            Unsupported(ref path, what) if path.is_empty() => write!(fmt, "cannot decode {}", what),
+            Unsupported(ref path, what) => write!(fmt, "cannot decode {} for field `{}`", what, path),
+            Rejected(_) => write!(fmt, "rejected by the visitor")
+        }
+    }
 }
 
 pub type DecodeResult<T> = Result<T, Error>;
@@ -89,21 +344,88 @@ pub type DecodeResult<T> = Result<T, Error>;
 fn have_equiv_types(v1: &Value, v2: &Value) -> bool {
     match (v1, v2) {
         (&Boolean(_), &Boolean(_)) => true,
-        (&PosInt(_), &PosInt(_)) => true,
-        (&PosInt(_), &NegInt(_)) => true,
-        (&NegInt(_), &PosInt(_)) => true,
-        (&NegInt(_), &NegInt(_)) => true,
+        (&Integer(_), &Integer(_)) => true,
         (&Float(_), &Float(_)) => true,
         (&String(_), &String(_)) => true,
         (&Datetime(..), &Datetime(..)) => true,
         (&Array(_), &Array(_)) => true, // Arrays can be heterogenous in TOML
+        (&Table(_), &Table(_)) => true,
         _ => false
     }
 }
 
+//
+// Edit distance between `a` and `b`, counted in characters. Besides
+// insertions, deletions and substitutions, swapping two adjacent characters
+// counts as a single edit, so that `ture` is close to `true`.
+//
+fn edit_distance(a: &str, b: &str) -> uint {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut prev2: Vec<uint> = vec!();
+    let mut prev: Vec<uint> = range(0, b.len() + 1).collect();
+    for (i, ca) in a.iter().enumerate() {
+        let mut curr = vec!(i + 1);
+        for (j, cb) in b.iter().enumerate() {
+            let cost = if ca == cb { 0 } else { 1 };
+            let mut dist = min(min(*prev.get(j + 1) + 1, *curr.get(j) + 1), *prev.get(j) + cost);
+            if i > 0 && j > 0 && *ca == *b.get(j - 1) && *a.get(i - 1) == *cb {
+                dist = min(dist, *prev2.get(j - 1) + 1);
+            }
+            curr.push(dist);
+        }
+        prev2 = prev;
+        prev = curr;
+    }
+    *prev.get(b.len())
+}
+
+//
+// Returns the candidate closest to `word`, provided it is close enough to be
+// a plausible typo (at most a third of the characters differ).
+//
+fn nearest_match<'a, I: Iterator<&'a str>>(word: &str, mut candidates: I) -> Option<&'a str> {
+    let limit = ::std::cmp::max(1, word.char_len() / 3);
+    let mut best: Option<(uint, &'a str)> = None;
+    for candidate in candidates {
+        let dist = edit_distance(word, candidate);
+        if dist > limit { continue }
+        match best {
+            Some((d, _)) if d <= dist => {}
+            _ => best = Some((dist, candidate))
+        }
+    }
+    best.map(|(_, candidate)| candidate)
+}
+
+//
+// The float values spelled as words, `inf` and `nan`, with `sign` applied.
+//
+fn special_float(word: &str, sign: f64) -> Option<Value> {
+    match word {
+        "inf" => Some(Float(sign * std::f64::INFINITY)),
+        "nan" => Some(Float(std::f64::NAN)),
+        _ => None
+    }
+}
+
 enum PathElement<'a> {
     Key(&'a str),
-    Idx(uint)
+    Idx(int)
+}
+
+//
+// Resolves `idx` into a position within a sequence of length `len`.
+// Negative indices count from the end, so -1 is the last element.
+//
+fn resolve_index(idx: int, len: uint) -> Option<uint> {
+    if idx >= 0 {
+        let idx = idx as uint;
+        if idx < len { Some(idx) } else { None }
+    } else {
+        let back = (-idx) as uint;
+        if back <= len { Some(len - back) } else { None }
+    }
 }
 
 trait LookupValue<'a> {
@@ -121,6 +443,18 @@ impl<'a> LookupValue<'a> for uint {
     }
 }
 
+// Negative indices count from the end of a table array
+impl<'a> LookupValue<'a> for int {
+    fn lookup_in(&self, value: &'a Value) -> Option<&'a Value> {
+        match value {
+           &TableArray(ref ary) => {
+               resolve_index(*self, ary.len()).and_then(|idx| ary.as_slice().get(idx))
+           }
+           _ => { None }
+        }
+    }
+}
+
 impl<'a, 'b> LookupValue<'a> for &'b str {
     fn lookup_in(&self, value: &'a Value) -> Option<&'a Value> {
         match value {
@@ -149,6 +483,35 @@ impl<'a, 'b, 'c> LookupValue<'a> for &'b[PathElement<'c>] {
 }
 
 impl Value {
+    /// Returns a human-readable name of the type of this value
+    pub fn type_name(&self) -> &'static str {
+        match *self {
+            NoValue => "no value",
+            Boolean(_) => "boolean",
+            Integer(_) => "integer",
+            Float(_) => "float",
+            String(_) => "string",
+            Datetime(..) => "datetime",
+            Array(_) => "array",
+            TableArray(_) => "table array",
+            Append(_) => "append",
+            TableInner(_) | Table(_) => "table"
+        }
+    }
+
+    // The type name along with the value if it is a scalar, e.g.
+    // `string "8080"`
+    fn describe(&self) -> String {
+        match *self {
+            Boolean(b) => format!("boolean {}", b),
+            Integer(n) => format!("integer {}", n),
+            Float(f) => format!("float {}", f),
+            String(ref s) => format!("string \"{}\"", s.as_slice().escape_default()),
+            Datetime(ref dt) => format!("datetime {}", dt.to_str()),
+            _ => self.type_name().to_string()
+        }
+    }
+
     pub fn get_bool(&self) -> Option<bool> {
         match self {
             &Boolean(b) => { Some(b) }
@@ -156,14 +519,32 @@ impl Value {
         }
     }
 
-    pub fn get_int(&self) -> Option<i64> { // XXX
+    pub fn get_int(&self) -> Option<i64> {
+        match self {
+            &Integer(i) => { Some(i) }
+            _ => { None }
+        }
+    }
+
+    /// Returns a non-negative integer as `u64`, `None` for negative integers
+    /// and other values.
+    pub fn get_u64(&self) -> Option<u64> {
         match self {
-            &PosInt(u) => { Some(u.to_i64().unwrap()) } // XXX
-            &NegInt(u) => { Some(-(u.to_i64().unwrap())) } // XXX
+            &Integer(i) if i >= 0 => { Some(i as u64) }
             _ => { None }
         }
     }
 
+    /// Like `get_int`, but describes the value found instead if it is not an
+    /// integer. Integers outside the range of `i64` are already rejected by
+    /// the parser with `IntegerOverflow`.
+    pub fn get_int_or_err(&self) -> Result<i64, String> {
+        match self {
+            &Integer(i) => Ok(i),
+            other => Err(format!("expected an integer, found {}", other.describe()))
+        }
+    }
+
     pub fn get_float(&self) -> Option<f64> {
         match self {
             &Float(num) => { Some(num) }
@@ -171,6 +552,36 @@ impl Value {
         }
     }
 
+    /// Like `get_float`, but also accepts integers, so that e.g. `timeout = 5`
+    /// can be read as a float. Large integers may lose precision.
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            &Float(num) => Some(num),
+            &Integer(i) => Some(i as f64),
+            _ => None
+        }
+    }
+
+    /// Like `get_int`, but also accepts floats without a fractional part
+    /// within the range of `i64`, like `3.0`.
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            &Integer(i) => Some(i),
+            // 2^63 is exactly representable, i64::MAX is not
+            &Float(num) if num.fract() == 0.0 && num >= -9223372036854775808.0
+                           && num < 9223372036854775808.0 => Some(num as i64),
+            _ => None
+        }
+    }
+
+    /// Like `as_i64`, but returns `None` for negative numbers
+    pub fn as_u64(&self) -> Option<u64> {
+        match self.as_i64() {
+            Some(i) if i >= 0 => Some(i as u64),
+            _ => None
+        }
+    }
+
     pub fn get_str<'a>(&'a self) -> Option<&'a String> {
         match self {
             &String(ref str) => { Some(str) }
@@ -178,6 +589,29 @@ impl Value {
         }
     }
 
+    pub fn get_str_mut<'a>(&'a mut self) -> Option<&'a mut String> {
+        match *self {
+            String(ref mut str) => Some(str),
+            _ => None
+        }
+    }
+
+    pub fn get_datetime<'a>(&'a self) -> Option<&'a datetime::Datetime> {
+        match self {
+            &Datetime(ref dt) => { Some(dt) }
+            _ => { None }
+        }
+    }
+
+    /// Decodes a base64 encoded string, as TOML has no type for binary data.
+    /// Returns `None` for other values and for invalid base64.
+    pub fn get_bytes_base64(&self) -> Option<Vec<u8>> {
+        match self {
+            &String(ref str) => str.as_slice().from_base64().ok(),
+            _ => None
+        }
+    }
+
     pub fn get_vec<'a>(&'a self) -> Option<&'a Vec<Value>> {
         match self {
             &Array(ref vec) => { Some(vec) }
@@ -185,6 +619,13 @@ impl Value {
         }
     }
 
+    pub fn get_vec_mut<'a>(&'a mut self) -> Option<&'a mut Vec<Value>> {
+        match *self {
+            Array(ref mut vec) => Some(vec),
+            _ => None
+        }
+    }
+
     pub fn get_table<'a>(&'a self) -> Option<&'a Box<HashMap<String, Value>>> {
         match self {
             &Table(ref table) | &TableInner(ref table) => Some(table),
@@ -192,6 +633,13 @@ impl Value {
         }
     }
 
+    pub fn get_table_mut<'a>(&'a mut self) -> Option<&'a mut Box<HashMap<String, Value>>> {
+        match *self {
+            Table(ref mut table) | TableInner(ref mut table) => Some(table),
+            _ => None
+        }
+    }
+
     pub fn get_table_array<'a>(&'a self) -> Option<&'a Vec<Value>> {
         match self {
             &TableArray(ref vec) => { Some(vec) }
@@ -199,6 +647,13 @@ impl Value {
         }
     }
 
+    pub fn get_table_array_mut<'a>(&'a mut self) -> Option<&'a mut Vec<Value>> {
+        match *self {
+            TableArray(ref mut vec) => Some(vec),
+            _ => None
+        }
+    }
+
     pub fn lookup_elm<'a>(&'a self, elm: &LookupValue<'a>) -> Option<&'a Value> {
         elm.lookup_in(self)
     }
@@ -212,6 +667,28 @@ impl Value {
         }
     }
 
+    /// Like `lookup_vec`, but returns a mutable reference
+    pub fn lookup_vec_mut<'a>(&'a mut self, idx: uint) -> Option<&'a mut Value> {
+        match *self {
+            Array(ref mut ary) if idx < ary.len() => Some(ary.get_mut(idx)),
+            _ => None
+        }
+    }
+
+    /// Like `lookup_vec`, but negative indices count from the end, so
+    /// `lookup_vec_signed(-1)` returns the last element.
+    pub fn lookup_vec_signed<'a>(&'a self, idx: int) -> Option<&'a Value> {
+        match self {
+            &Array(ref ary) => {
+                resolve_index(idx, ary.len()).and_then(|idx| ary.as_slice().get(idx))
+            }
+            _ => { None }
+        }
+    }
+
+    /// Returns the value at the dotted `path`, e.g. `products.1.name`.
+    /// Elements of table arrays are addressed by index, negative indices
+    /// counting from the end, e.g. `history.-1`.
     pub fn lookup<'a>(&'a self, path: &'a str) -> Option<&'a Value> {
         let mut curr: Option<&'a Value> = Some(self);
 
@@ -219,7 +696,7 @@ impl Value {
           match curr {
             None => break,
             Some(s) => { 
-              let elm = match from_str::<uint>(p) {
+              let elm = match from_str::<int>(p) {
                 Some(idx) => Idx(idx),
                 None => Key(p),
               };
@@ -230,21 +707,298 @@ impl Value {
 
         return curr 
     }
+
+    /// Like `lookup`, but returns a mutable reference, so that the value can
+    /// be modified in place
+    pub fn lookup_mut<'a>(&'a mut self, path: &str) -> Option<&'a mut Value> {
+        let elms: Vec<PathElement> = path.split_str(".").map(|p| {
+            match from_str::<int>(p) {
+                Some(idx) => Idx(idx),
+                None => Key(p)
+            }
+        }).collect();
+        self.lookup_path_mut(elms.as_slice())
+    }
+
+    fn lookup_path_mut<'a>(&'a mut self, path: &[PathElement]) -> Option<&'a mut Value> {
+        match path.head() {
+            None => Some(self),
+            Some(elm) => {
+                match self.child_mut(elm) {
+                    Some(child) => child.lookup_path_mut(path.tail()),
+                    None => None
+                }
+            }
+        }
+    }
+
+    /// Sets the value at the dotted `path`, e.g. `server.port`, replacing
+    /// an existing value. Missing tables on the way are created. Elements
+    /// of arrays and table arrays are addressed by index, where negative
+    /// indices count from the end (unlike `lookup`, which only indexes
+    /// table arrays).
+    /// Returns `false`, leaving the value unchanged, if a key is empty, or
+    /// the path is blocked by a value which is not a table or by an index
+    /// out of range.
+    pub fn set(&mut self, path: &str, val: Value) -> bool {
+        let keys: Vec<&str> = path.split('.').collect();
+        if !self.can_set_path(keys.as_slice()) { return false }
+        self.set_path(keys.as_slice(), val)
+    }
+
+    // Whether `set_path` would succeed, so that it creates no tables for a
+    // path which turns out to be blocked
+    fn can_set_path(&self, path: &[&str]) -> bool {
+        if path.is_empty() || path.iter().any(|key| key.is_empty()) { return false }
+        let key = path[0];
+        let last = path.len() == 1;
+        match *self {
+            Table(ref map) | TableInner(ref map) => {
+                if last { return true }
+                match map.find_equiv(&key) {
+                    Some(child) => child.can_set_path(path.tail()),
+                    // the tables on the way are created
+                    None => true
+                }
+            }
+            TableArray(ref ary) | Array(ref ary) => {
+                match from_str::<int>(key).and_then(|idx| resolve_index(idx, ary.len())) {
+                    Some(_) if last => true,
+                    Some(idx) => ary.get(idx).can_set_path(path.tail()),
+                    None => false
+                }
+            }
+            _ => false
+        }
+    }
+
+    fn set_path(&mut self, path: &[&str], val: Value) -> bool {
+        let key = match path.head() {
+            Some(key) if !key.is_empty() => *key,
+            _ => return false
+        };
+        let last = path.len() == 1;
+        match *self {
+            Table(ref mut map) | TableInner(ref mut map) => {
+                let key = key.to_string();
+                if last {
+                    map.insert(key, val);
+                    return true;
+                }
+                if !map.contains_key(&key) {
+                    map.insert(key.clone(), TableInner(box HashMap::new()));
+                }
+                match map.find_mut(&key) {
+                    Some(child) => child.set_path(path.tail(), val),
+                    None => false
+                }
+            }
+            TableArray(ref mut ary) | Array(ref mut ary) => {
+                let idx = match from_str::<int>(key).and_then(|idx| resolve_index(idx, ary.len())) {
+                    Some(idx) => idx,
+                    None => return false
+                };
+                if last {
+                    *ary.get_mut(idx) = val;
+                    true
+                } else {
+                    ary.get_mut(idx).set_path(path.tail(), val)
+                }
+            }
+            _ => false
+        }
+    }
+
+    /// Removes the value at the dotted `path`, a key or an element of an
+    /// array or table array (addressed by index as for `set`), and returns
+    /// it.
+    pub fn remove(&mut self, path: &str) -> Option<Value> {
+        let keys: Vec<&str> = path.split('.').collect();
+        self.remove_path(keys.as_slice(), false)
+    }
+
+    /// Like `remove`, but also removes the tables on the way to `path`
+    /// which are left empty
+    pub fn remove_pruned(&mut self, path: &str) -> Option<Value> {
+        let keys: Vec<&str> = path.split('.').collect();
+        self.remove_path(keys.as_slice(), true)
+    }
+
+    fn remove_path(&mut self, path: &[&str], prune: bool) -> Option<Value> {
+        let key = match path.head() {
+            Some(key) => *key,
+            None => return None
+        };
+        let last = path.len() == 1;
+        match *self {
+            Table(ref mut map) | TableInner(ref mut map) => {
+                let key = key.to_string();
+                if last { return map.pop(&key) }
+                let (emptied, removed) = match map.find_mut(&key) {
+                    Some(child) => {
+                        let removed = child.remove_path(path.tail(), prune);
+                        let empty = match *child {
+                            Table(ref m) | TableInner(ref m) => m.is_empty(),
+                            _ => false
+                        };
+                        (removed.is_some() && prune && empty, removed)
+                    }
+                    None => return None
+                };
+                if emptied { map.pop(&key); }
+                removed
+            }
+            TableArray(ref mut ary) | Array(ref mut ary) => {
+                let idx = match from_str::<int>(key).and_then(|idx| resolve_index(idx, ary.len())) {
+                    Some(idx) => idx,
+                    None => return None
+                };
+                // elements are never pruned, as that would shift the others
+                if last { ary.remove(idx) } else { ary.get_mut(idx).remove_path(path.tail(), prune) }
+            }
+            _ => None
+        }
+    }
+
+    // The child at `elm`, found like `LookupValue::lookup_in` does
+    fn child_mut<'a>(&'a mut self, elm: &PathElement) -> Option<&'a mut Value> {
+        match *self {
+            Table(ref mut map) | TableInner(ref mut map) => {
+                match *elm {
+                    Key(key) => map.find_mut(&key.to_string()),
+                    Idx(_) => None
+                }
+            }
+            TableArray(ref mut ary) => {
+                match *elm {
+                    Idx(idx) => match resolve_index(idx, ary.len()) {
+                        Some(idx) => Some(ary.get_mut(idx)),
+                        None => None
+                    },
+                    Key(_) => None
+                }
+            }
+            _ => None
+        }
+    }
 }
 
-trait Visitor {
-    fn section(&mut self, name: String, is_array: bool) -> bool;
+/// Receives the contents of a document from the parser, in document order
+pub trait Visitor {
+    /// Called for a `[a.b]` section header, or a `[[a.b]]` header if
+    /// `is_array` is true. `path` holds the keys of the header, unquoted,
+    /// e.g. `["a", "b"]`. Return `false` to abort parsing.
+    fn section(&mut self, path: Vec<String>, is_array: bool) -> bool;
+    /// Called for a `key = val` pair. Return `false` to abort parsing.
     fn pair(&mut self, key: String, val: Value) -> bool;
+
+    /// Like `section`, additionally receiving the span of the header. This
+    /// is what the parser calls, by default it forwards to `section`.
+    fn section_at(&mut self, path: Vec<String>, is_array: bool, _span: span::Span) -> bool {
+        self.section(path, is_array)
+    }
+
+    /// Like `pair`, additionally receiving the span of the value. This is
+    /// what the parser calls, by default it forwards to `pair`.
+    fn pair_at(&mut self, key: String, val: Value, _span: span::Span) -> bool {
+        self.pair(key, val)
+    }
+
+    /// Called before `pair_at` for every array element and inline table
+    /// member within the value of `key`, with its path relative to that
+    /// value (e.g. `["1", "x"]` for `key = [{...}, {x = 1}]`) and its span.
+    /// By default it does nothing.
+    fn nested_at(&mut self, _key: &str, _path: &[String], _span: span::Span) {}
+
+    /// Returns, and forgets, why the last call returned `false`, if known.
+    /// The parser reports it in place of `Rejected`.
+    fn take_error(&mut self) -> Option<Error> {
+        None
+    }
 }
 
-struct ValueBuilder<'a> {
-    root: &'a mut Box<HashMap<String, Value>>,
-    current_path: Vec<String>
+/// Builds a `Value` tree from the contents of a document. This is the
+/// `Visitor` used by the `parse_from_*` functions. It implements the TOML
+/// semantics of sections, implicitly created tables and table arrays, and
+/// rejects duplicate keys and sections.
+pub struct ValueBuilder {
+    root: Box<HashMap<String, Value>>,
+    current_path: Vec<String>,
+    // why the last section or pair was rejected
+    error: Option<Error>,
+    // resolved paths (see `resolve_path`) of the tables defined inline,
+    // which sections may not extend
+    inline_tables: HashSet<String>,
+    // the span of the section or pair being added, if known, and the spans
+    // of those added before by resolved path
+    span: Option<Span>,
+    defined: HashMap<String, Span>
 }
-  
-impl<'a> ValueBuilder<'a> {
-    fn new(root: &'a mut Box<HashMap<String, Value>>) -> ValueBuilder<'a> {
-        ValueBuilder { root: root, current_path: vec!() }
+
+impl ValueBuilder {
+    /// Creates a builder for an empty document
+    pub fn new() -> ValueBuilder {
+        ValueBuilder { root: box HashMap::new(), current_path: vec!(), error: None,
+                       inline_tables: HashSet::new(), span: None, defined: HashMap::new() }
+    }
+
+    // Remembers the span of the section or pair at `path`, which was just
+    // added
+    fn record_span(&mut self, path: &[String]) {
+        match (self.span.take(), self.resolve_path(path)) {
+            (Some(span), Some(resolved)) => { self.defined.insert(resolved, span); }
+            _ => {}
+        }
+    }
+
+    // The span of the existing definition of `path`, if known
+    fn defined_at(&self, path: &[String]) -> Option<Span> {
+        self.resolve_path(path).and_then(|resolved| self.defined.find(&resolved).map(|span| *span))
+    }
+
+    // Resolves the dotted `path` of an existing value into one addressing
+    // the latest element of every table array it passes through, e.g.
+    // `a.2.b`. Returns `None` if there is no such value.
+    fn resolve_path(&self, path: &[String]) -> Option<String> {
+        let mut resolved = String::new();
+        let mut table = &self.root;
+        for (i, key) in path.iter().enumerate() {
+            resolved = join_path(resolved.as_slice(), key.as_slice());
+            let last = i + 1 == path.len();
+            match table.find(key) {
+                Some(&Table(ref t)) | Some(&TableInner(ref t)) => table = t,
+                Some(&TableArray(ref vec)) => {
+                    resolved.push_str(format!(".{}", vec.len() - 1).as_slice());
+                    match vec.last() {
+                        Some(&Table(ref t)) | Some(&TableInner(ref t)) => table = t,
+                        _ => return None
+                    }
+                }
+                Some(_) if last => {}
+                _ => return None
+            }
+        }
+        Some(resolved)
+    }
+
+    // Whether `path`, or one of the tables on the way to it, was defined
+    // inline
+    fn extends_inline_table(&self, path: &[String]) -> bool {
+        range(1, path.len() + 1).any(|n| {
+            match self.resolve_path(path.slice_to(n)) {
+                Some(resolved) => self.inline_tables.contains(&resolved),
+                None => false
+            }
+        })
+    }
+
+    /// Returns the document built so far
+    pub fn into_value(self) -> Value {
+        TableInner(self.into_table())
+    }
+
+    fn into_table(self) -> Box<HashMap<String, Value>> {
+        self.root
     }
 
     fn recursive_create_tree_terminal(key: &String, ht: &mut Box<HashMap<String, Value>>, is_array: bool) -> bool {
@@ -252,8 +1006,6 @@ impl<'a> ValueBuilder<'a> {
             Some(node) => {
                 match node {
                     &TableArray(ref mut table_array) => {
-                        assert!(table_array.len() > 0);
-
                         if is_array {
                             table_array.push(Table(box HashMap::new()));
                             return true;
@@ -284,12 +1036,14 @@ impl<'a> ValueBuilder<'a> {
                         else {
                             // [a.b.c]
                             // [a.b]
-                            use std::mem::replace;
-                            let hasht = match replace(node, NoValue) {
-                              TableInner(inner) => inner,
-                              _ => unreachable!()
+                            let hasht = match mem::replace(node, NoValue) {
+                                TableInner(inner) => inner,
+                                other => {
+                                    *node = other;
+                                    return false;
+                                }
                             };
-                            replace(node, Table(hasht));
+                            *node = Table(hasht);
                             return true;
                         }
                     }
@@ -307,17 +1061,14 @@ impl<'a> ValueBuilder<'a> {
         let value =
             if is_array { TableArray(vec!(TableInner(box HashMap::new()))) }
             else { Table(box HashMap::new()) };
-        let ok = ht.insert(key.to_str(), value);
-        assert!(ok);
-        return ok;
+        return ht.insert(key.to_str(), value);
     }
 
     fn recursive_create_tree(path: &[String], ht: &mut Box<HashMap<String, Value>>, is_array: bool) -> bool {
-        assert!(path.len() > 0);
-
-        if path.head().unwrap().is_empty() { return false } // don't allow empty keys
-
-        let head = path.head().unwrap(); // TODO: optimize
+        let head = match path.head() {
+            Some(head) if !head.is_empty() => head,
+            _ => return false // don't allow empty keys
+        };
 
         if path.len() == 1 {
             // terminal recursion
@@ -328,15 +1079,14 @@ impl<'a> ValueBuilder<'a> {
             Some(node) => {
                 match node {
                     &TableArray(ref mut table_array) => {
-                        assert!(table_array.len() > 0);
-
                         match table_array.mut_last() {
                            Some(&Table(ref mut hmap)) | Some(&TableInner(ref mut hmap)) => {
                                 return ValueBuilder::recursive_create_tree(path.tail(), hmap, is_array);
                             }
                             _ => {
-                                // TableArray's only contain Table's and must be non-empty
-                                unreachable!();
+                                // TableArray's only contain Table's and are
+                                // non-empty, unless built by hand
+                                return false;
                             }
                         }
                     }
@@ -357,9 +1107,34 @@ impl<'a> ValueBuilder<'a> {
         let mut table = box HashMap::new();
         let ok = ValueBuilder::recursive_create_tree(path.tail(), &mut table, is_array);
         if !ok { return false }
-        let ok = ht.insert(head.to_str(), TableInner(table));
-        assert!(ok);
-        return ok;
+        return ht.insert(head.to_str(), TableInner(table));
+    }
+
+    // Inserts `val` at the dotted `path`, creating missing tables on the way
+    // as TableInner. Fails on empty keys, if a non-table is in the way or if
+    // the key already exists.
+    fn insert_path(path: &[String], ht: &mut Box<HashMap<String, Value>>, val: Value) -> bool {
+        let head = match path.head() {
+            Some(head) if !head.is_empty() => head,
+            _ => return false // don't allow empty keys
+        };
+
+        if path.len() == 1 {
+            return ht.insert(head.clone(), val);
+        }
+
+        if !ht.contains_key(head) {
+            ht.insert(head.clone(), TableInner(box HashMap::new()));
+        }
+        match ht.find_mut(head) {
+            Some(&Table(ref mut table)) | Some(&TableInner(ref mut table)) => {
+                return ValueBuilder::insert_path(path.tail(), table, val);
+            }
+            _ => {
+                debug!("Wrong type/duplicate key");
+                return false;
+            }
+        }
     }
 
     fn insert_value(path: &[String], key: &str, ht: &mut Box<HashMap<String, Value>>, val: Value) -> bool {
@@ -373,14 +1148,14 @@ impl<'a> ValueBuilder<'a> {
                     return ValueBuilder::insert_value(path.tail(), key, table, val);
                 }
                 Some(&TableArray(ref mut table_array)) => {
-                    assert!(table_array.len() > 0);
                     match table_array.mut_last() {
                         Some(&Table(ref mut hmap)) | Some(&TableInner(ref mut hmap)) => {
                             return ValueBuilder::insert_value(path.tail(), key, hmap, val);
                         }
                         _ => {
-                            // TableArray's only contain Table's and must be non-empty
-                            unreachable!();
+                            // TableArray's only contain Table's and are
+                            // non-empty, unless built by hand
+                            return false;
                         }
                     }
                 }
@@ -393,56 +1168,583 @@ impl<'a> ValueBuilder<'a> {
     }
 }
 
-impl<'a> Visitor for ValueBuilder<'a> {
-    fn section(&mut self, name: String, is_array: bool) -> bool {
-        self.current_path = name.as_slice().split('.').map(|i| i.to_str()).collect();
+impl Visitor for ValueBuilder {
+    fn section(&mut self, path: Vec<String>, is_array: bool) -> bool {
+        self.current_path = path;
+
+        // a table defined by `key = { ... }` is complete
+        if self.extends_inline_table(self.current_path.as_slice()) {
+            debug!("Section extends inline table: {}", self.current_path);
+            let first = self.defined_at(self.current_path.as_slice());
+            self.error = Some(DuplicateSection(self.current_path.connect("."), self.span.take(), first));
+            return false;
+        }
 
-        let ok = ValueBuilder::recursive_create_tree(self.current_path.as_slice(), self.root, is_array);
+        let ok = ValueBuilder::recursive_create_tree(self.current_path.as_slice(), &mut self.root, is_array);
         if !ok {
-            debug!("Duplicate section: {}", name);
+            debug!("Duplicate section: {}", self.current_path);
+            let first = self.defined_at(self.current_path.as_slice());
+            self.error = Some(DuplicateSection(self.current_path.connect("."), self.span.take(), first));
+        } else {
+            let path = self.current_path.clone();
+            self.record_span(path.as_slice());
         }
         return ok;
     }
 
     fn pair(&mut self, key: String, val: Value) -> bool {
-        let ok = ValueBuilder::insert_value(self.current_path.as_slice(), key.as_slice(), self.root, val);
+        let inline = match val { Table(_) => true, _ => false };
+        let ok = ValueBuilder::insert_value(self.current_path.as_slice(), key.as_slice(), &mut self.root, val);
+        let mut path = self.current_path.clone();
+        path.push(key);
         if !ok {
-            debug!("Duplicate key: {} in path {}", key, self.current_path);
+            debug!("Duplicate key: {}", path);
+            let first = self.defined_at(path.as_slice());
+            self.error = Some(DuplicateKey(path.connect("."), self.span.take(), first));
+            return false;
         }
+        if inline {
+            match self.resolve_path(path.as_slice()) {
+                Some(resolved) => { self.inline_tables.insert(resolved); }
+                None => {}
+            }
+        }
+        self.record_span(path.as_slice());
         return ok;
     }
-}
 
-struct Parser<'a, BUF> {
-    rd: &'a mut BUF,
-    current_char: IoResult<char>,
-    line: uint
-}
+    fn section_at(&mut self, path: Vec<String>, is_array: bool, span: Span) -> bool {
+        self.span = Some(span);
+        self.section(path, is_array)
+    }
 
-impl<'a, BUF: Buffer> Parser<'a, BUF> {
-    fn new(rd: &'a mut BUF) -> Parser<'a, BUF> {
-        let ch = rd.read_char();
-        let mut line = 1;
-        if ch == Ok('\n') { line += 1 }
-        Parser { rd: rd, current_char: ch, line: line }
+    fn pair_at(&mut self, key: String, val: Value, span: Span) -> bool {
+        self.span = Some(span);
+        self.pair(key, val)
     }
 
-    fn advance(&mut self) {
-        self.current_char = self.rd.read_char();
+    fn take_error(&mut self) -> Option<Error> {
+        self.error.take()
     }
+}
 
-    fn get_line(&self) -> uint { self.line }
+/// A version of the TOML specification to validate documents against, see
+/// `ParserOptions::version`. Only the syntax listed for each version is
+/// gated: syntax this parser does not implement at all, like dotted keys in
+/// key/value pairs or dates and times without the other half, is rejected
+/// for every version, so `V1_0` does not make it a TOML 1.0 parser.
+#[deriving(Show,Clone,PartialEq,Eq,PartialOrd,Ord)]
+pub enum TomlVersion {
+    /// Basic strings, homogeneous arrays and `Z` datetimes only
+    V0_2,
+    /// Adds literal and multi-line strings, quoted keys, inline tables,
+    /// `+` signs, `_` digit separators, `\U` escapes, fractional seconds
+    /// and offsets other than `Z`
+    V0_4,
+    /// Adds `inf` and `nan` and datetimes without offset, and allows
+    /// arrays of mixed types
+    V1_0
+}
 
-    fn ch(&self) -> Option<char> {
-        match self.current_char {
-            Ok(c) => Some(c),
-            Err(_) => None
+impl TomlVersion {
+    /// The version number, e.g. "0.4"
+    pub fn number(&self) -> &'static str {
+        match *self {
+            V0_2 => "0.2",
+            V0_4 => "0.4",
+            V1_0 => "1.0"
         }
     }
+}
 
-    /// Returns `true` if the input is exhausted (due to EOF or an error)
-    fn eos(&self) -> bool {
-        return self.current_char.is_err();
+/// Limits on the size of a document, see `ParserOptions::limits`.
+/// `None` means unlimited, which is the default.
+#[deriving(Show,Clone,PartialEq,Default)]
+pub struct Limits {
+    /// Maximum number of key/value pairs, including those of inline tables
+    pub max_keys: Option<uint>,
+    /// Maximum number of array elements, including those of nested arrays
+    pub max_array_elements: Option<uint>,
+    /// Maximum number of section headers, counting every `[[table array]]`
+    /// element
+    pub max_tables: Option<uint>,
+    /// Maximum length of a string, a key or a bare word, in bytes
+    pub max_string_len: Option<uint>
+}
+
+/// The configuration of `parse_from_buffer_with` and
+/// `parse_from_buffer_detailed`, built up like
+/// `ParserOptions::new().version(V1_0).max_depth(16)`
+pub struct ParserOptions<'a> {
+    version: Option<TomlVersion>,
+    allow_append: bool,
+    max_depth: uint,
+    limits: Limits,
+    recover: bool,
+    spans: bool,
+    stats: bool,
+    sections: Option<&'a [&'a str]>,
+    extensions: Option<&'a mut Extensions>,
+    interrupt: Option<Interrupt<'a>>,
+    skip_bodies: bool
+}
+
+impl<'a> ParserOptions<'a> {
+    /// The options of `parse_from_buffer`
+    pub fn new() -> ParserOptions<'a> {
+        ParserOptions { version: None, allow_append: false, max_depth: DEFAULT_MAX_DEPTH,
+                        limits: Default::default(), recover: false, spans: false, stats: false,
+                        sections: None, extensions: None, interrupt: None, skip_bodies: false }
+    }
+
+    /// Validates the document against `version` of the TOML specification:
+    /// the syntax `TomlVersion` lists for later versions is rejected, and
+    /// arrays of mixed types are accepted for `V1_0`. Otherwise, all syntax
+    /// supported by this parser is accepted, and the elements of an array
+    /// have to be of the same type.
+    pub fn version(mut self, version: TomlVersion) -> ParserOptions<'a> {
+        self.version = Some(version);
+        self
+    }
+
+    /// Accepts `key += [...]`, which yields an `Append` value. When merged
+    /// into a document (see `config::Layers`), its elements are appended to
+    /// the array `key` instead of replacing it.
+    pub fn allow_append(mut self, allow: bool) -> ParserOptions<'a> {
+        self.allow_append = allow;
+        self
+    }
+
+    /// Fails with `NestingTooDeep` on arrays and inline tables nested deeper
+    /// than `max_depth`, and on section names with more than `max_depth`
+    /// keys. This bounds the stack space needed to parse untrusted
+    /// documents. The default is `DEFAULT_MAX_DEPTH`.
+    pub fn max_depth(mut self, max_depth: uint) -> ParserOptions<'a> {
+        self.max_depth = max_depth;
+        self
+    }
+
+    /// Fails with `LimitExceeded` as soon as the document exceeds one of
+    /// `limits`. To parse untrusted documents, limit their nesting depth
+    /// as well.
+    pub fn limits(mut self, limits: Limits) -> ParserOptions<'a> {
+        self.limits = limits;
+        self
+    }
+
+    /// Does not stop at the first error. A section header or key/value pair
+    /// which fails to parse is skipped up to the end of its line, the body
+    /// of a broken section header as a whole, and parsing continues. The
+    /// errors are returned in `ParseOutput::errors`, in document order.
+    pub fn recover(mut self, recover: bool) -> ParserOptions<'a> {
+        self.recover = recover;
+        self
+    }
+
+    /// Records the source spans of all section headers, key/value pairs,
+    /// array elements and inline table members in `ParseOutput::spans`
+    pub fn spans(mut self, spans: bool) -> ParserOptions<'a> {
+        self.spans = spans;
+        self
+    }
+
+    /// Collects statistics about the document in `ParseOutput::stats`
+    pub fn stats(mut self, stats: bool) -> ParserOptions<'a> {
+        self.stats = stats;
+        self
+    }
+
+    /// Only parses the top-level sections named in `sections` (and the
+    /// key/value pairs before the first section). The bodies of all other
+    /// sections are skipped without building any values, and without
+    /// checking their syntax.
+    pub fn sections(mut self, sections: &'a [&'a str]) -> ParserOptions<'a> {
+        self.sections = Some(sections);
+        self
+    }
+
+    /// Accepts the extension values handled by `extensions`. An `@name`
+    /// value without a registered handler, or rejected by its handler, is a
+    /// parse error.
+    pub fn extensions(mut self, extensions: &'a mut Extensions) -> ParserOptions<'a> {
+        self.extensions = Some(extensions);
+        self
+    }
+
+    /// Calls `keep_going` with the number of bytes consumed so far each
+    /// time another `every` bytes have been parsed. If it returns `false`,
+    /// parsing is aborted with a `Cancelled` error.
+    pub fn interrupt(mut self, every: uint, keep_going: |uint|:'a -> bool) -> ParserOptions<'a> {
+        self.interrupt = Some(Interrupt { every: every, next: every, keep_going: keep_going });
+        self
+    }
+
+    /// Reports section headers, but skips their bodies without checking
+    /// their syntax, so that the document consists of empty tables
+    pub fn skip_bodies(mut self, skip: bool) -> ParserOptions<'a> {
+        self.skip_bodies = skip;
+        self
+    }
+}
+
+/// Statistics about a parsed document, see `ParserOptions::stats`
+#[deriving(Show,Clone,PartialEq,Default)]
+pub struct ParseStats {
+    /// Number of bytes parsed
+    pub bytes: uint,
+    /// Number of lines parsed
+    pub lines: uint,
+    /// Number of `[table]` and `[[table array]]` headers
+    pub tables: uint,
+    /// Number of key/value pairs
+    pub keys: uint,
+    /// Number of array elements, including those of nested arrays
+    pub array_elements: uint,
+    /// Maximum nesting depth of a value, counting tables and arrays. A key
+    /// at the top-level has depth 1, a key in `[a.b]` depth 3.
+    pub max_depth: uint
+}
+
+impl ParseStats {
+    // Accounts for a key/value pair in a section of depth `depth`
+    fn add_pair(&mut self, val: &Value, depth: uint) {
+        self.keys += 1;
+        let depth = depth + 1 + self.add_elements(val);
+        if depth > self.max_depth { self.max_depth = depth }
+    }
+
+    // Counts the array elements of `val`, returning its array nesting depth
+    fn add_elements(&mut self, val: &Value) -> uint {
+        match *val {
+            Array(ref arr) => {
+                self.array_elements += arr.len();
+                1 + arr.iter().fold(0, |depth, elm| std::cmp::max(depth, self.add_elements(elm)))
+            }
+            _ => 0
+        }
+    }
+}
+
+/// The result of `parse_from_buffer_detailed`
+pub struct ParseOutput {
+    /// The document. With `ParserOptions::recover`, it holds everything
+    /// that did parse.
+    pub value: Value,
+    /// The source spans, if requested with `ParserOptions::spans`
+    pub spans: Option<SpanMap>,
+    /// Statistics about the document, if requested with
+    /// `ParserOptions::stats`
+    pub stats: Option<ParseStats>,
+    /// The errors recovered from with `ParserOptions::recover`, in document
+    /// order
+    pub errors: Vec<Error>
+}
+
+/// A handler for extension values, see `Extensions`. It receives the
+/// argument and returns the resulting value, or `None` if the argument is
+/// invalid. Being a closure, it may capture configuration, like a base
+/// directory to resolve `@path` arguments against.
+pub type ExtensionFn = Box<|&str|: 'static -> Option<Value>>;
+
+/// Handlers for values of the form `@name argument`, which are otherwise
+/// invalid TOML, e.g. `timeout = @duration 5m`. The argument extends to the
+/// end of the line, a comment, or a `,`, `]` or `}` within an array or
+/// inline table. See `ParserOptions::extensions`.
+pub struct Extensions {
+    handlers: HashMap<String, ExtensionFn>
+}
+
+impl Extensions {
+    pub fn new() -> Extensions {
+        Extensions { handlers: HashMap::new() }
+    }
+
+    /// Registers `handler` for values starting with `@name`
+    pub fn register(&mut self, name: &str, handler: ExtensionFn) {
+        self.handlers.insert(name.to_string(), handler);
+    }
+}
+
+// Periodically asks the caller whether parsing should go on
+struct Interrupt<'a> {
+    every: uint,
+    next: uint,
+    keep_going: |uint|:'a -> bool
+}
+
+struct Parser<'a, BUF> {
+    rd: &'a mut BUF,
+    current_char: IoResult<char>,
+    // whether `current_char` is a `\n` read from a CRLF sequence
+    crlf: bool,
+    // position of `current_char`
+    line: uint,
+    col: uint,
+    offset: uint,
+    interrupt: Option<Interrupt<'a>>,
+    cancelled: bool,
+    stats: Option<ParseStats>,
+    // depth of the current section, for `stats`
+    section_depth: uint,
+    // why the construct at hand fails, see `fail` and `failure`
+    error: Option<Error>,
+    extensions: Option<&'a mut Extensions>,
+    // the top-level sections to parse, all others are skipped
+    only_sections: Option<&'a [&'a str]>,
+    // report section headers, but skip their bodies
+    skip_bodies: bool,
+    // accept `key += [...]`
+    allow_append: bool,
+    // the specification to validate against, `None` for everything supported
+    version: Option<TomlVersion>,
+    // continue after errors, collecting them in `errors`
+    recover: bool,
+    errors: Vec<Error>,
+    // the number of arrays and inline tables the parser is in, and the
+    // limit of it and of the number of keys of a section name, which
+    // bounds the recursion of parser and `ValueBuilder`
+    nesting: uint,
+    max_nesting: uint,
+    limits: Limits,
+    // the number of keys, array elements and section headers parsed, for
+    // `limits`
+    keys: uint,
+    array_elements: uint,
+    tables: uint,
+    // the path of the array element or inline table member being parsed,
+    // relative to the value of the current pair, and the spans of those
+    // parsed so far, for `Visitor::nested_at`
+    nested_path: Vec<String>,
+    nested_spans: Vec<(Vec<String>, span::Span)>
+}
+
+/// The default limit of `ParserOptions::max_depth`, applied by all parse
+/// functions
+pub static DEFAULT_MAX_DEPTH: uint = 128;
+
+impl<'a, BUF: Buffer> Parser<'a, BUF> {
+    fn new(rd: &'a mut BUF) -> Parser<'a, BUF> {
+        let mut parser = Parser {
+            rd: rd, current_char: Err(IoError { kind: EndOfFile, desc: "", detail: None }),
+            crlf: false, line: 1, col: 1, offset: 0,
+            interrupt: None, cancelled: false, stats: None, section_depth: 0,
+            error: None, extensions: None, only_sections: None,
+            skip_bodies: false, allow_append: false, version: None,
+            recover: false, errors: vec!(), nesting: 0, max_nesting: DEFAULT_MAX_DEPTH,
+            limits: Default::default(), keys: 0, array_elements: 0, tables: 0,
+            nested_path: vec!(), nested_spans: vec!()
+        };
+        parser.read_char();
+        // skip a byte order mark, as written by some editors
+        if parser.ch() == Some('\uFEFF') {
+            parser.offset += '\uFEFF'.len_utf8_bytes();
+            parser.read_char();
+        }
+        parser
+    }
+
+    // Reads the next character into `current_char`. A CRLF sequence is
+    // read as a single `\n`, a bare carriage return ends the input with a
+    // `BareCarriageReturn` error, and invalid UTF-8 with an `InvalidUtf8`
+    // error.
+    fn read_char(&mut self) {
+        self.crlf = false;
+        self.current_char = self.rd.read_char();
+        match self.current_char {
+            Ok('\r') => {}
+            Err(IoError { kind: InvalidInput, .. }) => {
+                let pos = self.pos();
+                let end = span::Pos { col: pos.col + 1, offset: pos.offset + 1, ..pos };
+                self.error = Some(InvalidUtf8(span::Span { start: pos, end: end }));
+                return
+            }
+            _ => return
+        }
+
+        match self.rd.read_char() {
+            Ok('\n') => {
+                self.crlf = true;
+                self.current_char = Ok('\n');
+            }
+            _ => {
+                self.error = Some(BareCarriageReturn(self.char_span('\r')));
+                self.current_char = Err(IoError { kind: OtherIoError,
+                                                  desc: "bare carriage return", detail: None });
+            }
+        }
+    }
+
+    // Whether syntax introduced by specification `since` is accepted
+    fn allows(&self, since: TomlVersion) -> bool {
+        match self.version {
+            Some(version) => version >= since,
+            None => true
+        }
+    }
+
+    fn with_options(rd: &'a mut BUF, options: ParserOptions<'a>) -> Parser<'a, BUF> {
+        let mut parser = Parser::new(rd);
+        parser.version = options.version;
+        parser.allow_append = options.allow_append;
+        parser.max_nesting = options.max_depth;
+        parser.limits = options.limits;
+        parser.recover = options.recover;
+        if options.stats { parser.stats = Some(Default::default()) }
+        parser.only_sections = options.sections;
+        parser.extensions = options.extensions;
+        parser.interrupt = options.interrupt;
+        parser.skip_bodies = options.skip_bodies;
+        parser
+    }
+
+    fn advance(&mut self) {
+        match self.current_char {
+            Ok(c) => {
+                self.offset += c.len_utf8_bytes();
+                if self.crlf { self.offset += 1 }
+                if c == '\n' {
+                    self.line += 1;
+                    self.col = 1;
+                } else {
+                    self.col += 1;
+                }
+            }
+            Err(_) => {}
+        }
+        self.read_char();
+        self.check_interrupt();
+    }
+
+    fn check_interrupt(&mut self) {
+        let cancel = match self.interrupt {
+            Some(ref mut interrupt) if self.offset >= interrupt.next => {
+                interrupt.next = self.offset + interrupt.every;
+                !(interrupt.keep_going)(self.offset)
+            }
+            _ => false
+        };
+        if cancel {
+            // pretend the input ended, so that parsing stops right here
+            self.cancelled = true;
+            self.current_char = Err(IoError { kind: EndOfFile, desc: "cancelled", detail: None });
+        }
+    }
+
+    fn get_line(&self) -> uint { self.line }
+
+    fn pos(&self) -> span::Pos {
+        span::Pos { line: self.line, col: self.col, offset: self.offset }
+    }
+
+    // Records `err` as the reason parsing fails, unless a reason was found
+    // already. Errors are detected innermost first, so the first one is the
+    // most specific.
+    fn fail(&mut self, err: Error) {
+        if self.error.is_none() {
+            self.error = Some(err);
+        }
+    }
+
+    // Takes the reason the construct at hand failed, as recorded by `fail`.
+    // If none was, fails on the current character where `expected` was
+    // wanted.
+    fn failure(&mut self, expected: &'static str) -> Error {
+        if self.error.is_none() { self.unexpected(expected) }
+        self.error.take().unwrap()
+    }
+
+    // Fails on the current character, or on the end of the input, where
+    // `expected` was wanted
+    fn unexpected(&mut self, expected: &'static str) {
+        let err = match self.ch() {
+            Some(c) => UnexpectedChar(c, expected, self.char_span(c)),
+            None => UnexpectedEof(expected, self.span_from(self.pos()))
+        };
+        self.fail(err);
+    }
+
+    // Fails with `LimitExceeded` at the current character
+    fn limit_exceeded(&mut self, what: &'static str) {
+        let span = match self.ch() {
+            Some(c) => self.char_span(c),
+            None => self.span_from(self.pos())
+        };
+        self.fail(LimitExceeded(what, span));
+    }
+
+    // Counts another key/value pair, failing if there are too many
+    fn count_key(&mut self) -> bool {
+        self.keys += 1;
+        match self.limits.max_keys {
+            Some(max) if self.keys > max => {
+                self.limit_exceeded("number of keys");
+                false
+            }
+            _ => true
+        }
+    }
+
+    // Counts another array element, failing if there are too many
+    fn count_array_element(&mut self) -> bool {
+        self.array_elements += 1;
+        match self.limits.max_array_elements {
+            Some(max) if self.array_elements > max => {
+                self.limit_exceeded("number of array elements");
+                false
+            }
+            _ => true
+        }
+    }
+
+    // Counts another section header, failing if there are too many
+    fn count_table(&mut self) -> bool {
+        self.tables += 1;
+        match self.limits.max_tables {
+            Some(max) if self.tables > max => {
+                self.limit_exceeded("number of tables");
+                false
+            }
+            _ => true
+        }
+    }
+
+    fn expected_equals(&mut self) {
+        let span = match self.ch() {
+            Some(c) => self.char_span(c),
+            None => self.span_from(self.pos())
+        };
+        self.fail(ExpectedEquals(span));
+    }
+
+    // Records the span from `start` of the array element or inline table
+    // member at `nested_path`, which it leaves
+    fn leave_nested(&mut self, start: span::Pos) {
+        let span = self.span_from(start);
+        self.nested_spans.push((self.nested_path.clone(), span));
+        self.nested_path.pop();
+    }
+
+    // The span from `start` up to the current character
+    fn span_from(&self, start: span::Pos) -> span::Span {
+        span::Span { start: start, end: self.pos() }
+    }
+
+    // The span of `c`, the current character
+    fn char_span(&self, c: char) -> span::Span {
+        let pos = self.pos();
+        span::Span { start: pos, end: span::Pos { col: pos.col + 1, offset: pos.offset + c.len_utf8_bytes(), ..pos } }
+    }
+
+    fn ch(&self) -> Option<char> {
+        match self.current_char {
+            Ok(c) => Some(c),
+            Err(_) => None
+        }
+    }
+
+    /// Returns `true` if the input is exhausted (due to EOF or an error)
+    fn eos(&self) -> bool {
+        return self.current_char.is_err();
     }
 
     /// Returns any error encountered by the parser. Returns `None` for EndOfFile.
@@ -476,49 +1778,62 @@ impl<'a, BUF: Buffer> Parser<'a, BUF> {
         }
     }
 
-    fn read_two_digits(&mut self) -> Option<u8> {
-        let d1 = self.read_digit(10);
-        let d2 = self.read_digit(10);
-        match (d1, d2) {
-            (Some(d1), Some(d2)) => Some(d1*10+d2),
-            _ => None
+    // Reads the digit following a `_` separator, which must be a digit
+    fn read_separated_digit(&mut self) -> Option<Option<u8>> {
+        if !self.allows(V0_4) || !self.advance_if('_') { return Some(self.read_digit(10)) }
+        match self.read_digit(10) {
+            Some(n) => Some(Some(n)),
+            None => None
         }
     }
 
-    fn read_digits(&mut self) -> (Option<u64>, uint) {
+    // Reads a decimal number, saturating at `u64::MAX`. Digits may be
+    // separated by single underscores, like `1_000`. Returns the number, the
+    // count of digits and whether underscores were used. The number is
+    // `None` if there is no digit, or if an underscore is not followed by a
+    // digit.
+    fn read_digits(&mut self) -> (Option<u64>, uint, bool) {
         let mut num: u64;
         match self.read_digit(10) {
             Some(n) => { num = n as u64; }
-            None => { return (None, 0) }
+            None => { return (None, 0, false) }
         }
         let mut ndigits = 1;
+        let mut separated = false;
         loop {
-            match self.read_digit(10) {
-                Some(n) => {
-                    // XXX: check range
-                    num = num * 10 + (n as u64);
+            if self.ch() == Some('_') { separated = true }
+            match self.read_separated_digit() {
+                Some(Some(n)) => {
+                    num = num.checked_mul(&10).and_then(|num| num.checked_add(&(n as u64)))
+                             .unwrap_or(std::u64::MAX);
                     ndigits += 1;
                 }
+                Some(None) => {
+                    return (Some(num), ndigits, separated)
+                }
                 None => {
-                    return (Some(num), ndigits)
+                    return (None, ndigits, separated)
                 }
             }
         }
     }
 
-    // allows a single "."
-    fn read_float_mantissa(&mut self) -> f64 {
+    // allows a single "." and underscores between digits
+    fn read_float_mantissa(&mut self) -> Option<f64> {
         let mut num: f64 = 0.0;
         let mut div: f64 = 10.0;
 
         loop {
-            match self.read_digit(10) {
-                Some(n) => {
+            match self.read_separated_digit() {
+                Some(Some(n)) => {
                     num = num + (n as f64)/div;
                     div = div * 10.0;
                 }
+                Some(None) => {
+                    return Some(num);
+                }
                 None => {
-                    return num;
+                    return None;
                 }
             }
         }
@@ -528,40 +1843,110 @@ impl<'a, BUF: Buffer> Parser<'a, BUF> {
         if self.ch().is_none() { return NoValue }
         match self.ch().unwrap() {
             '0' .. '9' => {
-                let num = self.read_float_mantissa();
-                let num = (n as f64) + num;
-                Float(num * mul)
+                match self.read_float_mantissa() {
+                    Some(num) => Float(((n as f64) + num) * mul),
+                    None => NoValue
+                }
             }
             _ => NoValue
         }
     }
 
+    fn invalid_datetime(&mut self, start: span::Pos) -> Value {
+        let err = InvalidDatetime(self.span_from(start));
+        self.fail(err);
+        NoValue
+    }
+
+    fn read_word(&mut self) -> Option<String> {
+        self.read_token(|ch| ch.is_alphanumeric() || ch == '_')
+    }
+
+    // Parses a value, recording why if it fails
     fn parse_value(&mut self) -> Value {
         self.skip_whitespaces_and_comments();
+        let start = self.pos();
+        let first = self.ch();
+        let nested = first == Some('[') || first == Some('{');
+        if nested {
+            if self.nesting == self.max_nesting {
+                let err = NestingTooDeep(self.span_from(start));
+                self.fail(err);
+                return NoValue
+            }
+            self.nesting += 1;
+        }
+        let val = self.parse_value_at(start);
+        if nested { self.nesting -= 1 }
+        match (&val, first) {
+            (&NoValue, Some('+')) | (&NoValue, Some('-')) | (&NoValue, Some('0' .. '9')) => {
+                let err = InvalidNumber(self.span_from(start));
+                self.fail(err);
+            }
+            (&NoValue, _) => self.unexpected("a value"),
+            _ => {}
+        }
+        val
+    }
 
+    fn parse_value_at(&mut self, start: span::Pos) -> Value {
         if self.eos() { return NoValue }
         match self.ch().unwrap() {
+            '+' if self.allows(V0_4) => {
+                self.advance();
+                match self.ch() {
+                    Some('0' .. '9') => {
+                        // like an unsigned number, but not a datetime
+                        match self.parse_value() {
+                            val @ Integer(_) | val @ Float(_) => return val,
+                            _ => return NoValue
+                        }
+                    }
+                    _ if self.allows(V1_0) => {
+                        return match self.read_word() {
+                            Some(word) => special_float(word.as_slice(), 1.0).unwrap_or(NoValue),
+                            None => NoValue
+                        };
+                    }
+                    _ => return NoValue
+                }
+            }
             '-' => {
                 self.advance();
+                match self.ch() {
+                    Some('i') | Some('n') if self.allows(V1_0) => {
+                        return match self.read_word() {
+                            Some(word) => special_float(word.as_slice(), -1.0).unwrap_or(NoValue),
+                            None => NoValue
+                        };
+                    }
+                    _ => {}
+                }
                 match self.read_digits() {
-                    (Some(n), _) => {
+                    (Some(n), _, _) => {
                         if self.ch() == Some('.') {
                             // floating point
                             self.advance();
                             return self.parse_float_rest(n, -1.0);
                         }
+                        else if n > std::i64::MAX as u64 + 1 {
+                            let err = IntegerOverflow(self.span_from(start));
+                            self.fail(err);
+                            return NoValue;
+                        }
                         else {
-                            return NegInt(n);
+                            // -(2^63) itself has no positive counterpart
+                            return Integer(if n == std::i64::MAX as u64 + 1 { std::i64::MIN } else { -(n as i64) });
                         }
                     }
-                    (None, _) => {
+                    (None, _, _) => {
                         return NoValue
                     }
                 }
             }
             '0' .. '9' => {
                 match self.read_digits() {
-                    (Some(n), ndigits) => {
+                    (Some(n), ndigits, separated) => {
                         match self.ch() {
                             Some('.') => {
                                 // floating point
@@ -569,102 +1954,107 @@ impl<'a, BUF: Buffer> Parser<'a, BUF> {
                                 return self.parse_float_rest(n, 1.0);
                             }
                             Some('-') => {
-                                if ndigits != 4 {
-                                    debug!("Invalid Datetime");
-                                    return NoValue;
-                                }
-                                self.advance();
-
-                                let year = n;
-
-                                let month = self.read_two_digits();
-                                if month.is_none() || !self.advance_if('-') {
-                                    debug!("Invalid Datetime");
-                                    return NoValue;
-                                }
-
-                                let day = self.read_two_digits();
-                                if day.is_none() || !self.advance_if('T'){
-                                    debug!("Invalid Datetime");
-                                    return NoValue;
-                                }
-
-                                let hour = self.read_two_digits();
-                                if hour.is_none() || !self.advance_if(':') {
-                                    debug!("Invalid Datetime");
-                                    return NoValue;
-                                }
-
-                                let min = self.read_two_digits();
-                                if min.is_none() || !self.advance_if(':') {
-                                    debug!("Invalid Datetime");
-                                    return NoValue;
+                                if ndigits != 4 || separated {
+                                    return self.invalid_datetime(start);
                                 }
-
-                                let sec = self.read_two_digits();
-                                if sec.is_none() || !self.advance_if('Z') {
-                                    debug!("Invalid Datetime");
-                                    return NoValue;
-                                }
-
-                                match (year, month, day, hour, min, sec) {
-                                    (y, Some(m), Some(d),
-                                     Some(h), Some(min), Some(s))
-                                    if m > 0 && m <= 12 && d > 0 && d <= 31 &&
-                                       h <= 24 && min <= 60 && s <= 60 => {
-                                        return Datetime(y as u16,m,d,h,min,s)
-                                    }
-                                    _ => {
-                                        debug!("Invalid Datetime range");
-                                        return NoValue;
+                                // the rest of the literal is checked by the
+                                // parser of `datetime::Datetime`
+                                let rest = match self.read_token(|ch| {
+                                    match ch {
+                                        '0' .. '9' | '-' | '+' | ':' | '.' | 'T' | 'Z' => true,
+                                        _ => false
                                     }
+                                }) {
+                                    Some(rest) => rest,
+                                    None => return NoValue
+                                };
+                                let literal = format!("{:04u}{}", n, rest);
+                                let dt: datetime::Datetime = match from_str(literal.as_slice()) {
+                                    Some(dt) => dt,
+                                    None => return self.invalid_datetime(start)
+                                };
+                                // fractional seconds and offsets other than
+                                // `Z` came with TOML 0.4, local datetimes
+                                // with 1.0
+                                let fraction = rest.as_slice().contains_char('.');
+                                let utc = rest.as_slice().ends_with("Z");
+                                let version = if dt.offset().is_none() {
+                                    V1_0
+                                } else if fraction || !utc {
+                                    V0_4
+                                } else {
+                                    V0_2
+                                };
+                                if !self.allows(version) {
+                                    return self.invalid_datetime(start);
                                 }
+                                return Datetime(dt)
+                            }
+                            _ if n > std::i64::MAX as u64 => {
+                                let err = IntegerOverflow(self.span_from(start));
+                                self.fail(err);
+                                return NoValue
                             }
                             _ => {
-                                return PosInt(n)
+                                return Integer(n as i64)
                             }
                         }
                     }
-                    (None, _) => {
-                        assert!(false);
+                    (None, _, _) => {
+                        // an underscore not followed by a digit
                         return NoValue
                     }
                 }
             }
-            't' => {
-                self.advance();
-                if self.advance_if('r') &&
-                   self.advance_if('u') &&
-                   self.advance_if('e') {
-                    return Boolean(true)
-                } else {
-                    return NoValue
-                }
-
-        }
-            'f' => {
-                self.advance();
-                if self.advance_if('a') &&
-                   self.advance_if('l') &&
-                   self.advance_if('s') &&
-                   self.advance_if('e') {
-                    return Boolean(false)
-                } else {
-                    return NoValue
+            'a' .. 'z' | 'A' .. 'Z' => {
+                let word = match self.read_word() {
+                    Some(word) => word,
+                    None => return NoValue
+                };
+                match word.as_slice() {
+                    "true" => return Boolean(true),
+                    "false" => return Boolean(false),
+                    "inf" | "nan" if self.allows(V1_0) => return special_float(word.as_slice(), 1.0).unwrap(),
+                    "inf" | "nan" => {
+                        let what = if word.as_slice() == "inf" { "inf" } else { "nan" };
+                        let err = UnsupportedInVersion(what, V1_0, self.span_from(start));
+                        self.fail(err);
+                        return NoValue
+                    }
+                    _ => {
+                        let keywords = ["true", "false", "inf", "nan"];
+                        // `inf` and `nan` only if they would be accepted
+                        let known = keywords.slice_to(if self.allows(V1_0) { 4 } else { 2 });
+                        let suggestion = nearest_match(word.as_slice(), known.iter().map(|s| *s));
+                        self.fail(UnknownValue(word.clone(), suggestion.map(|s| s.to_string())));
+                        return NoValue
+                    }
                 }
             }
             '[' => {
                 self.advance();
                 let mut arr = vec!();
                 loop {
-                    match self.parse_value() {
+                    // the array may be empty, or end with a trailing comma
+                    self.skip_whitespaces_and_comments();
+                    if self.ch() == Some(']') { break }
+                    if !self.count_array_element() { return NoValue }
+
+                    let elm_start = self.pos();
+                    self.nested_path.push(arr.len().to_str());
+                    let val = self.parse_value();
+                    self.leave_nested(elm_start);
+                    match val {
                         NoValue => {
-                            break;
+                            return NoValue;
                         }
                         val => {
-                            if !arr.is_empty() {
+                            // arrays of mixed types are only valid as of TOML 1.0
+                            if !arr.is_empty() && self.version != Some(V1_0) {
                                 if !have_equiv_types(arr.get(0), &val) {
                                     debug!("Incompatible element types in array");
+                                    let err = MixedArrayTypes(self.span_from(start));
+                                    self.fail(err);
                                     return NoValue;
                                 }
                             }
@@ -679,6 +2069,7 @@ impl<'a, BUF: Buffer> Parser<'a, BUF> {
                 if self.advance_if(']') {
                     return Array(arr);
                 } else {
+                    self.unexpected("`]` to close array");
                     return NoValue;
                 }
             }
@@ -688,58 +2079,292 @@ impl<'a, BUF: Buffer> Parser<'a, BUF> {
                     None => { return NoValue }
                 }
             }
-            _ => { return NoValue }
+            '\'' if self.allows(V0_4) => {
+                match self.parse_literal_string() {
+                    Some(str) => { return String(str) }
+                    None => { return NoValue }
+                }
+            }
+            '{' if self.allows(V0_4) => {
+                self.advance();
+                return self.parse_inline_table();
+            }
+            '@' if self.extensions.is_some() => {
+                self.advance();
+                let name = match self.read_token(|ch| ch.is_alphanumeric() || ch == '_' || ch == '-') {
+                    Some(name) => name,
+                    None => return NoValue
+                };
+                // the argument is on the same line, and may be empty
+                while self.advance_if(' ') || self.advance_if('\t') {}
+                let arg = match self.read_token(|ch| {
+                    match ch {
+                        '\n' | '#' | ',' | ']' | '}' => false,
+                        _ => true
+                    }
+                }) {
+                    Some(arg) => arg,
+                    None => return NoValue
+                };
+                let val = match self.extensions {
+                    Some(ref mut extensions) => match extensions.handlers.find_mut(&name) {
+                        Some(handler) => (**handler)(arg.as_slice().trim()),
+                        None => None
+                    },
+                    None => None
+                };
+                match val {
+                    Some(val) => { return val }
+                    None => {
+                        debug!("Invalid extension value @{} {}", name, arg);
+                        let err = UnexpectedChar('@', "a known extension value", self.span_from(start));
+                        self.fail(err);
+                        return NoValue
+                    }
+                }
+            }
+            _ => { return NoValue }
+        }
+    }
+
+    fn parse_string(&mut self) -> Option<String> {
+        let start = self.pos();
+        let res = self.read_basic_string();
+        self.check_terminated(start, res)
+    }
+
+    // Reports a string which fails to parse at the end of a line, or of the
+    // input, as unterminated
+    fn check_terminated(&mut self, start: span::Pos, res: Option<String>) -> Option<String> {
+        if res.is_none() && (self.eos() || self.ch() == Some('\n')) {
+            let err = UnterminatedString(self.span_from(start));
+            self.fail(err);
+        }
+        res
+    }
+
+    fn read_basic_string(&mut self) -> Option<String> {
+        if !self.advance_if('"') { return None }
+        if self.advance_if('"') {
+            if self.allows(V0_4) && self.advance_if('"') { return self.parse_multiline_string() }
+            // just an empty string
+            return Some(String::new());
+        }
+
+        let mut str = String::new();
+        loop {
+            if self.ch().is_none() { return None }
+            if !self.check_string_len(str.len()) { return None }
+            match self.ch().unwrap() {
+                '\n' => { return None }
+                c if !self.check_string_char(c) => { return None }
+                '\\' => {
+                    if !self.parse_escape(&mut str) { return None }
+                }
+                '"' => {
+                    self.advance();
+                    return Some(str);
+                }
+                c => {
+                    str.push_char(c);
+                    self.advance();
+                }
+            }
+        }
+    }
+
+    // Fails if a string of `len` bytes exceeds the limit
+    fn check_string_len(&mut self, len: uint) -> bool {
+        self.check_len(len, "string length")
+    }
+
+    // Fails if `what`, of `len` bytes, exceeds the limit on string length
+    fn check_len(&mut self, len: uint, what: &'static str) -> bool {
+        match self.limits.max_string_len {
+            Some(max) if len > max => {
+                self.limit_exceeded(what);
+                false
+            }
+            _ => true
+        }
+    }
+
+    // Rejects control characters other than tab and newline, which have to
+    // be escaped within strings
+    fn check_string_char(&mut self, c: char) -> bool {
+        if (c < ' ' && c != '\t' && c != '\n') || c == '\u007F' {
+            let err = ControlCharacter(c as u32, self.char_span(c));
+            self.fail(err);
+            return false;
+        }
+        true
+    }
+
+    // Parses the rest of a `"""` string, after the opening delimiter
+    fn parse_multiline_string(&mut self) -> Option<String> {
+        // a newline right after the delimiter is not part of the string
+        self.advance_if('\n');
+
+        let mut str = String::new();
+        loop {
+            if self.ch().is_none() { return None }
+            if !self.check_string_len(str.len()) { return None }
+            match self.ch().unwrap() {
+                c if !self.check_string_char(c) => { return None }
+                '\\' => {
+                    self.advance();
+                    match self.ch() {
+                        // a backslash at the end of a line trims all
+                        // whitespace up to the next non-whitespace character
+                        Some(' ') | Some('\t') | Some('\n') => {
+                            while self.advance_if(' ') || self.advance_if('\t') {}
+                            if self.ch() != Some('\n') {
+                                self.unexpected("a newline after a line ending backslash");
+                                return None
+                            }
+                            self.skip_whitespaces();
+                        }
+                        _ => {
+                            if !self.parse_escape_char(&mut str) { return None }
+                        }
+                    }
+                }
+                '"' => {
+                    let quotes_start = self.pos();
+                    let mut quotes = 0u;
+                    while self.advance_if('"') { quotes += 1 }
+                    if quotes >= 3 {
+                        // up to two quotes may directly precede the delimiter
+                        if quotes > 5 {
+                            let err = UnexpectedChar('"', "at most five quotes to close string", self.span_from(quotes_start));
+                            self.fail(err);
+                            return None
+                        }
+                        for _ in range(3, quotes) { str.push_char('"') }
+                        return Some(str);
+                    }
+                    for _ in range(0, quotes) { str.push_char('"') }
+                }
+                c => {
+                    str.push_char(c);
+                    self.advance();
+                }
+            }
+        }
+    }
+
+    // Parses the rest of an inline table `{ key = val, ... }`, after the
+    // opening brace. Duplicate keys are rejected by a ValueBuilder. The
+    // table has to be on a single line, only its values (like arrays) may
+    // span lines.
+    fn parse_inline_table(&mut self) -> Value {
+        let mut builder = ValueBuilder::new();
+        if !self.skip_inline_spaces() { return NoValue }
+        if !self.advance_if('}') {
+            loop {
+                if !self.skip_inline_spaces() { return NoValue }
+                if !self.count_key() { return NoValue }
+                let key = match self.parse_key() {
+                    Some(key) => key,
+                    None => {
+                        self.unexpected("a key in inline table");
+                        return NoValue
+                    }
+                };
+
+                if !self.skip_inline_spaces() { return NoValue }
+                if !self.advance_if('=') {
+                    self.expected_equals();
+                    return NoValue
+                }
+
+                if !self.skip_inline_spaces() { return NoValue }
+                let val_start = self.pos();
+                self.nested_path.push(key.clone());
+                let val = self.parse_value();
+                self.leave_nested(val_start);
+                match val {
+                    NoValue => { return NoValue }
+                    val => {
+                        if !builder.pair(key, val) {
+                            match builder.take_error() {
+                                Some(err) => self.fail(err),
+                                None => {}
+                            }
+                            return NoValue
+                        }
+                    }
+                }
+
+                if !self.skip_inline_spaces() { return NoValue }
+                if self.advance_if('}') { break }
+                if !self.advance_if(',') {
+                    self.unexpected("`,` or `}` in inline table");
+                    return NoValue
+                }
+            }
+        }
+        Table(builder.into_table())
+    }
+
+    // Skips spaces and tabs within an inline table. Fails on a newline or a
+    // comment, which would end the line.
+    fn skip_inline_spaces(&mut self) -> bool {
+        while self.advance_if(' ') || self.advance_if('\t') {}
+        match self.ch() {
+            Some('\n') | Some('#') => {
+                self.unexpected("inline table to end on the same line");
+                false
+            }
+            _ => true
         }
     }
 
-    fn parse_string(&mut self) -> Option<String> {
-        if !self.advance_if('"') { return None }
+    // Parses a `'literal'` or `'''multi-line literal'''` string, which
+    // have no escape sequences
+    fn parse_literal_string(&mut self) -> Option<String> {
+        let start = self.pos();
+        let res = self.read_literal_string();
+        self.check_terminated(start, res)
+    }
+
+    fn read_literal_string(&mut self) -> Option<String> {
+        if !self.advance_if('\'') { return None }
+        let multiline = if self.advance_if('\'') {
+            if !self.advance_if('\'') { return Some(String::new()) }
+            // a newline right after the delimiter is not part of the string
+            self.advance_if('\n');
+            true
+        } else {
+            false
+        };
 
         let mut str = String::new();
         loop {
             if self.ch().is_none() { return None }
+            if !self.check_string_len(str.len()) { return None }
             match self.ch().unwrap() {
-                '\r' | '\n' | '\u000C' | '\u0008' => { return None }
-                '\\' => {
+                '\n' if !multiline => { return None }
+                c if !self.check_string_char(c) => { return None }
+                '\'' if !multiline => {
                     self.advance();
-                    if self.ch().is_none() { return None }
-                    match self.ch().unwrap() {
-                        'b' => { str.push_char('\u0008'); self.advance() },
-                        't' => { str.push_char('\t'); self.advance() },
-                        'n' => { str.push_char('\n'); self.advance() },
-                        'f' => { str.push_char('\u000C'); self.advance() },
-                        'r' => { str.push_char('\r'); self.advance() },
-                        '"' => { str.push_char('"'); self.advance() },
-                        '/' => { str.push_char('/'); self.advance() },
-                        '\\' => { str.push_char('\\'); self.advance() },
-                        'u' => {
-                            self.advance();
-                            let d1 = self.read_digit(16);
-                            let d2 = self.read_digit(16);
-                            let d3 = self.read_digit(16);
-                            let d4 = self.read_digit(16);
-                            match (d1, d2, d3, d4) {
-                                (Some(d1), Some(d2), Some(d3), Some(d4)) => {
-                                    // XXX: how to construct an UTF character
-                                    let ch = (((((d1 as u32 << 4) | d2 as u32) << 4) | d3 as u32) << 4) | d4 as u32;
-                                    match char::from_u32(ch) {
-                                        Some(ch) => {
-                                            str.push_char(ch);
-                                        }
-                                        None => {
-                                            return None;
-                                        }
-                                    }
-                                }
-                                _ => return None
-                            }
+                    return Some(str);
+                }
+                '\'' => {
+                    let quotes_start = self.pos();
+                    let mut quotes = 0u;
+                    while self.advance_if('\'') { quotes += 1 }
+                    if quotes >= 3 {
+                        // up to two quotes may directly precede the delimiter
+                        if quotes > 5 {
+                            let err = UnexpectedChar('\'', "at most five quotes to close string", self.span_from(quotes_start));
+                            self.fail(err);
+                            return None
                         }
-                        _ => { return None }
+                        for _ in range(3, quotes) { str.push_char('\'') }
+                        return Some(str);
                     }
-                }
-                '"' => {
-                    self.advance();
-                    return Some(str);
+                    for _ in range(0, quotes) { str.push_char('\'') }
                 }
                 c => {
                     str.push_char(c);
@@ -749,7 +2374,77 @@ impl<'a, BUF: Buffer> Parser<'a, BUF> {
         }
     }
 
-    fn read_token(&mut self, f: |char| -> bool) -> String {
+    // Parses an escape sequence starting at the current backslash
+    fn parse_escape(&mut self, str: &mut String) -> bool {
+        if !self.advance_if('\\') { return false }
+        self.parse_escape_char(str)
+    }
+
+    // Parses the character of an escape sequence following the backslash
+    fn parse_escape_char(&mut self, str: &mut String) -> bool {
+        if self.ch().is_none() { return false }
+        // the escape starts at the backslash
+        let start = span::Pos { col: self.col - 1, offset: self.offset - 1, ..self.pos() };
+        match self.ch().unwrap() {
+            'b' => { str.push_char('\u0008'); self.advance() },
+            't' => { str.push_char('\t'); self.advance() },
+            'n' => { str.push_char('\n'); self.advance() },
+            'f' => { str.push_char('\u000C'); self.advance() },
+            'r' => { str.push_char('\r'); self.advance() },
+            '"' => { str.push_char('"'); self.advance() },
+            '/' => { str.push_char('/'); self.advance() },
+            '\\' => { str.push_char('\\'); self.advance() },
+            'u' | 'U' if self.ch() == Some('u') || self.allows(V0_4) => {
+                // `\uXXXX` or `\UXXXXXXXX`
+                let c = self.ch().unwrap();
+                let ndigits = if c == 'u' { 4 } else { 8 };
+                self.advance();
+                let code = match self.read_hex_digits(ndigits) {
+                    Some(code) => code,
+                    None => {
+                        if !self.eos() {
+                            let err = UnknownEscape(c, self.span_from(start));
+                            self.fail(err);
+                        }
+                        return false
+                    }
+                };
+                match char::from_u32(code) {
+                    Some(ch) => {
+                        str.push_char(ch);
+                    }
+                    None => {
+                        let err = InvalidEscape(code, self.span_from(start));
+                        self.fail(err);
+                        return false;
+                    }
+                }
+            }
+            c => {
+                self.advance();
+                let err = UnknownEscape(c, self.span_from(start));
+                self.fail(err);
+                return false
+            }
+        }
+        true
+    }
+
+    // Reads exactly `n` hexadecimal digits
+    fn read_hex_digits(&mut self, n: uint) -> Option<u32> {
+        let mut num = 0u32;
+        for _ in range(0, n) {
+            match self.read_digit(16) {
+                Some(d) => num = (num << 4) | d as u32,
+                None => return None
+            }
+        }
+        Some(num)
+    }
+
+    // Reads the characters accepted by `f`. Fails if the token exceeds the
+    // limit on string length.
+    fn read_token(&mut self, f: |char| -> bool) -> Option<String> {
         let mut token = String::new();
         loop {
             match self.ch() {
@@ -759,30 +2454,90 @@ impl<'a, BUF: Buffer> Parser<'a, BUF> {
                 }
                 None => { break }
             }
+            if !self.check_len(token.len(), "length of key or word") { return None }
             self.advance();
         }
 
-        return token;
+        return Some(token);
     }
 
-    fn parse_section_identifier(&mut self) -> String {
-        self.read_token(|ch| {
-            match ch {
-                '\t' | '\n' | '\r' | '[' | ']' => false,
-                _ => true
+    // Skips the characters accepted by `f`
+    fn skip_while(&mut self, f: |char| -> bool) {
+        loop {
+            match self.ch() {
+                Some(ch) if f(ch) => self.advance(),
+                _ => break
             }
-        })
+        }
+    }
+
+    // Parses the dotted keys of a section header, like `[ a . "b.c" ]`.
+    // Keys may be quoted, so that they can contain dots or brackets.
+    // Whitespace around keys is ignored, but a bare key may contain spaces,
+    // like `[valid key]`. Empty bare keys are not allowed.
+    fn parse_section_path(&mut self) -> Option<Vec<String>> {
+        let mut path = vec!();
+        loop {
+            while self.advance_if(' ') || self.advance_if('\t') {}
+            let key = match self.ch() {
+                Some('"') | Some('\'') if self.allows(V0_4) => {
+                    match self.parse_quoted_key() {
+                        Some(key) => key,
+                        None => return None
+                    }
+                }
+                _ => {
+                    let key = match self.read_token(|ch| {
+                        match ch {
+                            '\t' | '\n' | '[' | ']' | '.' => false,
+                            _ => true
+                        }
+                    }) {
+                        Some(key) => key,
+                        None => return None
+                    };
+                    let key = key.as_slice().trim_right_chars(' ');
+                    if key.is_empty() { return None }
+                    key.to_string()
+                }
+            };
+            while self.advance_if(' ') || self.advance_if('\t') {}
+            path.push(key);
+            if !self.advance_if('.') { return Some(path) }
+        }
+    }
+
+    // Parses a key written as a basic or literal string
+    fn parse_quoted_key(&mut self) -> Option<String> {
+        match self.ch() {
+            Some('"') => self.parse_string(),
+            Some('\'') => self.parse_literal_string(),
+            _ => None
+        }
+    }
+
+    // Parses the key of a key/value pair, which is either quoted, or
+    // extends up to whitespace, `=` or the `,` and `}` of an inline table
+    fn parse_key(&mut self) -> Option<String> {
+        match self.ch() {
+            Some('"') | Some('\'') if self.allows(V0_4) => self.parse_quoted_key(),
+            _ => {
+                let key = self.read_token(|ch| {
+                    match ch {
+                        ' ' | '\t' | '\n' | '=' | ',' | '}' => false,
+                        _ => true
+                    }
+                });
+                key.and_then(|key| if key.is_empty() { None } else { Some(key) })
+            }
+        }
     }
 
     fn skip_whitespaces(&mut self) {
         loop {
             match self.ch() {
-                Some(' ') | Some('\t') | Some('\r') => {
-                    self.advance();
-                }
-                Some('\n') => {
+                Some(' ') | Some('\t') | Some('\n') => {
                     self.advance();
-                    self.line += 1;
                 }
                 _ => { break }
             }
@@ -792,13 +2547,9 @@ impl<'a, BUF: Buffer> Parser<'a, BUF> {
     fn skip_whitespaces_and_comments(&mut self) {
         loop {
             match self.ch() {
-                Some(' ') | Some('\t') | Some('\r') => {
+                Some(' ') | Some('\t') | Some('\n') => {
                     self.advance();
                 }
-                Some('\n') => {
-                    self.advance();
-                    self.line += 1;
-                }
                 Some('#') => {
                     self.skip_comment();
                 }
@@ -807,9 +2558,26 @@ impl<'a, BUF: Buffer> Parser<'a, BUF> {
         }
     }
 
+    // Skips spaces and tabs, and a comment, up to and including the end of
+    // the line. Fails if anything else is found.
+    fn expect_end_of_line(&mut self) -> bool {
+        while self.advance_if(' ') || self.advance_if('\t') {}
+        match self.ch() {
+            None => true,
+            Some('#') => { self.skip_comment(); true }
+            Some('\n') => { self.advance(); true }
+            Some(_) => {
+                let start = self.pos();
+                self.skip_while(|ch| ch != '\n');
+                let err = ExpectedEndOfLine(self.span_from(start));
+                self.fail(err);
+                false
+            }
+        }
+    }
+
+    // Skips a comment, starting at its `#`, up to the end of the line
     fn skip_comment(&mut self) {
-        assert!(self.ch() == Some('#'));
-        // skip to end of line
         loop {
             self.advance();
             match self.ch() {
@@ -818,76 +2586,224 @@ impl<'a, BUF: Buffer> Parser<'a, BUF> {
                 _ => { /* skip */ }
             }
         }
-        self.line += 1;
         self.advance();
     }
 
+    // Skips everything up to the next section header, without building
+    // values. Only strings, comments and array brackets are tracked, so that
+    // a `[` within them is not taken for a header.
+    // It is called at the start of the line following a header.
+    fn skip_section_body(&mut self) {
+        let mut depth = 0u;
+        let mut line_start = true;
+        loop {
+            let ch = match self.ch() {
+                Some(ch) => ch,
+                None => return
+            };
+            match ch {
+                '[' if depth == 0 && line_start => return,
+                '[' => depth += 1,
+                ']' if depth > 0 => depth -= 1,
+                '"' => {
+                    // strings may span lines, so they need to be parsed
+                    self.parse_string();
+                    line_start = false;
+                    continue;
+                }
+                '\'' => {
+                    self.parse_literal_string();
+                    line_start = false;
+                    continue;
+                }
+                '#' => {
+                    self.skip_comment();
+                    line_start = true;
+                    continue;
+                }
+                _ => {}
+            }
+            line_start = match ch {
+                '\n' => true,
+                ' ' | '\t' => line_start,
+                _ => false
+            };
+            self.advance();
+        }
+    }
+
     fn parse<V: Visitor>(&mut self, visitor: &mut V) -> Result<(),Error> {
         loop {
+            // forget errors of constructs which were skipped, like strings
+            // in `skip_section_body`. An error which ended the input is kept.
+            if !self.eos() { self.error = None }
             self.skip_whitespaces_and_comments();
 
             if self.eos() {
                 return self.to_err().map_or(Ok(()), |e| Err(IOError(e)));
             }
 
-            match self.ch().unwrap() {
-                // section
-                '[' => {
-                    self.advance();
-                    let mut double_section = false;
-                    match self.ch() {
-                        Some('[') => {
-                            double_section = true;
-                            self.advance();
-                        }
-                        _ => {}
+            let header = self.ch() == Some('[');
+            match self.parse_construct(visitor) {
+                Ok(()) => {}
+                Err(e) => {
+                    if !self.recover || self.cancelled || self.eos() { return Err(e) }
+                    self.errors.push(e);
+                    // skip to the next construct. The body of a broken
+                    // section is skipped as a whole, as its pairs cannot be
+                    // placed.
+                    self.skip_while(|ch| ch != '\n');
+                    if header { self.skip_section_body() }
+                }
+            }
+        }
+    }
+
+    // Parses a section header, or a key/value pair
+    fn parse_construct<V: Visitor>(&mut self, visitor: &mut V) -> Result<(),Error> {
+        match self.ch().unwrap() {
+            // section
+            '[' => {
+                if !self.count_table() { return Err(self.failure("a section header")) }
+                let start = self.pos();
+                self.advance();
+                let mut double_section = false;
+                match self.ch() {
+                    Some('[') => {
+                        double_section = true;
+                        self.advance();
                     }
+                    _ => {}
+                }
 
-                    let section_name = self.parse_section_identifier();
-                    // don"t allow empty section names
-                    if section_name.is_empty() { return Err(ParseError) }
+                let section_path = match self.parse_section_path() {
+                    Some(path) => path,
+                    None => return Err(self.failure("a section name"))
+                };
 
-                    if !self.advance_if(']') { return Err(ParseError) }
-                    if double_section {
-                        if !self.advance_if(']') { return Err(ParseError) }
-                    }
+                if !self.advance_if(']') || (double_section && !self.advance_if(']')) {
+                    return Err(self.failure(if double_section { "`]]` to close section header" }
+                                            else { "`]` to close section header" }))
+                }
+                let end = self.pos();
+                if section_path.len() > self.max_nesting {
+                    return Err(NestingTooDeep(span::Span { start: start, end: end }))
+                }
+                if !self.expect_end_of_line() { return Err(self.failure("end of line")) }
 
-                    if !visitor.section(section_name, double_section) {
-                        return Err(ParseError)
+                let wanted = match self.only_sections {
+                    Some(sections) => {
+                        let top = section_path.get(0).as_slice();
+                        sections.iter().any(|s| *s == top)
                     }
+                    None => true
+                };
+                if !wanted {
+                    self.skip_section_body();
+                    return Ok(());
                 }
 
-                // identifier: anything else starts an idenfifier!
-                // NOTE that we do not allow '.' in identifiers!
-                _ => {
-                    let ident = self.read_token(|ch| {
-                        match ch {
-                            ' ' | '\t' | '\r' | '\n' | '=' => false,
-                            _ => true
+                self.section_depth = section_path.len();
+                match self.stats {
+                    Some(ref mut stats) => {
+                        stats.tables += 1;
+                        if self.section_depth > stats.max_depth {
+                            stats.max_depth = self.section_depth;
                         }
-                    });
+                    }
+                    None => {}
+                }
+
+                let span = span::Span { start: start, end: end };
+                if !visitor.section_at(section_path, double_section, span) {
+                    return Err(visitor.take_error().unwrap_or(Rejected(span)))
+                }
+                if self.skip_bodies {
+                    self.skip_section_body();
+                }
+            }
 
+            // identifier: anything else starts an idenfifier, which
+            // may also be a quoted string.
+            // NOTE that we do not allow '.' in bare identifiers!
+            _ => {
+                if !self.count_key() { return Err(self.failure("a key")) }
+                let quoted = self.ch() == Some('"') || self.ch() == Some('\'');
+                let mut ident = match self.parse_key() {
+                    Some(key) => key,
+                    None => return Err(self.failure("a key or section header"))
+                };
+
+                // `key += [...]`, the `+` may have been read as part of the
+                // key. `+=` is a single token, so `=` has to follow directly.
+                let append = if self.allow_append && !quoted && ident.as_slice().ends_with("+") {
+                    ident.pop_char();
+                    true
+                } else {
                     self.skip_whitespaces();
+                    self.allow_append && self.advance_if('+')
+                };
 
-                    if !self.advance_if('=') { return Err(ParseError) } // assign wanted
+                if !self.advance_if('=') {
+                    self.expected_equals();
+                    return Err(self.failure("`=` after key"))
+                }
 
-                    match self.parse_value() {
-                        NoValue => { return Err(ParseError); }
-                        val => {
-                            if !visitor.pair(ident, val) { return Err(ParseError); }
+                self.skip_whitespaces_and_comments();
+                let start = self.pos();
+                self.nested_path.clear();
+                self.nested_spans.clear();
+                match self.parse_value() {
+                    NoValue => return Err(self.failure("a value")),
+                    val => {
+                        let val = match val {
+                            Array(items) if append => Append(items),
+                            _ if append => return Err(AppendNotArray(ident, Some(self.span_from(start)))),
+                            val => val
+                        };
+                        match self.stats {
+                            Some(ref mut stats) => stats.add_pair(&val, self.section_depth),
+                            None => {}
+                        }
+                        let span = span::Span { start: start, end: self.pos() };
+                        for &(ref path, nested) in self.nested_spans.iter() {
+                            visitor.nested_at(ident.as_slice(), path.as_slice(), nested);
+                        }
+                        if !visitor.pair_at(ident, val, span) {
+                            return Err(visitor.take_error().unwrap_or(Rejected(span)))
                         }
                     }
                 }
-            } /* end match */
-        }
+                if !self.expect_end_of_line() { return Err(self.failure("end of line")) }
+            }
+        } /* end match */
+        Ok(())
     }
 }
 
 
+/// Parses the file at `path`. Files ending in `.gz` are decompressed first.
+/// I/O errors are returned as `IOErrorInFile`.
 pub fn parse_from_path(path: &Path) -> Result<Value,Error> {
-    let file = File::open(path);
-    let mut rd = BufferedReader::new(file);
-    return parse_from_buffer(&mut rd);
+    let res = if path.extension_str() == Some("gz") {
+        match File::open(path) {
+            Ok(mut file) => parse_from_gzip_reader(&mut file),
+            Err(e) => Err(IOError(e))
+        }
+    } else {
+        let file = File::open(path);
+        let mut rd = BufferedReader::new(file);
+        parse_from_buffer(&mut rd)
+    };
+    res.map_err(|e| in_file(path, e))
+}
+
+// Attaches `path` to an I/O error
+fn in_file(path: &Path, err: Error) -> Error {
+    match err {
+        IOError(e) => IOErrorInFile(path.clone(), e),
+        err => err
+    }
 }
 
 pub fn parse_from_file(name: &str) -> Result<Value,Error> {
@@ -895,20 +2811,158 @@ pub fn parse_from_file(name: &str) -> Result<Value,Error> {
 }
 
 pub fn parse_from_buffer<BUF: Buffer>(rd: &mut BUF) -> Result<Value,Error> {
-    let mut ht = box HashMap::<String, Value>::new();
-    {
-        let mut builder = ValueBuilder::new(&mut ht);
-        let mut parser = Parser::new(rd);
+    let mut builder = ValueBuilder::new();
+    try!(parse_with_visitor(rd, &mut builder));
+    return Ok(builder.into_value());
+}
 
-        match parser.parse(&mut builder) {
-            Err(e) => {
-                debug!("Error in line: {}", parser.get_line());
-                return Err(e);
-            }
-            Ok(_) => ()
+/// Parses a document, passing its contents to `visitor` instead of building
+/// a `Value` tree.
+pub fn parse_with_visitor<BUF: Buffer, V: Visitor>(rd: &mut BUF, visitor: &mut V) -> Result<(),Error> {
+    let mut parser = Parser::new(rd);
+    run_parser(&mut parser, visitor)
+}
+
+/// Like `parse_from_buffer`, but configured by `options`. Spans, statistics
+/// and the errors recovered from are dropped, see
+/// `parse_from_buffer_detailed`.
+pub fn parse_from_buffer_with<'a, BUF: Buffer>(rd: &'a mut BUF, options: ParserOptions<'a>) -> Result<Value,Error> {
+    let mut builder = ValueBuilder::new();
+    let mut parser = Parser::with_options(rd, options);
+    try!(run_parser(&mut parser, &mut builder));
+    return Ok(builder.into_value());
+}
+
+/// Like `parse_from_buffer_with`, but also returns the spans, statistics and
+/// errors requested by `options`. With `ParserOptions::recover`, this never
+/// fails, but returns all errors in `ParseOutput::errors`.
+pub fn parse_from_buffer_detailed<'a, BUF: Buffer>(rd: &'a mut BUF, options: ParserOptions<'a>) -> Result<ParseOutput,Error> {
+    let record_spans = options.spans;
+    let mut parser = Parser::with_options(rd, options);
+    let (res, value, spans) = if record_spans {
+        let mut recorder = SpanRecorder::new(ValueBuilder::new());
+        let res = run_parser(&mut parser, &mut recorder);
+        let (builder, spans) = recorder.unwrap();
+        (res, builder.into_value(), Some(spans))
+    } else {
+        let mut builder = ValueBuilder::new();
+        let res = run_parser(&mut parser, &mut builder);
+        (res, builder.into_value(), None)
+    };
+    let mut errors = mem::replace(&mut parser.errors, vec!());
+    match res {
+        Err(e) => {
+            if !parser.recover { return Err(e) }
+            errors.push(e);
+        }
+        Ok(()) => {}
+    }
+    let stats = match parser.stats.take() {
+        Some(mut stats) => {
+            stats.bytes = parser.offset;
+            stats.lines = parser.line;
+            Some(stats)
+        }
+        None => None
+    };
+    Ok(ParseOutput { value: value, spans: spans, stats: stats, errors: errors })
+}
+
+/// Like `parse_from_buffer`, but calls `keep_going` with the number of bytes
+/// consumed so far each time another `every` bytes have been parsed. If it
+/// returns `false`, parsing is aborted with a `Cancelled` error.
+#[deprecated = "use `parse_from_buffer_with` and `ParserOptions::interrupt`"]
+pub fn parse_from_buffer_cancellable<BUF: Buffer>(rd: &mut BUF, every: uint,
+                                                  keep_going: |uint| -> bool) -> Result<Value,Error> {
+    parse_from_buffer_with(rd, ParserOptions::new().interrupt(every, keep_going))
+}
+
+/// Like `parse_from_buffer`, but also returns statistics about the document
+#[deprecated = "use `parse_from_buffer_detailed` and `ParserOptions::stats`"]
+pub fn parse_from_buffer_with_stats<BUF: Buffer>(rd: &mut BUF) -> Result<(Value, ParseStats),Error> {
+    let output = try!(parse_from_buffer_detailed(rd, ParserOptions::new().stats(true)));
+    Ok((output.value, output.stats.unwrap_or(Default::default())))
+}
+
+/// Like `parse_from_buffer`, but accepts the extension values handled by
+/// `extensions`, see `ParserOptions::extensions`.
+#[deprecated = "use `parse_from_buffer_with` and `ParserOptions::extensions`"]
+pub fn parse_from_buffer_with_extensions<BUF: Buffer>(rd: &mut BUF, extensions: &mut Extensions) -> Result<Value,Error> {
+    parse_from_buffer_with(rd, ParserOptions::new().extensions(extensions))
+}
+
+/// Like `parse_from_buffer`, but only parses the top-level sections named in
+/// `sections`, see `ParserOptions::sections`.
+#[deprecated = "use `parse_from_buffer_with` and `ParserOptions::sections`"]
+pub fn parse_from_buffer_sections<BUF: Buffer>(rd: &mut BUF, sections: &[&str]) -> Result<Value,Error> {
+    parse_from_buffer_with(rd, ParserOptions::new().sections(sections))
+}
+
+/// Like `parse_from_buffer`, but validates the document against `version`
+/// of the TOML specification, see `ParserOptions::version`.
+#[deprecated = "use `parse_from_buffer_with` and `ParserOptions::version`"]
+pub fn parse_from_buffer_with_version<BUF: Buffer>(rd: &mut BUF, version: TomlVersion) -> Result<Value,Error> {
+    parse_from_buffer_with(rd, ParserOptions::new().version(version))
+}
+
+/// Like `parse_from_buffer`, but limits the nesting depth, see
+/// `ParserOptions::max_depth`.
+#[deprecated = "use `parse_from_buffer_with` and `ParserOptions::max_depth`"]
+pub fn parse_from_buffer_with_max_depth<BUF: Buffer>(rd: &mut BUF, max_depth: uint) -> Result<Value,Error> {
+    parse_from_buffer_with(rd, ParserOptions::new().max_depth(max_depth))
+}
+
+/// Like `parse_from_buffer`, but limits the size of the document, see
+/// `ParserOptions::limits`.
+#[deprecated = "use `parse_from_buffer_with` and `ParserOptions::limits`"]
+pub fn parse_from_buffer_with_limits<BUF: Buffer>(rd: &mut BUF, limits: Limits) -> Result<Value,Error> {
+    parse_from_buffer_with(rd, ParserOptions::new().limits(limits))
+}
+
+/// Like `parse_from_buffer`, but additionally accepts `key += [...]`, see
+/// `ParserOptions::allow_append`.
+#[deprecated = "use `parse_from_buffer_with` and `ParserOptions::allow_append`"]
+pub fn parse_from_buffer_with_append<BUF: Buffer>(rd: &mut BUF) -> Result<Value,Error> {
+    parse_from_buffer_with(rd, ParserOptions::new().allow_append(true))
+}
+
+/// Like `parse_from_buffer`, but also returns the source spans of all
+/// section headers, key/value pairs, array elements and inline table members.
+#[deprecated = "use `parse_from_buffer_detailed` and `ParserOptions::spans`"]
+pub fn parse_from_buffer_spanned<BUF: Buffer>(rd: &mut BUF) -> Result<(Value, SpanMap),Error> {
+    let output = try!(parse_from_buffer_detailed(rd, ParserOptions::new().spans(true)));
+    Ok((output.value, output.spans.unwrap_or(SpanMap::new())))
+}
+
+/// Like `parse_from_buffer`, but does not stop at the first error, see
+/// `ParserOptions::recover`. Returns the document built from everything
+/// that did parse, along with all errors in document order.
+#[deprecated = "use `parse_from_buffer_detailed` and `ParserOptions::recover`"]
+pub fn parse_from_buffer_recovering<BUF: Buffer>(rd: &mut BUF) -> (Value, Vec<Error>) {
+    match parse_from_buffer_detailed(rd, ParserOptions::new().recover(true)) {
+        Ok(output) => (output.value, output.errors),
+        Err(e) => (TableInner(box HashMap::new()), vec!(e))
+    }
+}
+
+fn run_parser<BUF: Buffer, V: Visitor>(parser: &mut Parser<BUF>, visitor: &mut V) -> Result<(),Error> {
+    let res = parser.parse(visitor);
+    if parser.cancelled {
+        return Err(Cancelled);
+    }
+    if res.is_err() {
+        match visitor.take_error().or(parser.error.take()) {
+            Some(e) => return Err(e),
+            None => {}
+        }
+    }
+    match res {
+        Err(e) => {
+            debug!("Error in line: {}", parser.get_line());
+            return Err(e);
         }
+        Ok(_) => return Ok(())
     }
-    return Ok(TableInner(ht));
 }
 
 pub fn parse_from_bytes(bytes: &[u8]) -> Result<Value,Error> {
@@ -916,135 +2970,397 @@ pub fn parse_from_bytes(bytes: &[u8]) -> Result<Value,Error> {
     return parse_from_buffer(&mut rd);
 }
 
+pub fn parse_from_str(s: &str) -> Result<Value,Error> {
+    parse_from_bytes(s.as_bytes())
+}
+
+// Passes on key/value pairs, but rejects section headers
+struct PairsOnly {
+    builder: ValueBuilder,
+    // the opening bracket of the rejected section header
+    error: Option<Error>
+}
+
+impl Visitor for PairsOnly {
+    fn section(&mut self, path: Vec<String>, _is_array: bool) -> bool {
+        debug!("Section {} in fragment", path);
+        false
+    }
+
+    fn section_at(&mut self, path: Vec<String>, is_array: bool, span: Span) -> bool {
+        let end = span::Pos { col: span.start.col + 1, offset: span.start.offset + 1, ..span.start };
+        self.error = Some(UnexpectedChar('[', "a key/value pair", Span { start: span.start, end: end }));
+        self.section(path, is_array)
+    }
+
+    fn pair(&mut self, key: String, val: Value) -> bool {
+        self.builder.pair(key, val)
+    }
+
+    fn pair_at(&mut self, key: String, val: Value, span: Span) -> bool {
+        self.builder.pair_at(key, val, span)
+    }
+
+    fn take_error(&mut self) -> Option<Error> {
+        self.error.take().or(self.builder.take_error())
+    }
+}
+
+/// Parses a snippet of `key = value` lines, such as `"port = 80\nhost =
+/// \"localhost\""`, into a `Table`. Section headers are not allowed.
+pub fn parse_fragment(s: &str) -> Result<Value,Error> {
+    let mut rd = BufReader::new(s.as_bytes());
+    let mut visitor = PairsOnly { builder: ValueBuilder::new(), error: None };
+    try!(parse_with_visitor(&mut rd, &mut visitor));
+    Ok(Table(visitor.builder.into_table()))
+}
+
+/// Parses a document read from standard input. Read errors are returned as
+/// `IOError`.
+pub fn parse_from_stdin() -> Result<Value,Error> {
+    let mut rd = std::io::stdin();
+    return parse_from_buffer(&mut rd);
+}
+
+/// Parses `s` as a single value literal, like the right-hand side of a
+/// key/value pair, e.g. `[1, 2]` or `"text"`. Surrounding whitespace and a
+/// trailing comment are allowed.
+pub fn parse_value_str(s: &str) -> Result<Value,Error> {
+    let mut rd = BufReader::new(s.as_bytes());
+    let mut parser = Parser::new(&mut rd);
+    let value = parser.parse_value();
+    if value != NoValue {
+        parser.skip_whitespaces_and_comments();
+        if parser.eos() && parser.error.is_none() { return Ok(value) }
+        return Err(parser.failure("the end of the value"))
+    }
+    Err(parser.failure("a value"))
+}
+
+// Like `parse_value_str`, but without the reason of a failure
+fn value_from_str(s: &str) -> Option<Value> {
+    parse_value_str(s).ok()
+}
+
 enum State {
     No,
     Arr(MoveItems<Value>),
+    // the entries of a table decoded as a sequence, and one of them
+    Pairs(MoveItems<(String, Value)>),
+    Pair(String, Option<Value>),
     Tab(Box<HashMap<String, Value>>),
-    Map(MoveEntries<String, Value>)
+    // the entries left, and the one whose key was decoded last
+    Map(MoveEntries<String, Value>, Option<(String, Value)>)
 }
 
 pub struct Decoder {
     value: Value,
     state: State,
-    field: Option<String>
+    field: Option<String>,
+    // the spans of the document, and the dotted path of `value` in it
+    spans: Option<Rc<SpanMap>>,
+    path: String,
+    // flag names and their bits, by the dotted path of the field
+    flags: Rc<HashMap<String, Vec<(String, u64)>>>
 }
 
 impl Decoder {
     pub fn new(value: Value) -> Decoder {
-        Decoder { value: value, state: No, field: None }
+        Decoder { value: value, state: No, field: None, spans: None, path: String::new(),
+                  flags: Rc::new(HashMap::new()) }
+    }
+
+    /// Creates a decoder which knows the source spans of `value`, so that
+    /// fields of type `Spanned<T>` can be decoded.
+    pub fn new_spanned(value: Value, spans: SpanMap) -> Decoder {
+        let mut decoder = Decoder::new(value);
+        decoder.spans = Some(Rc::new(spans));
+        decoder
     }
-    fn new_state(state: State) -> Decoder {
-        Decoder { value: NoValue, state: state, field: None }
+
+    // A decoder for the same value, in another state
+    fn new_state(&self, state: State) -> Decoder {
+        Decoder { value: NoValue, state: state, field: None, spans: self.spans.clone(),
+                  path: self.path.clone(), flags: self.flags.clone() }
+    }
+
+    // The dotted path of the child at `segment`
+    fn child_path(&self, segment: &str) -> String {
+        join_path(self.path.as_slice(), segment)
+    }
+
+    // A decoder for the child `value` found at `segment`
+    fn new_child(&self, value: Value, segment: &str) -> Decoder {
+        let path = self.child_path(segment);
+        Decoder { value: value, state: No, field: None, spans: self.spans.clone(), path: path,
+                  flags: self.flags.clone() }
+    }
+
+    // A decoder for the value of struct field `name`
+    fn new_field(&self, value: Value, name: &str) -> Decoder {
+        let mut decoder = self.new_child(value, name);
+        decoder.field = Some(name.to_string());
+        decoder
+    }
+
+    /// Registers flag names for the integer field at the dotted `path`, e.g.
+    /// `server.mode`, which then decodes from an array of flag names, e.g.
+    /// `mode = ["read", "write"]`, into the bitwise or of the named bits.
+    pub fn register_flags(&mut self, path: &str, flags: &[(&str, u64)]) {
+        let mut all = (*self.flags).clone();
+        all.insert(path.to_string(), flags.iter().map(|&(name, bits)| (name.to_string(), bits)).collect());
+        self.flags = Rc::new(all);
+    }
+
+    // Combines an array of flag names into their bits, if flags are
+    // registered for the path of the field being decoded
+    fn read_flags(&self) -> Option<DecodeResult<u64>> {
+        let (flags, names) = match (&self.field, &self.value) {
+            (&Some(_), &Array(ref names)) => {
+                match self.flags.find(&self.path) {
+                    Some(flags) => (flags, names),
+                    None => return None
+                }
+            }
+            _ => return None
+        };
+        let mut bits = 0u64;
+        for (idx, name) in names.iter().enumerate() {
+            let path = self.child_path(idx.to_str().as_slice());
+            let name = match *name {
+                String(ref name) => name,
+                ref other => return Some(Err(TypeMismatch(path, "flag name", other.describe())))
+            };
+            match flags.iter().find(|&&(ref n, _)| n == name) {
+                Some(&(_, b)) => bits |= b,
+                None => {
+                    let suggestion = nearest_match(name.as_slice(), flags.iter().map(|&(ref n, _)| n.as_slice()));
+                    return Some(Err(UnknownFlag(path, name.clone(), suggestion.map(|s| s.to_string()))));
+                }
+            }
+        }
+        Some(Ok(bits))
+    }
+
+    /// Returns the source span of the value being decoded, if known
+    pub fn span(&self) -> Option<Span> {
+        self.spans.as_ref().and_then(|spans| spans.get(self.path.as_slice()))
+    }
+
+    // Fails with the type of the value found instead of `expected`. A
+    // missing value fails with `MissingField`, which `read_struct_field`
+    // completes with a suggestion.
+    fn wrong_type<T>(&self, expected: &'static str) -> DecodeResult<T> {
+        match (&self.value, &self.state) {
+            (&NoValue, &Pair(ref key, _)) => {
+                let path = self.child_path(key.as_slice());
+                Err(TypeMismatch(path, expected, "table entry".to_string()))
+            }
+            (&NoValue, _) => Err(MissingField(self.path.clone(), None)),
+            (value, _) => Err(TypeMismatch(self.path.clone(), expected, value.describe()))
+        }
+    }
+
+    // A decoder for element `idx` of the sequence being decoded
+    fn next_element(&self, state: &mut State, idx: uint) -> DecodeResult<Decoder> {
+        let segment = idx.to_str();
+        match *state {
+            Arr(ref mut a) => match a.next() {
+                Some(val) => Ok(self.new_child(val, segment.as_slice())),
+                // a tuple longer than the array
+                None => Err(MissingField(self.child_path(segment.as_slice()), None))
+            },
+            Pairs(ref mut entries) => match entries.next() {
+                Some((key, val)) => Ok(self.new_state(Pair(key, Some(val)))),
+                None => Err(MissingField(self.child_path(segment.as_slice()), None))
+            },
+            // the key decodes like the key of a map, and the value at the
+            // path of the key, like a struct field
+            Pair(ref key, ref mut val) => {
+                if idx == 0 { return Ok(self.new_child(String(key.clone()), key.as_slice())) }
+                match val.take() {
+                    Some(val) if idx == 1 => Ok(self.new_child(val, key.as_slice())),
+                    _ => Err(Unsupported(self.child_path(key.as_slice()), "more than a key and a value"))
+                }
+            }
+            _ => Err(Unsupported(self.path.clone(), "an element outside of a sequence"))
+        }
+    }
+
+    // Fails with `OutOfRange` if a number did not fit type `ty`
+    fn in_range<T>(&self, n: Option<T>, ty: &'static str) -> DecodeResult<T> {
+        match n {
+            Some(n) => Ok(n),
+            None => Err(OutOfRange(self.path.clone(), ty))
+        }
+    }
+
+    // Fails for a request TOML cannot satisfy, or one outside of the
+    // expected state
+    fn unsupported<T>(&self, what: &'static str) -> DecodeResult<T> {
+        Err(Unsupported(self.path.clone(), what))
     }
 }
 
 impl serialize::Decoder<Error> for Decoder {
-    fn read_nil(&mut self) -> DecodeResult<()> { Err(ParseError) }
+    fn read_nil(&mut self) -> DecodeResult<()> { self.unsupported("nil") }
 
     fn read_u64(&mut self) -> DecodeResult<u64> {
+        match self.read_flags() {
+            Some(res) => return res,
+            None => {}
+        }
         match self.value {
-            PosInt(v) => Ok(v),
-            _ => Err(ParseError)
+            Integer(v) if v >= 0 => Ok(v as u64),
+            _ => self.wrong_type("non-negative integer")
         }
     }
 
-    fn read_uint(&mut self) -> DecodeResult<uint> { self.read_u64().and_then(|x| x.to_uint().map_or(Err(ParseError), |x| Ok(x))) }
-    fn read_u32(&mut self) -> DecodeResult<u32> { self.read_u64().and_then(|x| x.to_u32().map_or(Err(ParseError), |x| Ok(x))) }
-    fn read_u16(&mut self) -> DecodeResult<u16> { self.read_u64().and_then(|x| x.to_u16().map_or(Err(ParseError), |x| Ok(x))) }
-    fn read_u8(&mut self) -> DecodeResult<u8> { self.read_u64().and_then(|x| x.to_u8().map_or(Err(ParseError), |x| Ok(x))) }
+    fn read_uint(&mut self) -> DecodeResult<uint> { let x = try!(self.read_u64()); self.in_range(x.to_uint(), "uint") }
+    fn read_u32(&mut self) -> DecodeResult<u32> { let x = try!(self.read_u64()); self.in_range(x.to_u32(), "u32") }
+    fn read_u16(&mut self) -> DecodeResult<u16> { let x = try!(self.read_u64()); self.in_range(x.to_u16(), "u16") }
+    fn read_u8(&mut self) -> DecodeResult<u8> { let x = try!(self.read_u64()); self.in_range(x.to_u8(), "u8") }
 
     fn read_i64(&mut self) -> DecodeResult<i64> {
+        match self.read_flags() {
+            Some(res) => { let bits = try!(res); return self.in_range(bits.to_i64(), "i64") }
+            None => {}
+        }
         match self.value {
-            PosInt(v) => v.to_i64().map_or(Err(ParseError), |v| Ok(v)),
-            NegInt(v) => v.to_i64().map_or(Err(ParseError), |v| Ok(-v)),
-            _ => Err(ParseError)
+            Integer(v) => Ok(v),
+            _ => self.wrong_type("integer")
         }
     }
 
-    fn read_int(&mut self) -> DecodeResult<int> { self.read_i64().and_then(|x| x.to_int().map_or(Err(ParseError), |x| Ok(x))) }
-    fn read_i32(&mut self) -> DecodeResult<i32> { self.read_i64().and_then(|x| x.to_i32().map_or(Err(ParseError), |x| Ok(x))) }
-    fn read_i16(&mut self) -> DecodeResult<i16> { self.read_i64().and_then(|x| x.to_i16().map_or(Err(ParseError), |x| Ok(x))) }
-    fn read_i8(&mut self) -> DecodeResult<i8> { self.read_i64().and_then(|x| x.to_i8().map_or(Err(ParseError), |x| Ok(x))) }
+    fn read_int(&mut self) -> DecodeResult<int> { let x = try!(self.read_i64()); self.in_range(x.to_int(), "int") }
+    fn read_i32(&mut self) -> DecodeResult<i32> { let x = try!(self.read_i64()); self.in_range(x.to_i32(), "i32") }
+    fn read_i16(&mut self) -> DecodeResult<i16> { let x = try!(self.read_i64()); self.in_range(x.to_i16(), "i16") }
+    fn read_i8(&mut self) -> DecodeResult<i8> { let x = try!(self.read_i64()); self.in_range(x.to_i8(), "i8") }
 
     fn read_bool(&mut self) -> DecodeResult<bool> {
         match self.value {
             Boolean(b) => Ok(b),
-            _ => Err(ParseError)
+            _ => self.wrong_type("boolean")
         }
     }
 
     fn read_f64(&mut self) -> DecodeResult<f64> {
          match self.value {
             Float(f) => Ok(f),
-            _ => Err(ParseError)
+            _ => self.wrong_type("float")
         }
     }
 
     fn read_f32(&mut self) -> DecodeResult<f32> {
-        self.read_f64().and_then(|x| x.to_f32().map_or(Err(ParseError), |x| Ok(x)))
+        let x = try!(self.read_f64());
+        self.in_range(x.to_f32(), "f32")
     }
 
     fn read_char(&mut self) -> DecodeResult<char> {
         let s = try!(self.read_str());
-        if s.len() != 1 { return Err(ParseError); }
-        Ok(s.as_slice()[0] as char)
+        if s.as_slice().char_len() != 1 {
+            return Err(TypeMismatch(self.path.clone(), "single character", String(s).describe()));
+        }
+        Ok(s.as_slice().char_at(0))
     }
 
     fn read_str(&mut self) -> DecodeResult<String> {
         match mem::replace(&mut self.value, NoValue) {
             String(s) => Ok(s.to_str()),
-            _ => Err(ParseError)
+            value => {
+                self.value = value;
+                self.wrong_type("string")
+            }
         }
     }
 
-    fn read_enum<T>(&mut self, _name: &str, _f: |&mut Decoder| -> DecodeResult<T>) -> DecodeResult<T> { Err(ParseError) }
-    fn read_enum_variant<T>(&mut self, _names: &[&str], _f: |&mut Decoder, uint| -> DecodeResult<T>) -> DecodeResult<T> { Err(ParseError) }
-    fn read_enum_variant_arg<T>(&mut self, _idx: uint, _f: |&mut Decoder| -> DecodeResult<T>) -> DecodeResult<T> { Err(ParseError) }
+    fn read_enum<T>(&mut self, _name: &str, _f: |&mut Decoder| -> DecodeResult<T>) -> DecodeResult<T> { self.unsupported("an enum") }
+    fn read_enum_variant<T>(&mut self, _names: &[&str], _f: |&mut Decoder, uint| -> DecodeResult<T>) -> DecodeResult<T> { self.unsupported("an enum") }
+    fn read_enum_variant_arg<T>(&mut self, _idx: uint, _f: |&mut Decoder| -> DecodeResult<T>) -> DecodeResult<T> { self.unsupported("an enum") }
 
     fn read_seq<T>(&mut self, f: |&mut Decoder, uint| -> DecodeResult<T>) -> DecodeResult<T> {
+        // an entry of a table, see the `Table` arm below
+        let pair = match self.state { Pair(..) => true, _ => false };
+        if pair { return f(self, 2) }
         match mem::replace(&mut self.value, NoValue) {
-            Array(a) | TableArray(a) => {
+            Array(a) | TableArray(a) | Append(a) => {
                 let l = a.len();
-                f(&mut Decoder::new_state(Arr(a.move_iter())), l)
+                f(&mut self.new_state(Arr(a.move_iter())), l)
+            }
+            // a table decodes into a sequence of (key, value) pairs, e.g. for
+            // a Vec<(String, T)> field, each value at the path of its key,
+            // like a struct field. The pairs are in source order if the
+            // spans are known (see `from_toml_spanned`). Tables do not
+            // retain their order, so otherwise, and for keys without a span,
+            // keys are sorted.
+            Table(hm) | TableInner(hm) => {
+                let mut entries: Vec<(uint, String, Value)> = hm.move_iter().map(|(k, v)| {
+                    let offset = self.spans.as_ref()
+                                     .and_then(|spans| spans.get(self.child_path(k.as_slice()).as_slice()))
+                                     .map_or(std::uint::MAX, |span| span.start.offset);
+                    (offset, k, v)
+                }).collect();
+                entries.sort_by(|&(a_offset, ref a, _), &(b_offset, ref b, _)| (a_offset, a).cmp(&(b_offset, b)));
+                let entries: Vec<(String, Value)> = entries.move_iter().map(|(_, k, v)| (k, v)).collect();
+                let l = entries.len();
+                f(&mut self.new_state(Pairs(entries.move_iter())), l)
+            }
+            value => {
+                self.value = value;
+                self.wrong_type("array")
             }
-            _ => Err(ParseError)
         }
     }
 
-    fn read_seq_elt<T>(&mut self, _idx: uint, f: |&mut Decoder| -> DecodeResult<T>) -> DecodeResult<T> {
+    fn read_seq_elt<T>(&mut self, idx: uint, f: |&mut Decoder| -> DecodeResult<T>) -> DecodeResult<T> {
         // XXX: assert(idx)
         // XXX: assert!(self.value == NoValue);
         // XXX: self.value = ...
-        match self.state {
-            Arr(ref mut a) => f(&mut Decoder::new(a.next().unwrap())),
-            _ => Err(ParseError)
-        }
+        let mut state = mem::replace(&mut self.state, No);
+        let next = self.next_element(&mut state, idx);
+        self.state = state;
+        f(&mut try!(next))
     }
 
     fn read_struct<T>(&mut self, _name: &str, _len: uint, f: |&mut Decoder| -> DecodeResult<T>) -> DecodeResult<T> {
         match mem::replace(&mut self.value, NoValue) {
             Table(hm) | TableInner(hm) => {
-                f(&mut Decoder::new_state(Tab(hm)))
+                f(&mut self.new_state(Tab(hm)))
+            }
+            value => {
+                self.value = value;
+                self.wrong_type("table")
             }
-            _ => Err(ParseError)
         }
     }
 
     fn read_struct_field<T>(&mut self, name: &str, _idx: uint, f: |&mut Decoder| -> DecodeResult<T>) -> DecodeResult<T> {
         // XXX: assert!(self.value == NoValue);
-        let res = match self.state {
-            Tab(ref mut tab) => {
-                match tab.pop(&name.to_str()) { // XXX: pop_equiv(...) or find_equiv_mut...
-                    None => f(&mut Decoder::new(NoValue)), // XXX: NoValue means "nil" here
-                    Some(val) => f(&mut Decoder::new(val))
+        let found = match self.state {
+            Tab(ref mut tab) => tab.pop(&name.to_str()), // XXX: pop_equiv(...) or find_equiv_mut...
+            _ => return Err(Unsupported(self.path.clone(), "a field outside of a table"))
+        };
+        let res = match found {
+            None => {
+                // XXX: NoValue means "nil" here
+                let path = self.child_path(name);
+                match f(&mut self.new_field(NoValue, name)) {
+                    Err(MissingField(ref missing, None)) if *missing == path => {
+                        let suggestion = match self.state {
+                            Tab(ref tab) => nearest_match(name, tab.keys().map(|k| k.as_slice())),
+                            _ => None
+                        };
+                        Err(MissingField(path.clone(), suggestion.map(|s| s.to_string())))
+                    }
+                    res => res
                 }
             }
-            _ => Err(ParseError)
+            Some(val) => f(&mut self.new_field(val, name))
         };
 
         match res {
             Ok(val) => Ok(val),
-            Err(ParseError) => Err(ParseErrorInField(name.to_str())),
+            Err(ParseError) => Err(ParseErrorInField(self.child_path(name))),
             Err(e) => Err(e)
         }
     }
@@ -1060,30 +3376,46 @@ impl serialize::Decoder<Error> for Decoder {
         match mem::replace(&mut self.value, NoValue) {
             Table(hm) | TableInner(hm) => {
                 let len = hm.len();
-                f(&mut Decoder::new_state(Map(hm.move_iter())), len)
+                f(&mut self.new_state(Map(hm.move_iter(), None)), len)
+            }
+            value => {
+                self.value = value;
+                self.wrong_type("table")
             }
-            _ => Err(ParseError)
         }
     }
 
     fn read_map_elt_key<T>(&mut self, _idx: uint, f: |&mut Decoder| -> DecodeResult<T>) -> DecodeResult<T> {
-        let (k, v) = match self.state {
-            Map(ref mut map) => {
+        let key = match self.state {
+            Map(ref mut map, ref mut current) => {
                 match map.next() {
-                    None => return Err(ParseError),
-                    Some((k, v)) => (k, v)
+                    None => None,
+                    Some((k, v)) => {
+                        *current = Some((k.clone(), v));
+                        Some(k)
+                    }
                 }
             }
-            _ => return Err(ParseError)
+            _ => return Err(Unsupported(self.path.clone(), "a map entry outside of a table"))
         };
-        self.value = String(k);
-        let res = f(self);
-        self.value = v;
-        res
+        let key = match key {
+            Some(key) => key,
+            None => return self.unsupported("more map entries than the table has")
+        };
+        f(&mut self.new_child(String(key.clone()), key.as_slice()))
     }
 
+    // The value is decoded at the path of its key, like a struct field
     fn read_map_elt_val<T>(&mut self, _idx: uint, f: |&mut Decoder| -> DecodeResult<T>) -> DecodeResult<T> {
-        f(self)
+        let entry = match self.state {
+            Map(_, ref mut current) => current.take(),
+            _ => return Err(Unsupported(self.path.clone(), "a map entry outside of a table"))
+        };
+        let (k, v) = match entry {
+            Some(entry) => entry,
+            None => return self.unsupported("a map value before its key")
+        };
+        f(&mut self.new_child(v, k.as_slice()))
     }
 
     fn read_enum_struct_variant<T>(&mut self,
@@ -1125,7 +3457,42 @@ impl serialize::Decoder<Error> for Decoder {
     }
 }
 
+/// Binary data, decoded from a base64 encoded string, as TOML has no type
+/// for it. Declare a field as `Base64Bytes` to opt in; a `Vec<u8>` field
+/// decodes from an array of integers like any other `Vec`.
+#[deriving(Show,Clone,PartialEq,Eq)]
+pub struct Base64Bytes(pub Vec<u8>);
+
+impl Decodable<Decoder, Error> for Base64Bytes {
+    fn decode(d: &mut Decoder) -> DecodeResult<Base64Bytes> {
+        match d.value.get_bytes_base64() {
+            Some(bytes) => Ok(Base64Bytes(bytes)),
+            None => d.wrong_type("base64 encoded string")
+        }
+    }
+}
+
 pub fn from_toml<T: Decodable<Decoder, Error>>(value: Value) -> DecodeResult<T> {
     let mut decoder = Decoder::new(value);
     Decodable::decode(&mut decoder)
 }
+
+/// Like `from_toml`, but the integer fields at the dotted paths given in
+/// `flags` decode from arrays of flag names (see `Decoder::register_flags`),
+/// e.g. `from_toml_with_flags(value, &[("server.mode", mode_flags)])` with
+/// `mode_flags` being `&[("read", 4), ("write", 2)]`.
+pub fn from_toml_with_flags<T: Decodable<Decoder, Error>>(value: Value, flags: &[(&str, &[(&str, u64)])]) -> DecodeResult<T> {
+    let mut decoder = Decoder::new(value);
+    for &(field, names) in flags.iter() {
+        decoder.register_flags(field, names);
+    }
+    Decodable::decode(&mut decoder)
+}
+
+/// Like `from_toml`, but fields of type `Spanned<T>` receive the span of
+/// their value in the source document, as recorded with
+/// `ParserOptions::spans`.
+pub fn from_toml_spanned<T: Decodable<Decoder, Error>>(value: Value, spans: SpanMap) -> DecodeResult<T> {
+    let mut decoder = Decoder::new_spanned(value, spans);
+    Decodable::decode(&mut decoder)
+}
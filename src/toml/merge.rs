@@ -0,0 +1,216 @@
+// Deep merging of Value trees.
+//
+// Tables are merged key by key, recursively. How arrays and other values
+// present in both trees are combined is chosen by a `MergeStrategy`. The
+// elements of an `Append` value (see `ParserOptions::allow_append`) are
+// always appended, which fails for anything but an array, and the result
+// contains no `Append` values.
+
+use super::{Value, Error, MergeConflict, AppendNotArray, NoValue, Array, TableArray, Append, Table, TableInner};
+
+use std::collections::hashmap::HashMap;
+
+/// How `Value::merge` combines two arrays, or two table arrays
+#[deriving(Show,Clone,PartialEq)]
+pub enum ArrayMerge {
+    /// The array merged in replaces the existing one
+    ReplaceArrays,
+    /// The elements of the array merged in are appended to the existing
+    /// one, e.g. the `[[servers]]` of both trees are kept
+    AppendArrays
+}
+
+/// What `Value::merge` does with a value present in both trees, unless
+/// both are tables (or arrays to append)
+#[deriving(Show,Clone,PartialEq)]
+pub enum ConflictPolicy {
+    /// The value merged in replaces the existing one
+    Overwrite,
+    /// The existing value is kept
+    KeepExisting,
+    /// Merging fails with `MergeConflict`, unless both values are equal
+    FailOnConflict
+}
+
+/// The configuration of `Value::merge`
+#[deriving(Show,Clone,PartialEq)]
+pub struct MergeStrategy {
+    pub arrays: ArrayMerge,
+    pub conflicts: ConflictPolicy
+}
+
+impl MergeStrategy {
+    /// Replaces arrays and overwrites conflicting values, so that the tree
+    /// merged in takes precedence. This is how `config::Layers` merges.
+    pub fn new() -> MergeStrategy {
+        MergeStrategy { arrays: ReplaceArrays, conflicts: Overwrite }
+    }
+}
+
+impl Value {
+    /// Merges `other` into this tree according to `strategy`. With
+    /// `FailOnConflict`, the dotted path of the first conflicting value is
+    /// returned in a `MergeConflict` error, and this tree is left partially
+    /// merged. Likewise, appending to a value other than an array fails with
+    /// `AppendNotArray`, whatever the strategy.
+    pub fn merge(&mut self, other: Value, strategy: MergeStrategy) -> Result<(), Error> {
+        merge_at(self, other, &strategy, "")
+    }
+}
+
+fn merge_at(dst: &mut Value, src: Value, strategy: &MergeStrategy, path: &str) -> Result<(), Error> {
+    let (src_map, explicit) = match src {
+        Table(map) => (map, true),
+        TableInner(map) => (map, false),
+        Append(items) => return merge_array(dst, items, true, strategy, path),
+        Array(items) => return merge_array(dst, items, false, strategy, path),
+        TableArray(items) => return merge_table_array(dst, items, strategy, path),
+        src => return replace(dst, src, strategy, path)
+    };
+    let is_table = match *dst {
+        Table(_) | TableInner(_) => true,
+        _ => false
+    };
+    if !is_table {
+        if *dst != NoValue {
+            match strategy.conflicts {
+                Overwrite => {}
+                KeepExisting => return Ok(()),
+                FailOnConflict => return Err(conflict(path))
+            }
+        }
+        *dst = if explicit { Table(box HashMap::new()) } else { TableInner(box HashMap::new()) };
+    }
+    match *dst {
+        Table(ref mut dst_map) | TableInner(ref mut dst_map) => {
+            for (key, val) in src_map.move_iter() {
+                let child = if path.is_empty() { key.clone() } else { format!("{}.{}", path, key) };
+                match dst_map.find_mut(&key) {
+                    Some(existing) => {
+                        try!(merge_at(existing, val, strategy, child.as_slice()));
+                        continue;
+                    }
+                    None => {}
+                }
+                // merged into nothing, to resolve nested appends
+                let mut fresh = NoValue;
+                try!(merge_at(&mut fresh, val, strategy, child.as_slice()));
+                dst_map.insert(key, fresh);
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+fn merge_array(dst: &mut Value, items: Vec<Value>, append: bool, strategy: &MergeStrategy,
+               path: &str) -> Result<(), Error> {
+    if append || strategy.arrays == AppendArrays {
+        match *dst {
+            Array(ref mut existing) => {
+                existing.push_all_move(items);
+                return Ok(());
+            }
+            // `key += [...]` needs an array to append to
+            NoValue => {}
+            _ if append => return Err(AppendNotArray(path.to_string(), None)),
+            _ => {}
+        }
+    }
+    replace(dst, Array(items), strategy, path)
+}
+
+fn merge_table_array(dst: &mut Value, items: Vec<Value>, strategy: &MergeStrategy,
+                     path: &str) -> Result<(), Error> {
+    if strategy.arrays == AppendArrays {
+        match *dst {
+            TableArray(ref mut existing) => {
+                existing.push_all_move(items);
+                return Ok(());
+            }
+            _ => {}
+        }
+    }
+    replace(dst, TableArray(items), strategy, path)
+}
+
+// Replaces `dst` by `src`, unless the strategy says otherwise
+fn replace(dst: &mut Value, src: Value, strategy: &MergeStrategy, path: &str) -> Result<(), Error> {
+    if *dst == NoValue || *dst == src {
+        *dst = src;
+        return Ok(());
+    }
+    match strategy.conflicts {
+        Overwrite => *dst = src,
+        KeepExisting => {}
+        FailOnConflict => return Err(conflict(path))
+    }
+    Ok(())
+}
+
+fn conflict(path: &str) -> Error {
+    MergeConflict(path.to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::{Value, Integer, Array, Append, TableArray, TableInner};
+    use super::super::{AppendNotArray, MergeConflict};
+    use super::{MergeStrategy, AppendArrays, FailOnConflict, KeepExisting};
+
+    use std::collections::hashmap::HashMap;
+
+    fn table(key: &str, value: Value) -> Value {
+        let mut map = box HashMap::new();
+        map.insert(key.to_string(), value);
+        TableInner(map)
+    }
+
+    #[test]
+    fn test_append() {
+        let mut dst = table("a", Array(vec!(Integer(1))));
+        dst.merge(table("a", Append(vec!(Integer(2)))), MergeStrategy::new()).unwrap();
+        assert_eq!(dst, table("a", Array(vec!(Integer(1), Integer(2)))));
+    }
+
+    #[test]
+    fn test_append_to_nothing() {
+        let mut dst = table("b", Integer(1));
+        dst.merge(table("a", Append(vec!(Integer(2)))), MergeStrategy::new()).unwrap();
+        assert_eq!(dst.lookup("a"), Some(&Array(vec!(Integer(2)))));
+    }
+
+    #[test]
+    fn test_append_to_scalar() {
+        let mut dst = table("t", table("a", Integer(1)));
+        let res = dst.merge(table("t", table("a", Append(vec!(Integer(2))))), MergeStrategy::new());
+        assert_eq!(res, Err(AppendNotArray("t.a".to_string(), None)));
+        assert_eq!(dst.lookup("t.a"), Some(&Integer(1)));
+    }
+
+    #[test]
+    fn test_append_table_arrays() {
+        let strategy = MergeStrategy { arrays: AppendArrays, ..MergeStrategy::new() };
+        let mut dst = table("servers", TableArray(vec!(table("port", Integer(1)))));
+        dst.merge(table("servers", TableArray(vec!(table("port", Integer(2))))), strategy).unwrap();
+        assert_eq!(dst.lookup("servers.1.port"), Some(&Integer(2)));
+
+        // replaced by default
+        let mut dst = table("servers", TableArray(vec!(table("port", Integer(1)))));
+        dst.merge(table("servers", TableArray(vec!(table("port", Integer(2))))), MergeStrategy::new()).unwrap();
+        assert_eq!(dst, table("servers", TableArray(vec!(table("port", Integer(2))))));
+    }
+
+    #[test]
+    fn test_conflicts() {
+        let strategy = MergeStrategy { conflicts: FailOnConflict, ..MergeStrategy::new() };
+        let mut dst = table("t", table("a", Integer(1)));
+        assert!(dst.merge(table("t", table("a", Integer(1))), strategy.clone()).is_ok());
+        assert_eq!(dst.merge(table("t", table("a", Integer(2))), strategy),
+                   Err(MergeConflict("t.a".to_string())));
+
+        let strategy = MergeStrategy { conflicts: KeepExisting, ..MergeStrategy::new() };
+        dst.merge(table("t", table("a", Integer(3))), strategy).unwrap();
+        assert_eq!(dst.lookup("t.a"), Some(&Integer(1)));
+    }
+}
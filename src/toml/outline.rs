@@ -0,0 +1,104 @@
+// The section structure of a document, for editors.
+//
+// Only section headers are parsed, the section bodies are skipped without
+// building any values.
+
+use super::{Value, Visitor, Error, Parser, ParserOptions, run_parser};
+use super::span::Span;
+
+use std::io::Buffer;
+
+/// A `[section]` or `[[section]]` header of a document, see `outline`
+#[deriving(Show,Clone,PartialEq)]
+pub struct SectionInfo {
+    /// The name of the section with its keys joined by dots, e.g.
+    /// `servers.alpha`
+    pub path: String,
+    /// `true` for a `[[section]]` header
+    pub is_array: bool,
+    /// The line of the header, starting at 1
+    pub header_line: uint,
+    /// The lines of the body, from the line following the header up to but
+    /// excluding the next header, or the end of the document
+    pub body_lines: (uint, uint)
+}
+
+struct Outliner {
+    sections: Vec<SectionInfo>
+}
+
+impl Outliner {
+    // Ends the body of the previous section before `line`
+    fn close(&mut self, line: uint) {
+        match self.sections.mut_last() {
+            Some(section) => {
+                let (start, _) = section.body_lines;
+                section.body_lines = (start, line);
+            }
+            None => {}
+        }
+    }
+}
+
+impl Visitor for Outliner {
+    // the parser calls `section_at` instead
+    fn section(&mut self, _path: Vec<String>, _is_array: bool) -> bool {
+        true
+    }
+
+    fn pair(&mut self, _key: String, _val: Value) -> bool {
+        true
+    }
+
+    fn section_at(&mut self, path: Vec<String>, is_array: bool, span: Span) -> bool {
+        let line = span.start.line;
+        self.close(line);
+        self.sections.push(SectionInfo { path: path.connect("."), is_array: is_array, header_line: line,
+                                         body_lines: (line + 1, line + 1) });
+        true
+    }
+}
+
+/// Returns the section headers of the document read from `rd`, in document
+/// order. The section bodies are not checked for syntax errors.
+pub fn outline<BUF: Buffer>(rd: &mut BUF) -> Result<Vec<SectionInfo>, Error> {
+    let mut outliner = Outliner { sections: vec!() };
+    let mut parser = Parser::with_options(rd, ParserOptions::new().skip_bodies(true));
+    try!(run_parser(&mut parser, &mut outliner));
+    // the last line counts if it is not terminated by a newline
+    let end = if parser.col == 1 { parser.line } else { parser.line + 1 };
+    outliner.close(end);
+    Ok(outliner.sections)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{SectionInfo, outline};
+
+    use std::io::BufReader;
+
+    fn sections(doc: &str) -> Vec<SectionInfo> {
+        outline(&mut BufReader::new(doc.as_bytes())).unwrap()
+    }
+
+    fn section(path: &str, is_array: bool, header_line: uint, body_lines: (uint, uint)) -> SectionInfo {
+        SectionInfo { path: path.to_string(), is_array: is_array, header_line: header_line,
+                      body_lines: body_lines }
+    }
+
+    #[test]
+    fn test_outline() {
+        // the bodies are not parsed, so `?` goes unnoticed
+        let doc = "title = 1\n[a]\nx = 1\n\n[[b.c]]\ny = ?\n";
+        assert_eq!(sections(doc), vec!(section("a", false, 2, (3, 5)), section("b.c", true, 5, (6, 7))));
+        // the last line counts without a newline as well
+        assert_eq!(sections("[a]\nx = 1"), vec!(section("a", false, 1, (2, 3))));
+        assert_eq!(sections("[a]"), vec!(section("a", false, 1, (2, 2))));
+        assert!(sections("x = 1").is_empty());
+    }
+
+    #[test]
+    fn test_outline_invalid_header() {
+        assert!(outline(&mut BufReader::new("[a]\n[b\n".as_bytes())).is_err());
+    }
+}
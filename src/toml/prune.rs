@@ -0,0 +1,67 @@
+// Removal of empty tables and arrays.
+
+use super::{Value, Array, TableArray, Append, TableInner, Table};
+
+// Returns `true` if `value` should be removed from its parent table
+fn is_empty(value: &Value, keep_explicit: bool) -> bool {
+    match *value {
+        Array(ref vec) | TableArray(ref vec) | Append(ref vec) => vec.is_empty(),
+        TableInner(ref map) => map.is_empty(),
+        Table(ref map) => map.is_empty() && !keep_explicit,
+        _ => false
+    }
+}
+
+impl Value {
+    /// Recursively removes empty tables and arrays from the tables of this
+    /// tree, including tables which become empty by doing so. With
+    /// `keep_explicit`, tables which had a `[section]` header are kept even
+    /// if empty. Elements of arrays are never removed, as that would shift
+    /// the indices of the others.
+    pub fn prune_empty(&mut self, keep_explicit: bool) {
+        match *self {
+            Table(ref mut map) | TableInner(ref mut map) => {
+                let mut empty = vec!();
+                for (key, val) in map.mut_iter() {
+                    val.prune_empty(keep_explicit);
+                    if is_empty(val, keep_explicit) { empty.push(key.clone()) }
+                }
+                for key in empty.iter() {
+                    map.pop(key);
+                }
+            }
+            Array(ref mut vec) | TableArray(ref mut vec) | Append(ref mut vec) => {
+                for elm in vec.mut_iter() {
+                    elm.prune_empty(keep_explicit);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::{Integer, parse_from_str};
+
+    static DOC: &'static str = "a = 1\nxs = []\n[empty]\n[outer.inner]\n[[h]]\n[[h]]\nk = 1\n";
+
+    #[test]
+    fn test_prune_empty() {
+        let mut value = parse_from_str(DOC).unwrap();
+        value.prune_empty(false);
+        // `h.0` is empty, but elements of table arrays are never removed
+        assert_eq!(value.paths(), vec!("a".to_string(), "h".to_string(), "h.0".to_string(),
+                                       "h.1".to_string(), "h.1.k".to_string()));
+        assert_eq!(value.lookup("h.1.k"), Some(&Integer(1)));
+    }
+
+    #[test]
+    fn test_prune_empty_keeps_explicit_sections() {
+        let mut value = parse_from_str(DOC).unwrap();
+        value.prune_empty(true);
+        assert_eq!(value.paths(), vec!("a".to_string(), "empty".to_string(), "h".to_string(),
+                                       "h.0".to_string(), "h.1".to_string(), "h.1.k".to_string(),
+                                       "outer".to_string(), "outer.inner".to_string()));
+    }
+}
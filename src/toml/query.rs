@@ -0,0 +1,458 @@
+// Path queries over a Value tree.
+//
+// `lookup_all` extends the dotted path syntax of `Value::lookup` with
+// wildcards: a `*` segment matches every key of a table, and every element
+// of an array or table array. A `**` segment matches at any depth,
+// including none. A `start..end` segment selects the half-open range of
+// elements of an array or table array, either bound may be omitted.
+// Finally `key[field=value]` selects those elements of `key` whose `field`
+// equals the given value literal, e.g. `servers[enabled=true].host`.
+
+use super::{value_from_str, resolve_index};
+use super::{Value, String, Array, TableArray, Table, TableInner};
+
+use std::ascii::StrAsciiExt;
+use std::fmt;
+
+/// Why a `lookup_checked` failed
+#[deriving(Show,Clone,PartialEq)]
+pub enum LookupErrorKind {
+    /// The table has no such key
+    KeyNotFound,
+    /// The index is beyond the end of the array (its length is given)
+    IndexOutOfBounds(uint),
+    /// A key was looked up in a value which is not a table (its type is given)
+    NotATable(&'static str),
+    /// An index was looked up in a value which is not a table array (its
+    /// type is given)
+    NotAnArray(&'static str)
+}
+
+/// The error returned by `lookup_checked`
+#[deriving(Clone,PartialEq)]
+pub struct LookupError {
+    /// The path up to, but excluding, the failing segment
+    pub parent: String,
+    /// The failing segment
+    pub segment: String,
+    pub kind: LookupErrorKind
+}
+
+// Prefixes the type name `name` with its indefinite article
+fn with_article(name: &str) -> String {
+    let article = if "aeiou".contains_char(name.char_at(0)) { "an" } else { "a" };
+    format!("{} {}", article, name)
+}
+
+impl fmt::Show for LookupError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let parent = if self.parent.is_empty() { "<root>" } else { self.parent.as_slice() };
+        match self.kind {
+            KeyNotFound => {
+                write!(fmt, "key `{}` not found in `{}`", self.segment, parent)
+            }
+            IndexOutOfBounds(len) => {
+                write!(fmt, "index {} out of bounds in `{}`, length {}", self.segment, parent, len)
+            }
+            NotATable(typ) => {
+                write!(fmt, "`{}` exists but is {}, not a table", parent, with_article(typ))
+            }
+            NotAnArray(typ) => {
+                write!(fmt, "`{}` exists but is {}, not a table array", parent, with_article(typ))
+            }
+        }
+    }
+}
+
+enum Segment<'a> {
+    Key(&'a str),
+    Index(int),
+    Range(uint, Option<uint>),
+    Filter(&'a str, &'a str, Value),
+    Any,
+    Descend
+}
+
+// Splits `path` at each `.`, except for the `..` of range segments and dots
+// inside of `[...]` filters.
+fn split_path<'a>(path: &'a str) -> Vec<&'a str> {
+    let bytes = path.as_bytes();
+    let mut parts = vec!();
+    let mut start = 0;
+    let mut depth = 0u;
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == '[' as u8 {
+            depth += 1;
+        } else if bytes[i] == ']' as u8 && depth > 0 {
+            depth -= 1;
+        } else if bytes[i] == '.' as u8 && depth == 0 {
+            let run = bytes.slice_from(i).iter().take_while(|&&b| b == '.' as u8).count();
+            if run == 1 {
+                parts.push(path.slice(start, i));
+                start = i + 1;
+            } else if run == 3 {
+                // a separator next to the `..` of a range, which belongs to
+                // the bound beside it: `1...name` is `1..` and `name`,
+                // `items...2` is `items` and `..2`
+                let at = if from_str::<uint>(path.slice(start, i)).is_some() { i + 2 } else { i };
+                parts.push(path.slice(start, at));
+                start = at + 1;
+            }
+            i += run;
+            continue;
+        }
+        i += 1;
+    }
+    parts.push(path.slice_from(start));
+    parts
+}
+
+fn parse_segment<'a>(segment: &'a str) -> Segment<'a> {
+    if segment == "*" { return Any }
+    if segment == "**" { return Descend }
+    if segment.ends_with("]") {
+        match (segment.find('['), segment.find('=')) {
+            (Some(open), Some(eq)) if open < eq => {
+                let literal = segment.slice(eq + 1, segment.len() - 1);
+                // bare words compare as strings
+                let value = value_from_str(literal).unwrap_or(String(literal.to_string()));
+                return Filter(segment.slice_to(open), segment.slice(open + 1, eq), value);
+            }
+            _ => {}
+        }
+    }
+    match segment.find_str("..") {
+        Some(pos) => {
+            let start = segment.slice_to(pos);
+            let end = segment.slice_from(pos + 2);
+            let start = if start.is_empty() { Some(0) } else { from_str::<uint>(start) };
+            let end = if end.is_empty() { Some(None) } else { from_str::<uint>(end).map(|e| Some(e)) };
+            match (start, end) {
+                (Some(start), Some(end)) => return Range(start, end),
+                _ => return Key(segment)
+            }
+        }
+        None => {}
+    }
+    match from_str::<int>(segment) {
+        Some(idx) => Index(idx),
+        None => Key(segment)
+    }
+}
+
+// Calls `f` on every direct child of `value`. Table entries are visited in
+// sorted key order, so results do not depend on hashing.
+fn for_each_child<'a>(value: &'a Value, f: |&'a Value|) {
+    match *value {
+        Table(ref map) | TableInner(ref map) => {
+            let mut keys: Vec<&String> = map.keys().collect();
+            keys.sort();
+            for key in keys.iter() {
+                f(map.get(*key));
+            }
+        }
+        Array(ref vec) | TableArray(ref vec) => {
+            for elm in vec.iter() {
+                f(elm);
+            }
+        }
+        _ => {}
+    }
+}
+
+// Appends the paths of `value` and everything below it to `out`
+fn collect_paths(value: &Value, path: &str, out: &mut Vec<String>) {
+    match *value {
+        Table(ref map) | TableInner(ref map) => {
+            let mut keys: Vec<&String> = map.keys().collect();
+            keys.sort();
+            for key in keys.iter() {
+                let child = if path.is_empty() { key.to_string() }
+                            else { format!("{}.{}", path, key) };
+                out.push(child.clone());
+                collect_paths(map.get(*key), child.as_slice(), out);
+            }
+        }
+        TableArray(ref vec) => {
+            for (idx, elm) in vec.iter().enumerate() {
+                let child = format!("{}.{}", path, idx);
+                out.push(child.clone());
+                collect_paths(elm, child.as_slice(), out);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn collect<'a>(value: &'a Value, segments: &[Segment], out: &mut Vec<&'a Value>) {
+    let head = match segments.head() {
+        Some(head) => head,
+        None => {
+            out.push(value);
+            return;
+        }
+    };
+    let rest = segments.tail();
+
+    match (head, value) {
+        (&Descend, _) => {
+            // match the rest of the path here, then keep descending
+            collect(value, rest, out);
+            for_each_child(value, |child| collect(child, segments, out));
+        }
+        (&Any, _) => {
+            for_each_child(value, |child| collect(child, rest, out));
+        }
+        (&Filter(key, field, ref expected), _) => {
+            let parent = if key.is_empty() { Some(value) } else { value.lookup_elm(&key) };
+            match parent {
+                Some(parent) => {
+                    for_each_child(parent, |child| {
+                        if child.lookup_elm(&field) == Some(expected) {
+                            collect(child, rest, out);
+                        }
+                    });
+                }
+                None => {}
+            }
+        }
+        (&Index(idx), &Array(ref vec)) | (&Index(idx), &TableArray(ref vec)) => {
+            match resolve_index(idx, vec.len()) {
+                Some(idx) => collect(vec.get(idx), rest, out),
+                None => {}
+            }
+        }
+        (&Range(start, end), &Array(ref vec)) | (&Range(start, end), &TableArray(ref vec)) => {
+            let end = end.map_or(vec.len(), |e| ::std::cmp::min(e, vec.len()));
+            for elm in vec.iter().skip(start).take(end - ::std::cmp::min(start, end)) {
+                collect(elm, rest, out);
+            }
+        }
+        (&Index(idx), &Table(ref map)) | (&Index(idx), &TableInner(ref map)) => {
+            // numeric keys are valid TOML keys
+            match map.find(&idx.to_str()) {
+                Some(elm) => collect(elm, rest, out),
+                None => {}
+            }
+        }
+        (&Key(key), &Table(ref map)) | (&Key(key), &TableInner(ref map)) => {
+            match map.find_equiv(&key) {
+                Some(elm) => collect(elm, rest, out),
+                None => {}
+            }
+        }
+        _ => {}
+    }
+}
+
+impl Value {
+    /// Returns all values matching `path`, a dotted path in which `*`
+    /// matches any table key or array index at that position. For example
+    /// `servers.*.host` returns the `host` of every element of the
+    /// `servers` table array. A `**` segment matches at any depth, so
+    /// `**.password` finds every `password` key in the document, and
+    /// `products.1..3.name` the names of the second and third product.
+    /// `servers[enabled=true].host` returns the `host` of those servers
+    /// with `enabled = true`.
+    pub fn lookup_all<'a>(&'a self, path: &str) -> Vec<&'a Value> {
+        let segments: Vec<Segment> = split_path(path).move_iter().map(parse_segment).collect();
+        let mut found = vec!();
+        collect(self, segments.as_slice(), &mut found);
+        found
+    }
+
+    /// Returns the dotted path of every table, table array, table array
+    /// element and leaf value below this one, parents before children and
+    /// table keys in sorted order. Arrays are leaves, their elements are
+    /// not listed. Each path can be passed to `lookup`.
+    pub fn paths(&self) -> Vec<String> {
+        let mut paths = vec!();
+        collect_paths(self, "", &mut paths);
+        paths
+    }
+
+    /// Like `lookup`, but matches table keys ignoring ASCII case. A key
+    /// matching exactly is always preferred. Otherwise, if several keys of
+    /// a table differ only by case, the lookup is ambiguous and returns
+    /// `None`.
+    pub fn lookup_ci<'a>(&'a self, path: &str) -> Option<&'a Value> {
+        let mut curr = self;
+        for p in path.split('.') {
+            let next = match *curr {
+                Table(ref map) | TableInner(ref map) => {
+                    match map.find_equiv(&p) {
+                        Some(v) => Some(v),
+                        None => {
+                            let mut matches = map.iter().filter(|&(k, _)| k.as_slice().eq_ignore_ascii_case(p));
+                            match (matches.next(), matches.next()) {
+                                (Some((_, v)), None) => Some(v),
+                                _ => None
+                            }
+                        }
+                    }
+                }
+                Array(_) | TableArray(_) => {
+                    from_str::<int>(p).and_then(|idx| curr.lookup_elm(&idx))
+                }
+                _ => None
+            };
+            match next {
+                Some(v) => curr = v,
+                None => return None
+            }
+        }
+        Some(curr)
+    }
+
+    /// Like `lookup`, but tells which segment of `path` failed and why.
+    /// As with `lookup`, indices address elements of table arrays only.
+    pub fn lookup_checked<'a>(&'a self, path: &str) -> Result<&'a Value, LookupError> {
+        let mut curr = self;
+        let mut parent = String::new();
+        for p in path.split('.') {
+            let next = match from_str::<int>(p) {
+                Some(idx) => {
+                    match *curr {
+                        TableArray(ref ary) => {
+                            curr.lookup_elm(&idx).ok_or(IndexOutOfBounds(ary.len()))
+                        }
+                        _ => Err(NotAnArray(curr.type_name()))
+                    }
+                }
+                None => {
+                    match *curr {
+                        Table(_) | TableInner(_) => curr.lookup_elm(&p).ok_or(KeyNotFound),
+                        _ => Err(NotATable(curr.type_name()))
+                    }
+                }
+            };
+            match next {
+                Ok(v) => curr = v,
+                Err(kind) => {
+                    return Err(LookupError { parent: parent, segment: p.to_string(), kind: kind });
+                }
+            }
+            if !parent.is_empty() { parent.push_char('.') }
+            parent.push_str(p);
+        }
+        Ok(curr)
+    }
+
+    /// Returns those values matching `path` (see `lookup_all`) for which
+    /// `pred` returns `true`.
+    pub fn lookup_where<'a>(&'a self, path: &str, pred: |&Value| -> bool) -> Vec<&'a Value> {
+        self.lookup_all(path).move_iter().filter(|v| pred(*v)).collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::{Value, parse_from_str};
+    use super::{LookupError, KeyNotFound, IndexOutOfBounds, NotATable, NotAnArray};
+
+    // The documents used below
+    static PRODUCTS: &'static str = "[[products]]\nname = \"p0\"\n[[products]]\nname = \"p1\"\n\
+                                     [[products]]\nname = \"p2\"\n";
+    static SERVERS: &'static str = "[[servers]]\nhost = \"a\"\nport = 80\nenabled = true\n\
+                                    [[servers]]\nhost = \"b\"\nport = 8080\nenabled = false\n\
+                                    [db]\npassword = \"x\"\n[db.replica]\npassword = \"y\"\n";
+
+    // The strings among `values`
+    fn strs(values: Vec<&Value>) -> Vec<String> {
+        values.iter().filter_map(|v| v.get_str()).map(|s| s.clone()).collect()
+    }
+
+    #[test]
+    fn test_lookup_all_wildcards() {
+        let root = parse_from_str(SERVERS).unwrap();
+        assert_eq!(strs(root.lookup_all("servers.*.host")), vec!("a".to_string(), "b".to_string()));
+        assert_eq!(strs(root.lookup_all("*.password")), vec!("x".to_string()));
+        assert_eq!(strs(root.lookup_all("servers.1.host")), vec!("b".to_string()));
+        assert!(root.lookup_all("servers.*.user").is_empty());
+        assert!(root.lookup_all("missing.*").is_empty());
+        assert!(root.lookup_all("db.password.*").is_empty());
+    }
+
+    #[test]
+    fn test_lookup_all_recursive() {
+        let root = parse_from_str(SERVERS).unwrap();
+        assert_eq!(strs(root.lookup_all("**.password")), vec!("x".to_string(), "y".to_string()));
+        assert_eq!(strs(root.lookup_all("db.**.password")), vec!("x".to_string(), "y".to_string()));
+        assert_eq!(strs(root.lookup_all("**.host")), vec!("a".to_string(), "b".to_string()));
+        assert!(root.lookup_all("**.user").is_empty());
+    }
+
+    #[test]
+    fn test_lookup_all_ranges() {
+        let root = parse_from_str(PRODUCTS).unwrap();
+        let names = |path: &str| strs(root.lookup_all(path));
+        assert_eq!(names("products.1..3.name"), vec!("p1".to_string(), "p2".to_string()));
+        assert_eq!(names("products...2.name"), vec!("p0".to_string(), "p1".to_string()));
+        assert_eq!(names("products.2...name"), vec!("p2".to_string()));
+        assert_eq!(names("products.1..9.name"), vec!("p1".to_string(), "p2".to_string()));
+        assert!(names("products.2..1.name").is_empty());
+        assert!(names("products.5...name").is_empty());
+        assert_eq!(strs(root.lookup("products").unwrap().lookup_all("..1.name")), vec!("p0".to_string()));
+    }
+
+    #[test]
+    fn test_lookup_filters() {
+        let root = parse_from_str(SERVERS).unwrap();
+        assert_eq!(strs(root.lookup_all("servers[enabled=true].host")), vec!("a".to_string()));
+        assert_eq!(strs(root.lookup_all("servers[port=8080].host")), vec!("b".to_string()));
+        // bare words compare as strings
+        assert_eq!(root.lookup_all("servers[host=b].port").len(), 1);
+        assert!(root.lookup_all("servers[user=a].host").is_empty());
+        assert!(root.lookup_all("missing[enabled=true]").is_empty());
+
+        let high = root.lookup_where("servers.*.port", |v| v.get_int().map_or(false, |p| p > 1024));
+        assert_eq!(high.len(), 1);
+        assert_eq!(high.get(0).get_int(), Some(8080));
+        assert!(root.lookup_where("servers.*.port", |_| false).is_empty());
+    }
+
+    #[test]
+    fn test_lookup_ci() {
+        let root = parse_from_str("[Server]\nHost = \"a\"\nPort = 80\nport = 81\n\
+                                   [[Products]]\nName = \"p0\"\n").unwrap();
+        assert_eq!(root.lookup_ci("server.host").and_then(|h| h.get_str()), Some(&"a".to_string()));
+        assert_eq!(root.lookup_ci("products.0.name").and_then(|n| n.get_str()), Some(&"p0".to_string()));
+        // an exact match wins, otherwise the lookup is ambiguous
+        assert_eq!(root.lookup_ci("SERVER.port").and_then(|p| p.get_int()), Some(81));
+        assert_eq!(root.lookup_ci("server.PORT"), None);
+        assert_eq!(root.lookup_ci("server.user"), None);
+        assert_eq!(root.lookup_ci("server.host.x"), None);
+    }
+
+    #[test]
+    fn test_lookup_checked() {
+        let root = parse_from_str(SERVERS).unwrap();
+        assert_eq!(root.lookup_checked("servers.-1.host").ok().and_then(|h| h.get_str()), Some(&"b".to_string()));
+
+        let err = |path: &str| root.lookup_checked(path).err().unwrap();
+        assert_eq!(err("servers.2.host"), LookupError { parent: "servers".to_string(), segment: "2".to_string(),
+                                                        kind: IndexOutOfBounds(2) });
+        assert_eq!(err("db.user").kind, KeyNotFound);
+        assert_eq!(err("db.password.x").kind, NotATable("string"));
+        assert_eq!(err("db.0").kind, NotAnArray("table"));
+
+        assert_eq!(format!("{}", err("user")).as_slice(), "key `user` not found in `<root>`");
+        assert_eq!(format!("{}", err("servers.0.port.x")).as_slice(),
+                   "`servers.0.port` exists but is an integer, not a table");
+        assert_eq!(format!("{}", err("servers.5")).as_slice(), "index 5 out of bounds in `servers`, length 2");
+    }
+
+    #[test]
+    fn test_paths() {
+        let paths = parse_from_str(SERVERS).unwrap().paths();
+        let expected: Vec<String> = ["db", "db.password", "db.replica", "db.replica.password",
+            "servers", "servers.0", "servers.0.enabled", "servers.0.host", "servers.0.port",
+            "servers.1", "servers.1.enabled", "servers.1.host", "servers.1.port"]
+            .iter().map(|s| s.to_string()).collect();
+        assert_eq!(paths, expected);
+        // elements of plain arrays are not listed
+        assert_eq!(parse_from_str("xs = [1, 2]").unwrap().paths(), vec!("xs".to_string()));
+        assert!(parse_from_str("").unwrap().paths().is_empty());
+    }
+}
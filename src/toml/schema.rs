@@ -0,0 +1,471 @@
+// Schema descriptions for TOML documents.
+//
+// A `Schema` is a list of entries, each describing the value expected at a
+// dotted path (the same syntax as accepted by `Value::lookup`). Entries can
+// be required, or optional with a default value, which `apply_defaults`
+// materializes into a parsed document. String entries can further be
+// restricted to a fixed vocabulary with `one_of`, or to a regular expression
+// with `pattern`. Relationships between keys are expressed with `rule`.
+
+use super::{nearest_match, encode_inline, SpanMap};
+use super::{Value, Boolean, Integer, Float, String, Datetime,
+            Array, TableArray, TableInner, Table};
+
+use std::cell::RefCell;
+use std::collections::hashmap::HashMap;
+use regex::{Regex, Error};
+
+/// The type of value expected by a schema entry
+#[deriving(Show,Clone,PartialEq)]
+pub enum Kind {
+    AnyKind,
+    BooleanKind,
+    IntegerKind,
+    FloatKind,
+    StringKind,
+    DatetimeKind,
+    ArrayKind,
+    TableKind,
+    TableArrayKind
+}
+
+impl Kind {
+    /// Returns `true` if `value` is of this kind
+    pub fn matches(&self, value: &Value) -> bool {
+        match (*self, value) {
+            (AnyKind, _) => true,
+            (BooleanKind, &Boolean(_)) => true,
+            (IntegerKind, &Integer(_)) => true,
+            (FloatKind, &Float(_)) => true,
+            (StringKind, &String(_)) => true,
+            (DatetimeKind, &Datetime(..)) => true,
+            (ArrayKind, &Array(_)) => true,
+            (TableKind, &Table(_)) | (TableKind, &TableInner(_)) => true,
+            (TableArrayKind, &TableArray(_)) => true,
+            _ => false
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            AnyKind => "any value",
+            BooleanKind => "boolean",
+            IntegerKind => "integer",
+            FloatKind => "float",
+            StringKind => "string",
+            DatetimeKind => "datetime",
+            ArrayKind => "array",
+            TableKind => "table",
+            TableArrayKind => "table array"
+        }
+    }
+}
+
+/// A single problem found while validating a document against a schema
+#[deriving(Show,Clone,PartialEq)]
+pub struct Violation {
+    /// The dotted path of the offending value
+    pub path: String,
+    /// A human-readable description of the problem
+    pub message: String,
+    /// The offending value, if there is one
+    pub found: Option<Value>,
+    /// Line and column of the offending value, if known
+    pub position: Option<(uint, uint)>
+}
+
+impl Violation {
+    fn new(path: &str, message: String, found: Option<&Value>) -> Violation {
+        Violation { path: path.to_string(), message: message,
+                    found: found.map(|v| v.clone()), position: None }
+    }
+}
+
+/// Formats `violations` as a multi-line report, one problem per line
+pub fn render(violations: &[Violation]) -> String {
+    let mut report = match violations.len() {
+        0 => return "no problems found\n".to_string(),
+        1 => "1 problem found:\n".to_string(),
+        n => format!("{} problems found:\n", n)
+    };
+    for v in violations.iter() {
+        report.push_str(format!("  {}: {}", v.path, v.message).as_slice());
+        match v.found {
+            Some(ref value) => report.push_str(format!(" (found {})", encode_inline(value)).as_slice()),
+            None => {}
+        }
+        match v.position {
+            Some((line, col)) => report.push_str(format!(" at line {}, column {}", line, col).as_slice()),
+            None => {}
+        }
+        report.push_char('\n');
+    }
+    report
+}
+
+struct Entry {
+    path: String,
+    kind: Kind,
+    required: bool,
+    default: Option<Value>,
+    one_of: Option<Vec<String>>,
+    // the source of the pattern, for messages, and the compiled pattern
+    pattern: Option<(String, Regex)>
+}
+
+impl Entry {
+    fn new(path: &str, kind: Kind, required: bool, default: Option<Value>) -> Entry {
+        Entry { path: path.to_string(), kind: kind, required: required,
+                default: default, one_of: None, pattern: None }
+    }
+
+    fn check(&self, value: &Value, out: &mut Vec<Violation>) {
+        if !self.kind.matches(value) {
+            out.push(self.violation(format!("expected {}, found {}",
+                                            self.kind.name(), value.type_name()), value));
+            return;
+        }
+
+        match self.pattern {
+            Some((ref source, ref re)) => self.check_pattern(source.as_slice(), re, value, out),
+            None => {}
+        }
+
+        match self.one_of {
+            Some(ref allowed) => {
+                let found = match *value {
+                    String(ref s) if allowed.contains(s) => return,
+                    String(ref s) => s.as_slice(),
+                    // nothing to suggest for a value of another kind
+                    _ => {
+                        out.push(self.violation(format!("expected string, found {}", value.type_name()), value));
+                        return;
+                    }
+                };
+                let list: Vec<String> = allowed.iter().map(|a| format!("\"{}\"", a)).collect();
+                let mut message = format!("expected one of {}", list.connect(", "));
+                match nearest_match(found, allowed.iter().map(|a| a.as_slice())) {
+                    Some(suggestion) => {
+                        message.push_str(format!(" (did you mean \"{}\"?)", suggestion).as_slice());
+                    }
+                    None => {}
+                }
+                out.push(self.violation(message, value));
+            }
+            None => {}
+        }
+    }
+
+    fn check_pattern(&self, pattern: &str, re: &Regex, value: &Value, out: &mut Vec<Violation>) {
+        match *value {
+            String(ref s) if re.is_match(s.as_slice()) => {}
+            String(_) => {
+                out.push(self.violation(format!("does not match pattern \"{}\"", pattern), value));
+            }
+            _ => {
+                out.push(self.violation(format!("expected string matching pattern \"{}\"", pattern), value));
+            }
+        }
+    }
+
+    fn violation(&self, message: String, found: &Value) -> Violation {
+        Violation::new(self.path.as_slice(), message, Some(found))
+    }
+}
+
+/// A check run against the whole document. Returns a message describing the
+/// problem on failure. Any closure not borrowing from its environment is
+/// accepted.
+pub type RuleFn = Box<|&Value|: 'static -> Result<(), String>>;
+
+struct Rule {
+    path: String,
+    // calling a closure needs unique access, which `validate` lacks
+    check: RefCell<RuleFn>
+}
+
+pub struct Schema {
+    entries: Vec<Entry>,
+    rules: Vec<Rule>
+}
+
+impl Schema {
+    pub fn new() -> Schema {
+        Schema { entries: vec!(), rules: vec!() }
+    }
+
+    /// Declares a key that must be present in the document
+    pub fn required(&mut self, path: &str, kind: Kind) {
+        self.entries.push(Entry::new(path, kind, true, None));
+    }
+
+    /// Declares a key that may be absent from the document
+    pub fn optional(&mut self, path: &str, kind: Kind) {
+        self.entries.push(Entry::new(path, kind, false, None));
+    }
+
+    /// Declares an optional key which takes the value `default` when absent
+    pub fn with_default(&mut self, path: &str, kind: Kind, default: Value) {
+        self.entries.push(Entry::new(path, kind, false, Some(default)));
+    }
+
+    /// Restricts the string at `path` to one of `allowed`. Declares `path`
+    /// as an optional string if it was not declared before.
+    pub fn one_of(&mut self, path: &str, allowed: &[&str]) {
+        self.entry(path).one_of = Some(allowed.iter().map(|a| a.to_string()).collect());
+    }
+
+    /// Restricts the string at `path` to values matching the regular
+    /// expression `pattern`. The pattern is not anchored implicitly, use
+    /// `^...$` to match the whole value. Declares `path` as an optional
+    /// string if it was not declared before. Fails, without changing the
+    /// schema, if `pattern` is not a valid regular expression.
+    pub fn pattern(&mut self, path: &str, pattern: &str) -> Result<(), Error> {
+        let re = try!(Regex::new(pattern));
+        self.entry(path).pattern = Some((pattern.to_string(), re));
+        Ok(())
+    }
+
+    /// Registers a check which receives the whole document, for constraints
+    /// spanning several keys, e.g. "`tls.cert` is required when `tls.enabled`
+    /// is true". Failures are reported against `path`.
+    pub fn rule(&mut self, path: &str, check: RuleFn) {
+        self.rules.push(Rule { path: path.to_string(), check: RefCell::new(check) });
+    }
+
+    fn entry<'a>(&'a mut self, path: &str) -> &'a mut Entry {
+        match self.entries.iter().position(|e| e.path.as_slice() == path) {
+            Some(idx) => self.entries.get_mut(idx),
+            None => {
+                self.entries.push(Entry::new(path, StringKind, false, None));
+                self.entries.mut_last().unwrap()
+            }
+        }
+    }
+
+    /// Returns the default value declared for `path`, if any
+    pub fn default_for<'a>(&'a self, path: &str) -> Option<&'a Value> {
+        self.entries.iter()
+            .find(|e| e.path.as_slice() == path)
+            .and_then(|e| e.default.as_ref())
+    }
+
+    /// Inserts the default value of every entry missing from `root`,
+    /// creating intermediate tables as needed. Entries whose parent path
+    /// exists but is not a table are left alone; `validate` reports those.
+    pub fn apply_defaults(&self, root: &mut Value) {
+        for entry in self.entries.iter() {
+            match entry.default {
+                Some(ref default) => {
+                    let path: Vec<&str> = entry.path.as_slice().split('.').collect();
+                    insert_missing(root, path.as_slice(), default);
+                }
+                None => {}
+            }
+        }
+    }
+
+    /// Checks `root` against the schema and returns every violation found.
+    /// An empty vector means the document is valid.
+    pub fn validate(&self, root: &Value) -> Vec<Violation> {
+        let mut violations = vec!();
+        for entry in self.entries.iter() {
+            match root.lookup(entry.path.as_slice()) {
+                None if entry.required => {
+                    violations.push(Violation::new(entry.path.as_slice(),
+                                                   "missing required key".to_string(), None));
+                }
+                None => {}
+                Some(value) => entry.check(value, &mut violations)
+            }
+        }
+        for rule in self.rules.iter() {
+            let mut check = rule.check.borrow_mut();
+            match (**check)(root) {
+                Ok(()) => {}
+                Err(message) => {
+                    violations.push(Violation::new(rule.path.as_slice(), message,
+                                                   root.lookup(rule.path.as_slice())));
+                }
+            }
+        }
+        violations
+    }
+
+    /// Like `validate`, but fills in the position of each violation from
+    /// the spans recorded while parsing `root`.
+    pub fn validate_spanned(&self, root: &Value, spans: &SpanMap) -> Vec<Violation> {
+        let mut violations = self.validate(root);
+        for v in violations.mut_iter() {
+            v.position = spans.get(v.path.as_slice()).map(|span| (span.start.line, span.start.col));
+        }
+        violations
+    }
+}
+
+fn insert_missing(node: &mut Value, path: &[&str], default: &Value) {
+    let key = match path.head() {
+        Some(head) => head.to_string(),
+        None => return
+    };
+    match *node {
+        Table(ref mut table) | TableInner(ref mut table) => {
+            if path.len() == 1 {
+                if !table.contains_key(&key) {
+                    table.insert(key, default.clone());
+                }
+                return;
+            }
+            if !table.contains_key(&key) {
+                table.insert(key.clone(), TableInner(box HashMap::new()));
+            }
+            match table.find_mut(&key) {
+                Some(child) => insert_missing(child, path.tail(), default),
+                None => {}
+            }
+        }
+        TableArray(ref mut tables) => {
+            match from_str::<uint>(key.as_slice()) {
+                Some(idx) => {
+                    match tables.mut_iter().nth(idx) {
+                        Some(child) => insert_missing(child, path.tail(), default),
+                        None => {}
+                    }
+                }
+                None => {}
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::{Value, Integer, String, ParserOptions, parse_from_str, parse_from_buffer_detailed};
+    use super::{Schema, IntegerKind, StringKind, TableKind, Violation, render};
+
+    use std::io::BufReader;
+
+    fn validate(schema: &Schema, doc: &str) -> Vec<Violation> {
+        schema.validate(&parse_from_str(doc).unwrap())
+    }
+
+    #[test]
+    fn test_defaults() {
+        let mut schema = Schema::new();
+        schema.with_default("server.port", IntegerKind, Integer(8080));
+        schema.with_default("server.host", StringKind, String("localhost".to_string()));
+        schema.with_default("name.first", StringKind, String("x".to_string()));
+        assert_eq!(schema.default_for("server.port"), Some(&Integer(8080)));
+        assert_eq!(schema.default_for("server"), None);
+
+        let mut root = parse_from_str("name = \"n\"\n[server]\nhost = \"example.org\"").unwrap();
+        schema.apply_defaults(&mut root);
+        assert_eq!(root.lookup("server.port"), Some(&Integer(8080)));
+        assert_eq!(root.lookup("server.host").and_then(|h| h.get_str()), Some(&"example.org".to_string()));
+        // `name` is not a table, so `name.first` is left out
+        assert_eq!(root.lookup("name.first"), None);
+
+        let mut root = parse_from_str("").unwrap();
+        schema.apply_defaults(&mut root);
+        assert_eq!(root.lookup("server.host").and_then(|h| h.get_str()), Some(&"localhost".to_string()));
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut schema = Schema::new();
+        schema.required("server", TableKind);
+        schema.required("server.port", IntegerKind);
+        schema.optional("server.host", StringKind);
+        assert!(validate(&schema, "[server]\nport = 80").is_empty());
+
+        let violations = validate(&schema, "[server]\nhost = 1");
+        assert_eq!(violations.len(), 2);
+        assert_eq!(violations.get(0).path.as_slice(), "server.port");
+        assert_eq!(violations.get(0).message.as_slice(), "missing required key");
+        assert_eq!(violations.get(0).found, None);
+        assert_eq!(violations.get(1).path.as_slice(), "server.host");
+        assert_eq!(violations.get(1).message.as_slice(), "expected string, found integer");
+        assert_eq!(violations.get(1).found, Some(Integer(1)));
+    }
+
+    #[test]
+    fn test_render() {
+        assert_eq!(render(&[]).as_slice(), "no problems found\n");
+
+        let mut schema = Schema::new();
+        schema.required("port", IntegerKind);
+        schema.required("host", StringKind);
+        let violations = validate(&schema, "port = \"80\"");
+        assert_eq!(render(violations.as_slice()).as_slice(),
+                   "2 problems found:\n  port: expected integer, found string (found \"80\")\n  host: missing required key\n");
+    }
+
+    #[test]
+    fn test_rule() {
+        let mut schema = Schema::new();
+        schema.optional("port", IntegerKind);
+        schema.rule("port", box |root: &Value| {
+            match root.lookup("port").and_then(|p| p.get_int()) {
+                Some(port) if port < 1024 => Err("must be at least 1024".to_string()),
+                _ => Ok(())
+            }
+        });
+        assert!(validate(&schema, "port = 8080").is_empty());
+        let violations = validate(&schema, "port = 80");
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations.get(0).path.as_slice(), "port");
+        assert_eq!(violations.get(0).message.as_slice(), "must be at least 1024");
+    }
+
+    #[test]
+    fn test_pattern() {
+        let mut schema = Schema::new();
+        assert!(schema.pattern("name", "^[a-z]+$").is_ok());
+        assert!(validate(&schema, "name = \"web\"").is_empty());
+        let violations = validate(&schema, "name = \"Web 1\"");
+        assert_eq!(violations.get(0).message.as_slice(), "does not match pattern \"^[a-z]+$\"");
+        let violations = validate(&schema, "name = 1");
+        assert_eq!(violations.get(0).message.as_slice(), "expected string, found integer");
+    }
+
+    #[test]
+    fn test_invalid_pattern() {
+        let mut schema = Schema::new();
+        assert!(schema.pattern("name", "[a-z").is_err());
+        // nothing was declared
+        assert!(validate(&schema, "name = 1").is_empty());
+    }
+
+    #[test]
+    fn test_one_of() {
+        let mut schema = Schema::new();
+        schema.one_of("level", &["debug", "info"]);
+        assert!(validate(&schema, "level = \"info\"").is_empty());
+        let violations = validate(&schema, "level = \"debg\"");
+        assert_eq!(violations.get(0).message.as_slice(),
+                   "expected one of \"debug\", \"info\" (did you mean \"debug\"?)");
+
+        let mut schema = Schema::new();
+        schema.required("level", IntegerKind);
+        schema.one_of("level", &["debug", "info"]);
+        let violations = validate(&schema, "level = 1");
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations.get(0).message.as_slice(), "expected string, found integer");
+    }
+
+    #[test]
+    fn test_validate_spanned() {
+        let mut schema = Schema::new();
+        schema.required("server.port", IntegerKind);
+        schema.required("server.host", StringKind);
+        let mut rd = BufReader::new("[server]\nport = \"80\"\n".as_bytes());
+        let output = parse_from_buffer_detailed(&mut rd, ParserOptions::new().spans(true)).unwrap();
+        let violations = schema.validate_spanned(&output.value, &output.spans.unwrap());
+        assert_eq!(violations.len(), 2);
+        assert_eq!(violations.get(0).position, Some((2, 8)));
+        // a missing key has no position
+        assert_eq!(violations.get(1).path.as_slice(), "server.host");
+        assert_eq!(violations.get(1).position, None);
+    }
+}
@@ -0,0 +1,60 @@
+// Memory footprint estimation of Value trees.
+
+use super::{Value, String, Array, TableArray, Append, TableInner, Table};
+
+use std::collections::hashmap::HashMap;
+use std::mem::size_of;
+
+// Heap memory owned by `value`, excluding the `Value` itself
+fn heap_size(value: &Value) -> uint {
+    match *value {
+        String(ref s) => s.capacity(),
+        Array(ref vec) | TableArray(ref vec) | Append(ref vec) => {
+            vec.capacity() * size_of::<Value>() +
+                vec.iter().fold(0, |sum, elm| sum + heap_size(elm))
+        }
+        Table(ref map) | TableInner(ref map) => {
+            // each bucket holds a hash besides key and value
+            let buckets = map.capacity() * (size_of::<u64>() + size_of::<String>() + size_of::<Value>());
+            size_of::<HashMap<String, Value>>() + buckets +
+                map.iter().fold(0, |sum, (k, v)| sum + k.capacity() + heap_size(v))
+        }
+        _ => 0
+    }
+}
+
+impl Value {
+    /// Returns an estimate of the memory occupied by this tree in bytes,
+    /// including string and vector capacities and hash table overhead, but
+    /// not allocator bookkeeping.
+    pub fn estimated_size(&self) -> uint {
+        size_of::<Value>() + heap_size(self)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::{Value, Integer, String, Array, parse_from_str};
+
+    use std::mem::size_of;
+
+    #[test]
+    fn test_estimated_size_of_leaves() {
+        assert_eq!(Integer(1).estimated_size(), size_of::<Value>());
+        let mut s = String::with_capacity(64);
+        s.push_str("abc");
+        assert_eq!(String(s).estimated_size(), size_of::<Value>() + 64);
+    }
+
+    #[test]
+    fn test_estimated_size_of_containers() {
+        let mut vec = Vec::with_capacity(4);
+        vec.push(Integer(1));
+        assert_eq!(Array(vec).estimated_size(), 5 * size_of::<Value>());
+
+        let small = parse_from_str("a = 1").unwrap().estimated_size();
+        let large = parse_from_str("a = 1\n[t]\nb = \"a longer string value\"").unwrap().estimated_size();
+        assert!(small > size_of::<Value>());
+        assert!(large > small + "a longer string value".len());
+    }
+}
@@ -0,0 +1,216 @@
+// Source positions of parsed values.
+//
+// `ParserOptions::spans` records the span of every section header, every
+// key/value pair, array element and inline table member in a `SpanMap`,
+// keyed by the dotted path of the value (elements of arrays and table arrays
+// are addressed by index, e.g. `products.1.name` or `point.coords.0`). Dots
+// and backslashes within keys are escaped, see `join_path`.
+
+use super::{Value, Visitor, Decoder, Error};
+
+use serialize::Decodable;
+use std::collections::hashmap::HashMap;
+
+/// A position in a source document
+#[deriving(Show,Clone,PartialEq,Eq)]
+pub struct Pos {
+    /// Line, starting at 1
+    pub line: uint,
+    /// Column in characters, starting at 1
+    pub col: uint,
+    /// Byte offset, starting at 0
+    pub offset: uint
+}
+
+/// A region of a source document, from `start` up to but excluding `end`
+#[deriving(Show,Clone,PartialEq,Eq)]
+pub struct Span {
+    pub start: Pos,
+    pub end: Pos
+}
+
+/// A value together with the region of the document it was parsed from
+#[deriving(Show,Clone,PartialEq)]
+pub struct Spanned<T> {
+    pub value: T,
+    pub span: Span
+}
+
+/// A `Spanned<T>` field decodes like `T`, additionally receiving the span of
+/// the value if decoded with `from_toml_spanned`. Otherwise, or if the span
+/// is unknown, all positions of the span are zero.
+impl<T: Decodable<Decoder, Error>> Decodable<Decoder, Error> for Spanned<T> {
+    fn decode(d: &mut Decoder) -> Result<Spanned<T>, Error> {
+        let unknown = Pos { line: 0, col: 0, offset: 0 };
+        let span = d.span().unwrap_or(Span { start: unknown, end: unknown });
+        let value = try!(Decodable::decode(d));
+        Ok(Spanned { value: value, span: span })
+    }
+}
+
+/// Appends `key` to the span path `path`, escaping `.` and `\` within the
+/// key with a backslash, so that the quoted key `"a.b"` has the path `a\.b`
+/// and is told apart from the key `b` of table `a`.
+pub fn join_path(path: &str, key: &str) -> String {
+    let mut joined = path.to_string();
+    if !joined.is_empty() { joined.push_char('.') }
+    for c in key.chars() {
+        if c == '.' || c == '\\' { joined.push_char('\\') }
+        joined.push_char(c);
+    }
+    joined
+}
+
+/// The spans of the values of a document, by dotted path (see
+/// `join_path`). The span of a key/value pair or inline table member is that
+/// of its value, the span of a table that of its header. Tables without a
+/// header (like `a` for `[a.b]`) have no span.
+pub struct SpanMap {
+    spans: HashMap<String, Span>
+}
+
+impl SpanMap {
+    pub fn new() -> SpanMap {
+        SpanMap { spans: HashMap::new() }
+    }
+
+    /// Returns the span of the value at `path`
+    pub fn get(&self, path: &str) -> Option<Span> {
+        self.spans.find_equiv(&path).map(|span| *span)
+    }
+
+    /// Looks up `path` in `root` (see `Value::lookup`) and returns the value
+    /// found along with its span. As `Value::lookup` does not know about
+    /// escapes, use `get` for keys containing dots.
+    pub fn lookup<'a>(&self, root: &'a Value, path: &'a str) -> Option<Spanned<&'a Value>> {
+        match (root.lookup(path), self.get(path)) {
+            (Some(value), Some(span)) => Some(Spanned { value: value, span: span }),
+            _ => None
+        }
+    }
+
+    pub fn insert(&mut self, path: String, span: Span) {
+        self.spans.insert(path, span);
+    }
+}
+
+/// A `Visitor` recording the spans of everything passed on to `inner`
+pub struct SpanRecorder<V> {
+    inner: V,
+    spans: SpanMap,
+    // number of elements of each table array, by resolved path
+    counts: HashMap<String, uint>,
+    // resolved path of the current section
+    current: String
+}
+
+impl<V: Visitor> SpanRecorder<V> {
+    pub fn new(inner: V) -> SpanRecorder<V> {
+        SpanRecorder { inner: inner, spans: SpanMap::new(),
+                       counts: HashMap::new(), current: String::new() }
+    }
+
+    /// Returns the wrapped visitor and the recorded spans
+    pub fn unwrap(self) -> (V, SpanMap) {
+        (self.inner, self.spans)
+    }
+
+    // Resolves section `parts` into a path addressing the latest element of
+    // every table array it passes through. For a `[[name]]` header, this
+    // accounts for the new element.
+    fn resolve(&mut self, parts: &[String], is_array: bool) -> String {
+        let mut path = String::new();
+        for (i, part) in parts.iter().enumerate() {
+            path = join_path(path.as_slice(), part.as_slice());
+
+            if is_array && i + 1 == parts.len() {
+                let n = match self.counts.find_mut(&path) {
+                    Some(n) => { *n += 1; *n }
+                    None => 0
+                };
+                if n == 0 { self.counts.insert(path.clone(), 1) }
+                path.push_str(format!(".{}", if n == 0 { 0 } else { n - 1 }).as_slice());
+            } else {
+                match self.counts.find_copy(&path) {
+                    Some(n) => path.push_str(format!(".{}", n - 1).as_slice()),
+                    None => {}
+                }
+            }
+        }
+        path
+    }
+}
+
+impl<V: Visitor> Visitor for SpanRecorder<V> {
+    fn section(&mut self, path: Vec<String>, is_array: bool) -> bool {
+        self.current = self.resolve(path.as_slice(), is_array);
+        self.inner.section(path, is_array)
+    }
+
+    fn pair(&mut self, key: String, val: Value) -> bool {
+        self.inner.pair(key, val)
+    }
+
+    fn section_at(&mut self, path: Vec<String>, is_array: bool, span: Span) -> bool {
+        self.current = self.resolve(path.as_slice(), is_array);
+        self.spans.insert(self.current.clone(), span);
+        self.inner.section_at(path, is_array, span)
+    }
+
+    fn pair_at(&mut self, key: String, val: Value, span: Span) -> bool {
+        let path = join_path(self.current.as_slice(), key.as_slice());
+        self.spans.insert(path, span);
+        self.inner.pair_at(key, val, span)
+    }
+
+    fn nested_at(&mut self, key: &str, path: &[String], span: Span) {
+        let mut full = join_path(self.current.as_slice(), key);
+        for segment in path.iter() {
+            full = join_path(full.as_slice(), segment.as_slice());
+        }
+        self.spans.insert(full, span);
+        self.inner.nested_at(key, path, span)
+    }
+
+    fn take_error(&mut self) -> Option<Error> {
+        self.inner.take_error()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::{Integer, ValueBuilder, parse_with_visitor};
+    use super::{SpanRecorder, join_path};
+
+    use std::io::BufReader;
+
+    #[test]
+    fn test_span_recorder() {
+        let doc = "[[h]]\nn = 1\n[[h]]\nn = 2\n[h.t]\nm = 3\n";
+        let mut recorder = SpanRecorder::new(ValueBuilder::new());
+        let mut rd = BufReader::new(doc.as_bytes());
+        assert!(parse_with_visitor(&mut rd, &mut recorder).is_ok());
+        let (builder, spans) = recorder.unwrap();
+        let root = builder.into_value();
+
+        let at = |path: &str| spans.get(path).map(|span| (span.start.line, span.start.col));
+        assert_eq!(at("h.0"), Some((1, 1)));
+        assert_eq!(at("h.0.n"), Some((2, 5)));
+        assert_eq!(at("h.1.n"), Some((4, 5)));
+        assert_eq!(at("h.1.t.m"), Some((6, 5)));
+        assert_eq!(at("h.2"), None);
+
+        let found = spans.lookup(&root, "h.1.t.m").unwrap();
+        assert_eq!(found.value, &Integer(3));
+        assert_eq!(found.span.start.line, 6);
+        assert!(spans.lookup(&root, "h.1.t.x").is_none());
+    }
+
+    #[test]
+    fn test_join_path() {
+        assert_eq!(join_path("", "a").as_slice(), "a");
+        assert_eq!(join_path("a", "b").as_slice(), "a.b");
+        assert_eq!(join_path("a", "b.c").as_slice(), "a.b\\.c");
+        assert_eq!(join_path("a", "b\\c").as_slice(), "a.b\\\\c");
+    }
+}
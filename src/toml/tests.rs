@@ -0,0 +1,1110 @@
+// Unit tests of the parser, the decoder and the methods of Value defined in
+// lib.rs. The other modules carry their own tests.
+
+use super::{Value, Error, DecodeResult, Base64Bytes, Limits, Extensions};
+use super::{Integer, Float, Boolean, String, Array, Append};
+use super::{TypeMismatch, LimitExceeded, UnexpectedChar, ExpectedEquals};
+use super::{AppendNotArray, MissingField, Cancelled, Spanned, OutOfRange, Rejected, DuplicateKey};
+use super::{IntegerOverflow, UnknownValue, InvalidEscape, BareCarriageReturn, ControlCharacter};
+use super::{MixedArrayTypes, DuplicateSection, ExpectedEndOfLine, InvalidUtf8, NestingTooDeep};
+use super::{UnsupportedInVersion, UnknownFlag, InvalidDatetime};
+use super::{ParseError, ParseErrorInField, IOError, IOErrorInFile, UnexpectedEof};
+use super::{ParserOptions, SpanMap, Visitor, ValueBuilder, TomlVersion, V0_2, V0_4, V1_0};
+use super::{parse_from_str, parse_from_bytes, parse_from_buffer_with, parse_from_buffer_detailed,
+            parse_with_visitor, parse_fragment, parse_from_path, parse_from_file, parse_value_str};
+use super::{from_toml, from_toml_spanned, from_toml_with_flags};
+
+use std::collections::hashmap::HashMap;
+use std::io;
+use std::io::{BufReader, File, TempDir};
+
+#[deriving(Decodable)]
+struct Key {
+    key: Base64Bytes
+}
+
+#[deriving(Decodable)]
+struct Names {
+    names: Vec<String>
+}
+
+#[test]
+fn test_decode_base64_bytes() {
+    let key: Key = from_toml(parse_from_str("key = \"aGVsbG8=\"").unwrap()).unwrap();
+    assert_eq!(key.key, Base64Bytes(vec!(104, 101, 108, 108, 111)));
+}
+
+#[test]
+fn test_decode_base64_bytes_invalid() {
+    let res: DecodeResult<Key> = from_toml(parse_from_str("key = \"not base64!\"").unwrap());
+    assert_eq!(res.err(), Some(TypeMismatch("key".to_string(), "base64 encoded string",
+                                            "string \"not base64!\"".to_string())));
+}
+
+#[test]
+fn test_string_does_not_decode_into_vec() {
+    let res: DecodeResult<Names> = from_toml(parse_from_str("names = \"aGVsbG8=\"").unwrap());
+    assert_eq!(res.err(), Some(TypeMismatch("names".to_string(), "array", "string \"aGVsbG8=\"".to_string())));
+}
+
+#[test]
+fn test_get_bytes_base64() {
+    let value = parse_from_str("key = \"aGk=\"").unwrap();
+    assert_eq!(value.lookup("key").and_then(|v| v.get_bytes_base64()), Some(vec!(104, 105)));
+}
+
+fn parse_limited(s: &str, limits: Limits) -> Result<Value, Error> {
+    let mut rd = BufReader::new(s.as_bytes());
+    parse_from_buffer_with(&mut rd, ParserOptions::new().limits(limits))
+}
+
+// The limit exceeded, if parsing failed because of one
+fn exceeded(res: Result<Value, Error>) -> Option<&'static str> {
+    match res {
+        Err(LimitExceeded(what, _)) => Some(what),
+        _ => None
+    }
+}
+
+#[test]
+fn test_limit_string_len() {
+    let limits = Limits { max_string_len: Some(4), ..Default::default() };
+    assert_eq!(exceeded(parse_limited("a = \"abcde\"", limits.clone())), Some("string length"));
+    assert_eq!(exceeded(parse_limited("\"abcde\" = 1", limits.clone())), Some("string length"));
+    assert!(parse_limited("a = \"abcd\"", limits).is_ok());
+}
+
+#[test]
+fn test_limit_bare_key_len() {
+    let limits = Limits { max_string_len: Some(4), ..Default::default() };
+    assert_eq!(exceeded(parse_limited("abcde = 1", limits.clone())), Some("length of key or word"));
+    assert_eq!(exceeded(parse_limited("[abcde]", limits.clone())), Some("length of key or word"));
+    assert_eq!(exceeded(parse_limited("a = { abcde = 1 }", limits.clone())), Some("length of key or word"));
+    assert!(parse_limited("[abcd]\nabcd = 1", limits).is_ok());
+}
+
+#[test]
+fn test_limit_word_len() {
+    let limits = Limits { max_string_len: Some(4), ..Default::default() };
+    assert_eq!(exceeded(parse_limited("a = truetrue", limits.clone())), Some("length of key or word"));
+    assert!(parse_limited("a = true", limits).is_ok());
+}
+
+#[test]
+fn test_limit_tables() {
+    let limits = Limits { max_tables: Some(2), ..Default::default() };
+    assert_eq!(exceeded(parse_limited("[[a]]\n[[a]]\n[[a]]", limits.clone())), Some("number of tables"));
+    assert_eq!(exceeded(parse_limited("[a]\n[b]\n[c]", limits.clone())), Some("number of tables"));
+    assert!(parse_limited("[[a]]\n[[a]]", limits).is_ok());
+}
+
+#[test]
+fn test_limit_keys_and_elements() {
+    let limits = Limits { max_keys: Some(2), max_array_elements: Some(3), ..Default::default() };
+    assert_eq!(exceeded(parse_limited("a = 1\nb = 2\nc = 3", limits.clone())), Some("number of keys"));
+    assert_eq!(exceeded(parse_limited("a = [[1, 2], [3]]", limits.clone())), Some("number of array elements"));
+    assert!(parse_limited("a = [1, 2, 3]\nb = 2", limits).is_ok());
+}
+
+fn parse_extended(s: &str) -> Result<Value, Error> {
+    let mut extensions = Extensions::new();
+    extensions.register("minutes", box |arg: &str| from_str::<i64>(arg).map(|n| Integer(n * 60)));
+    extensions.register("now", box |arg: &str| {
+        if arg.is_empty() { Some(String("now".to_string())) } else { None }
+    });
+    let mut rd = BufReader::new(s.as_bytes());
+    parse_from_buffer_with(&mut rd, ParserOptions::new().extensions(&mut extensions))
+}
+
+#[test]
+fn test_extension_value() {
+    let value = parse_extended("a = @minutes 5 # comment\nb = [@minutes 1, @minutes 2]").unwrap();
+    assert_eq!(value.lookup("a"), Some(&Integer(300)));
+    assert_eq!(value.lookup("b").and_then(|b| b.lookup_vec(1)), Some(&Integer(120)));
+}
+
+#[test]
+fn test_extension_value_ends_with_line() {
+    let value = parse_extended("a = @now\nb = 1").unwrap();
+    assert_eq!(value.lookup("a"), Some(&String("now".to_string())));
+    assert_eq!(value.lookup("b"), Some(&Integer(1)));
+}
+
+#[test]
+fn test_extension_value_in_inline_table() {
+    let value = parse_extended("t = { a = @minutes 2 }").unwrap();
+    assert_eq!(value.lookup("t.a"), Some(&Integer(120)));
+}
+
+#[test]
+fn test_extension_value_rejected() {
+    assert!(parse_extended("a = @minutes five").is_err());
+    assert!(parse_extended("a = @unknown 5").is_err());
+}
+
+#[test]
+fn test_inline_table_on_one_line() {
+    let value = parse_from_str("point = { x = 1, y = [\n  2\n] }").unwrap();
+    assert_eq!(value.lookup("point.x"), Some(&Integer(1)));
+    assert_eq!(value.lookup("point.y").and_then(|y| y.lookup_vec(0)), Some(&Integer(2)));
+}
+
+#[test]
+fn test_inline_table_spanning_lines() {
+    match parse_from_str("point = {\n  x = 1 }") {
+        Err(UnexpectedChar('\n', _, span)) => assert_eq!((span.start.line, span.start.col), (1, 10)),
+        res => fail!("unexpected result {}", res)
+    }
+    match parse_from_str("point = { x = 1, # comment\n y = 2 }") {
+        Err(UnexpectedChar('#', _, _)) => {}
+        res => fail!("unexpected result {}", res)
+    }
+    assert!(parse_from_str("point = { x =\n 1 }").is_err());
+}
+
+fn parse_append(s: &str) -> Result<Value, Error> {
+    let mut rd = BufReader::new(s.as_bytes());
+    parse_from_buffer_with(&mut rd, ParserOptions::new().allow_append(true))
+}
+
+#[test]
+fn test_append() {
+    let value = parse_append("a += [1]\nb+=[2]\n\"c\" += [3]").unwrap();
+    assert_eq!(value.lookup("a"), Some(&Append(vec!(Integer(1)))));
+    assert_eq!(value.lookup("b"), Some(&Append(vec!(Integer(2)))));
+    assert_eq!(value.lookup("c"), Some(&Append(vec!(Integer(3)))));
+    // without `+=`, arrays are plain arrays
+    assert_eq!(parse_append("a = [1]").unwrap().lookup("a"), Some(&Array(vec!(Integer(1)))));
+}
+
+#[test]
+fn test_append_is_single_token() {
+    match parse_append("a+ = [1]") {
+        Err(ExpectedEquals(span)) => assert_eq!(span.start.col, 3),
+        res => fail!("unexpected result {}", res)
+    }
+    match parse_append("a + = [1]") {
+        Err(ExpectedEquals(span)) => assert_eq!(span.start.col, 4),
+        res => fail!("unexpected result {}", res)
+    }
+    // the default parser has no `+=`
+    assert!(parse_from_str("a += [1]").is_err());
+}
+
+#[test]
+fn test_append_not_array() {
+    match parse_append("a += 1") {
+        Err(AppendNotArray(key, Some(span))) => {
+            assert_eq!(key.as_slice(), "a");
+            assert_eq!(span.start.col, 6);
+        }
+        res => fail!("unexpected result {}", res)
+    }
+}
+
+fn parse_spanned(s: &str) -> (Value, SpanMap) {
+    let mut rd = BufReader::new(s.as_bytes());
+    let output = parse_from_buffer_detailed(&mut rd, ParserOptions::new().spans(true)).unwrap();
+    (output.value, output.spans.unwrap())
+}
+
+#[deriving(Decodable)]
+struct Ports {
+    ports: HashMap<String, Spanned<i64>>
+}
+
+#[test]
+fn test_decode_spanned_in_map() {
+    let (value, spans) = parse_spanned("[ports]\nhttp = 80\nhttps = 443\n");
+    let ports: Ports = from_toml_spanned(value, spans).unwrap();
+    let https = ports.ports.get(&"https".to_string());
+    assert_eq!(https.value, 443);
+    assert_eq!((https.span.start.line, https.span.start.col), (3, 9));
+}
+
+#[deriving(Decodable)]
+struct Server {
+    host: String,
+    port: u16
+}
+
+#[deriving(Decodable)]
+struct Servers {
+    servers: HashMap<String, Server>
+}
+
+#[test]
+fn test_decode_error_path_in_map() {
+    let res: DecodeResult<Servers> = from_toml(parse_from_str(
+        "[servers.alpha]\nhost = \"a\"\nport = \"80\"").unwrap());
+    assert_eq!(res.err(), Some(TypeMismatch("servers.alpha.port".to_string(), "non-negative integer",
+                                            "string \"80\"".to_string())));
+
+    let res: DecodeResult<Servers> = from_toml(parse_from_str("[servers.beta]\nport = 80").unwrap());
+    assert_eq!(res.err(), Some(MissingField("servers.beta.host".to_string(), None)));
+}
+
+#[test]
+fn test_decode_out_of_range() {
+    let res: DecodeResult<Servers> = from_toml(parse_from_str(
+        "[servers.alpha]\nhost = \"a\"\nport = 70000").unwrap());
+    let err = res.err().unwrap();
+    assert_eq!(err, OutOfRange("servers.alpha.port".to_string(), "u16"));
+    assert_eq!(format!("{}", err).as_slice(), "value out of range for `u16` for field `servers.alpha.port`");
+}
+
+#[deriving(Decodable)]
+struct Separator {
+    sep: char,
+    pair: (i64, i64)
+}
+
+#[test]
+fn test_decode_char_and_tuple() {
+    let sep: Separator = from_toml(parse_from_str("sep = \":\"\npair = [1, 2]").unwrap()).unwrap();
+    assert_eq!((sep.sep, sep.pair), (':', (1, 2)));
+
+    let res: DecodeResult<Separator> = from_toml(parse_from_str("sep = \"::\"\npair = [1, 2]").unwrap());
+    assert_eq!(res.err(), Some(TypeMismatch("sep".to_string(), "single character", "string \"::\"".to_string())));
+
+    let res: DecodeResult<Separator> = from_toml(parse_from_str("sep = \":\"\npair = [1]").unwrap());
+    assert_eq!(res.err(), Some(MissingField("pair.1".to_string(), None)));
+}
+
+struct RejectPairs;
+
+impl Visitor for RejectPairs {
+    fn section(&mut self, _path: Vec<String>, _is_array: bool) -> bool { true }
+    fn pair(&mut self, _key: String, _val: Value) -> bool { false }
+}
+
+#[test]
+fn test_rejected() {
+    let mut rd = BufReader::new("[a]\nb = 1".as_bytes());
+    match parse_with_visitor(&mut rd, &mut RejectPairs) {
+        Err(Rejected(span)) => assert_eq!((span.start.line, span.start.col), (2, 5)),
+        res => fail!("unexpected result {}", res)
+    }
+
+    match parse_fragment("a = 1\n[b]\n") {
+        Err(UnexpectedChar('[', _, span)) => assert_eq!((span.start.line, span.start.col), (2, 1)),
+        res => fail!("unexpected result {}", res)
+    }
+}
+
+#[test]
+fn test_spans_of_nested_values() {
+    let (_, spans) = parse_spanned("[p]\nxs = [1, [2, 3]]\npt = { x = 4, y = { z = 5 } }\n");
+    let at = |path: &str| spans.get(path).map(|span| (span.start.line, span.start.col, span.end.col));
+    assert_eq!(at("p.xs"), Some((2, 6, 17)));
+    assert_eq!(at("p.xs.0"), Some((2, 7, 8)));
+    assert_eq!(at("p.xs.1"), Some((2, 10, 16)));
+    assert_eq!(at("p.xs.1.1"), Some((2, 14, 15)));
+    assert_eq!(at("p.pt.x"), Some((3, 12, 13)));
+    assert_eq!(at("p.pt.y.z"), Some((3, 25, 26)));
+    assert_eq!(at("p.xs.2"), None);
+}
+
+#[test]
+fn test_spans_of_dotted_keys() {
+    let (_, spans) = parse_spanned("\"a.b\" = 1\n[a]\nb = 2\n[c]\n\"d\\\\\" = 3\n");
+    let at = |path: &str| spans.get(path).map(|span| (span.start.line, span.start.col));
+    assert_eq!(at("a\\.b"), Some((1, 9)));
+    assert_eq!(at("a.b"), Some((3, 5)));
+    assert_eq!(at("c.d\\\\"), Some((5, 9)));
+}
+
+#[deriving(Decodable)]
+struct Hosts {
+    hosts: HashMap<String, Server>
+}
+
+#[test]
+fn test_decode_error_path_with_dotted_key() {
+    let res: DecodeResult<Hosts> = from_toml(parse_from_str("[hosts.\"example.org\"]\nport = 80").unwrap());
+    assert_eq!(res.err(), Some(MissingField("hosts.example\\.org.host".to_string(), None)));
+}
+
+#[test]
+fn test_parse_detailed() {
+    let mut rd = BufReader::new("a = 1\nb = ?\n[t]\nc = [1, 2]\n".as_bytes());
+    let options = ParserOptions::new().recover(true).stats(true);
+    let output = parse_from_buffer_detailed(&mut rd, options).unwrap();
+    assert_eq!(output.value.lookup("a"), Some(&Integer(1)));
+    assert_eq!(output.value.lookup("t.c").and_then(|c| c.lookup_vec(1)), Some(&Integer(2)));
+    assert_eq!(output.errors.len(), 1);
+    let stats = output.stats.unwrap();
+    assert_eq!((stats.tables, stats.array_elements), (1, 2));
+    assert!(output.spans.is_none());
+}
+
+#[test]
+fn test_parse_sections() {
+    let sections = ["b"];
+    let mut rd = BufReader::new("[a]\nx = ?\n[b]\ny = 1\n".as_bytes());
+    let value = parse_from_buffer_with(&mut rd, ParserOptions::new().sections(sections.as_slice())).unwrap();
+    assert_eq!(value.lookup("a"), None);
+    assert_eq!(value.lookup("b.y"), Some(&Integer(1)));
+}
+
+#[test]
+fn test_parse_interrupted() {
+    let text = String::from_char(4096, '\n');
+    let mut rd = BufReader::new(text.as_bytes());
+    let mut calls = 0u;
+    let res = parse_from_buffer_with(&mut rd, ParserOptions::new().interrupt(1024, |_| { calls += 1; calls < 2 }));
+    assert_eq!(res.err(), Some(Cancelled));
+    assert_eq!(calls, 2);
+}
+
+#[test]
+fn test_set() {
+    let mut value = parse_from_str("[server]\nport = 80\nhosts = [\"a\", \"b\"]").unwrap();
+    assert!(value.set("server.port", Integer(8080)));
+    assert!(value.set("server.hosts.1", String("c".to_string())));
+    assert!(value.set("log.file.level", Integer(3)));
+    assert_eq!(value.lookup("server.port"), Some(&Integer(8080)));
+    assert_eq!(value.lookup("server.hosts").and_then(|h| h.lookup_vec(1)), Some(&String("c".to_string())));
+    assert_eq!(value.lookup("log.file.level"), Some(&Integer(3)));
+}
+
+#[test]
+fn test_set_fails_without_changes() {
+    let mut value = parse_from_str("[server]\nport = 80\nhosts = [\"a\"]").unwrap();
+    let before = value.clone();
+    assert!(!value.set("server.port.x", Integer(1)));
+    // the empty key comes after tables which would be created
+    assert!(!value.set("new.table..x", Integer(1)));
+    assert!(!value.set("new.hosts.", Integer(1)));
+    assert!(!value.set("server.hosts.5", Integer(1)));
+    assert_eq!(value, before);
+}
+
+#[test]
+fn test_remove() {
+    let mut value = parse_from_str("[a.b]\nc = 1\n[d]\ne = 2\nf = 3").unwrap();
+    assert_eq!(value.remove("d.e"), Some(Integer(2)));
+    assert_eq!(value.remove("d.e"), None);
+    assert_eq!(value.lookup("d.f"), Some(&Integer(3)));
+    assert_eq!(value.remove("a.b.x"), None);
+    assert_eq!(value.remove_pruned("a.b.c"), Some(Integer(1)));
+    assert_eq!(value.lookup("a"), None);
+}
+
+#[test]
+fn test_lookup_indices() {
+    let mut value = parse_from_str("xs = [1, 2, 3]\n[[h]]\nn = 1\n[[h]]\nn = 2").unwrap();
+    assert_eq!(value.lookup("h.-1.n"), Some(&Integer(2)));
+    assert_eq!(value.lookup("h.0.n"), Some(&Integer(1)));
+    assert_eq!(value.lookup("h.-3"), None);
+    // plain arrays are not indexed by paths
+    assert_eq!(value.lookup("xs.0"), None);
+    assert!(value.lookup_mut("xs.0").is_none());
+
+    let xs = value.lookup("xs").unwrap();
+    assert_eq!(xs.lookup_vec(2), Some(&Integer(3)));
+    assert_eq!(xs.lookup_vec(3), None);
+    assert_eq!(xs.lookup_vec_signed(-1), Some(&Integer(3)));
+    assert_eq!(xs.lookup_vec_signed(-4), None);
+}
+
+#[deriving(Decodable)]
+struct Steps {
+    steps: Vec<(String, i64)>
+}
+
+#[test]
+fn test_decode_pairs_in_source_order() {
+    let doc = "[steps]\nfetch = 1\nbuild = 2\nalpha = 3\n";
+    let (value, spans) = parse_spanned(doc);
+    let steps: Steps = from_toml_spanned(value, spans).unwrap();
+    assert_eq!(steps.steps, vec!(("fetch".to_string(), 1), ("build".to_string(), 2), ("alpha".to_string(), 3)));
+
+    // without spans, keys are sorted
+    let steps: Steps = from_toml(parse_from_str(doc).unwrap()).unwrap();
+    assert_eq!(steps.steps, vec!(("alpha".to_string(), 3), ("build".to_string(), 2), ("fetch".to_string(), 1)));
+}
+
+#[deriving(Decodable)]
+struct SpannedSteps {
+    steps: Vec<(String, Spanned<i64>)>
+}
+
+#[test]
+fn test_decode_pairs_at_key_paths() {
+    let res: DecodeResult<Steps> = from_toml(parse_from_str("[steps]\nfetch = 1\nbuild = \"x\"").unwrap());
+    let err = res.err().unwrap();
+    assert_eq!(err, TypeMismatch("steps.build".to_string(), "integer", "string \"x\"".to_string()));
+    assert_eq!(format!("{}", err).as_slice(),
+               "expected integer, found string \"x\" for field `steps.build`");
+
+    // the values are found in the spans at the paths of their keys
+    let (value, spans) = parse_spanned("[steps]\nfetch = 1\nbuild = 22\n");
+    let steps: SpannedSteps = from_toml_spanned(value, spans).unwrap();
+    let &(ref key, ref build) = steps.steps.get(1);
+    assert_eq!(key.as_slice(), "build");
+    assert_eq!((build.value, build.span.start.line, build.span.start.col), (22, 3, 9));
+}
+
+#[test]
+fn test_value_builder() {
+    let mut builder = ValueBuilder::new();
+    assert!(builder.pair("a".to_string(), Integer(1)));
+    assert!(builder.section(vec!("t".to_string(), "u".to_string()), false));
+    assert!(builder.pair("b".to_string(), Integer(2)));
+    assert!(!builder.pair("b".to_string(), Integer(3)));
+    assert_eq!(builder.take_error(), Some(DuplicateKey("t.u.b".to_string(), None, None)));
+    assert_eq!(builder.take_error(), None);
+    let value = builder.into_value();
+    assert_eq!(value.lookup("a"), Some(&Integer(1)));
+    assert_eq!(value.lookup("t.u.b"), Some(&Integer(2)));
+
+    let mut builder = ValueBuilder::new();
+    let mut rd = BufReader::new("[t]\nc = 3".as_bytes());
+    assert!(parse_with_visitor(&mut rd, &mut builder).is_ok());
+    assert_eq!(builder.into_value().lookup("t.c"), Some(&Integer(3)));
+}
+
+#[test]
+fn test_parse_from_str() {
+    let doc = "a = 1\n[t]\nb = \"x\"\n";
+    let value = parse_from_str(doc).unwrap();
+    assert!(parse_from_bytes(doc.as_bytes()).unwrap() == value);
+    assert_eq!(value.lookup("t.b"), Some(&String("x".to_string())));
+    match parse_from_str("a 1") {
+        Err(ExpectedEquals(span)) => assert_eq!(span.start.line, 1),
+        other => fail!("unexpected {}", other)
+    }
+}
+
+#[test]
+fn test_parse_interrupt_keeps_going() {
+    let mut text = String::from_char(4096, '\n');
+    text.push_str("a = 1");
+    let mut rd = BufReader::new(text.as_bytes());
+    let mut offsets = vec!();
+    let value = parse_from_buffer_with(&mut rd, ParserOptions::new().interrupt(1024, |offset| {
+        offsets.push(offset);
+        true
+    })).unwrap();
+    assert_eq!(value.lookup("a"), Some(&Integer(1)));
+    assert!(offsets.len() >= 3);
+    assert!(offsets.windows(2).all(|w| w[1] >= w[0] + 1024));
+}
+
+#[test]
+fn test_parse_stats() {
+    let mut rd = BufReader::new("a = 1\n[t.u]\nb = [[1, 2], [3]]".as_bytes());
+    let output = parse_from_buffer_detailed(&mut rd, ParserOptions::new().stats(true)).unwrap();
+    let stats = output.stats.unwrap();
+    assert_eq!((stats.bytes, stats.lines), (29, 3));
+    assert_eq!((stats.tables, stats.keys, stats.array_elements), (1, 2, 5));
+    assert_eq!(stats.max_depth, 5);
+
+    let mut rd = BufReader::new("a = 1".as_bytes());
+    assert!(parse_from_buffer_detailed(&mut rd, ParserOptions::new()).unwrap().stats.is_none());
+}
+
+#[test]
+fn test_integer_range() {
+    let value = parse_from_str("max = 9223372036854775807\nmin = -9223372036854775808").unwrap();
+    assert_eq!(value.lookup("max"), Some(&Integer(::std::i64::MAX)));
+    assert_eq!(value.lookup("min"), Some(&Integer(::std::i64::MIN)));
+    match parse_from_str("a = 9223372036854775808") {
+        Err(IntegerOverflow(span)) => assert_eq!((span.start.col, span.end.col), (5, 24)),
+        other => fail!("unexpected {}", other)
+    }
+    for doc in ["a = -9223372036854775809", "a = 99999999999999999999999"].iter() {
+        assert!(match parse_from_str(*doc) { Err(IntegerOverflow(_)) => true, _ => false });
+    }
+}
+
+#[test]
+fn test_integer_accessors() {
+    assert_eq!(Integer(5).get_u64(), Some(5));
+    assert_eq!(Integer(::std::i64::MAX).get_u64(), Some(::std::i64::MAX as u64));
+    assert_eq!(Integer(-1).get_u64(), None);
+    assert_eq!(String("5".to_string()).get_u64(), None);
+
+    assert_eq!(Integer(-1).get_int_or_err(), Ok(-1));
+    assert_eq!(String("5".to_string()).get_int_or_err(),
+               Err("expected an integer, found string \"5\"".to_string()));
+}
+
+#[test]
+fn test_parse_fragment() {
+    let value = parse_fragment("key = 1\nname = \"x\"\n").unwrap();
+    assert_eq!(value.lookup("key"), Some(&Integer(1)));
+    assert_eq!(value.lookup("name"), Some(&String("x".to_string())));
+    assert!(parse_fragment("").unwrap().get_table().map_or(false, |t| t.is_empty()));
+    match parse_fragment("a = 1\na = 2") {
+        Err(DuplicateKey(key, Some(span), Some(first))) => {
+            assert_eq!(key.as_slice(), "a");
+            assert_eq!((span.start.line, first.start.line), (2, 1));
+        }
+        res => fail!("unexpected result {}", res)
+    }
+}
+
+#[test]
+fn test_parse_sections_nested() {
+    let sections = ["b"];
+    let doc = "r = 0\n[a.b]\nx = \"\"\"\n[b]\"\"\"\n[b.c]\ny = 1\n[[b.d]]\nz = 2\n";
+    let mut rd = BufReader::new(doc.as_bytes());
+    let value = parse_from_buffer_with(&mut rd, ParserOptions::new().sections(sections.as_slice())).unwrap();
+    // `[b]` within the skipped string is not taken for a header
+    assert_eq!(value.paths(), vec!("b".to_string(), "b.c".to_string(), "b.c.y".to_string(),
+                                   "b.d".to_string(), "b.d.0".to_string(), "b.d.0.z".to_string(),
+                                   "r".to_string()));
+
+    // the sections parsed are still checked
+    let mut rd = BufReader::new("[a]\nx = ?\n[b]\ny = ?\n".as_bytes());
+    assert!(parse_from_buffer_with(&mut rd, ParserOptions::new().sections(sections.as_slice())).is_err());
+}
+
+#[test]
+fn test_suggestions() {
+    let err = parse_from_str("a = ture").err().unwrap();
+    assert_eq!(err, UnknownValue("ture".to_string(), Some("true".to_string())));
+    assert_eq!(format!("{}", err).as_slice(), "unknown value `ture`, did you mean `true`?");
+    assert_eq!(parse_from_str("a = fasle").err(),
+               Some(UnknownValue("fasle".to_string(), Some("false".to_string()))));
+    assert_eq!(parse_from_str("a = yes").err(), Some(UnknownValue("yes".to_string(), None)));
+
+    let res: DecodeResult<Servers> = from_toml(parse_from_str(
+        "[servers.gamma]\nhost = \"a\"\nprot = 80").unwrap());
+    assert_eq!(res.err(), Some(MissingField("servers.gamma.port".to_string(), Some("prot".to_string()))));
+}
+
+#[deriving(Decodable)]
+struct Access {
+    mode: u8,
+    other: u64
+}
+
+fn decode_access(doc: &str, mode_flags: &[(&str, u64)]) -> DecodeResult<Access> {
+    from_toml_with_flags(parse_from_str(doc).unwrap(), &[("mode", mode_flags)])
+}
+
+#[test]
+fn test_decode_flags() {
+    let mode_flags: &[(&str, u64)] = &[("read", 4), ("write", 2), ("exec", 1)];
+    let access = decode_access("mode = [\"read\", \"write\"]\nother = 3", mode_flags).unwrap();
+    assert_eq!((access.mode, access.other), (6, 3));
+    // plain integers are accepted as well
+    assert_eq!(decode_access("mode = 5\nother = 0", mode_flags).unwrap().mode, 5);
+
+    let err = decode_access("mode = [\"read\", \"wirte\"]\nother = 0", mode_flags).err().unwrap();
+    assert_eq!(err, UnknownFlag("mode.1".to_string(), "wirte".to_string(), Some("write".to_string())));
+    assert_eq!(err.to_str().as_slice(), "unknown flag `wirte` for field `mode.1`, did you mean `write`?");
+    assert_eq!(decode_access("mode = [1]\nother = 0", mode_flags).err(),
+               Some(TypeMismatch("mode.0".to_string(), "flag name", "integer 1".to_string())));
+    assert_eq!(decode_access("mode = [\"big\"]\nother = 0", &[("big", 256)]).err(),
+               Some(OutOfRange("mode".to_string(), "u8")));
+}
+
+#[deriving(Decodable)]
+struct Permissions {
+    owner: Access,
+    group: Access
+}
+
+#[test]
+fn test_decode_flags_by_path() {
+    let mode_flags: &[(&str, u64)] = &[("read", 4), ("write", 2)];
+    let doc = "[owner]\nmode = [\"read\", \"write\"]\nother = 0\n[group]\nmode = 4\nother = 0";
+    let perms: Permissions = from_toml_with_flags(parse_from_str(doc).unwrap(),
+                                                  &[("owner.mode", mode_flags)]).unwrap();
+    assert_eq!((perms.owner.mode, perms.group.mode), (6, 4));
+
+    // flags registered for `owner.mode` do not apply to `group.mode`
+    let doc = "[owner]\nmode = 0\nother = 0\n[group]\nmode = [\"read\"]\nother = 0";
+    let res: DecodeResult<Permissions> = from_toml_with_flags(parse_from_str(doc).unwrap(),
+                                                              &[("owner.mode", mode_flags)]);
+    assert_eq!(res.err(), Some(TypeMismatch("group.mode".to_string(), "non-negative integer",
+                                            "array".to_string())));
+    // nor does a bare field name match at any depth
+    let res: DecodeResult<Permissions> = from_toml_with_flags(parse_from_str(doc).unwrap(),
+                                                              &[("mode", mode_flags)]);
+    assert!(match res { Err(TypeMismatch(..)) => true, _ => false });
+}
+
+fn parse_error(doc: &str) -> Error {
+    parse_from_str(doc).err().expect("an error")
+}
+
+#[test]
+fn test_invalid_escape() {
+    match parse_error("a = \"x\\uD800\"") {
+        InvalidEscape(code, span) => assert_eq!((code, span.start.col, span.end.col), (0xD800, 7, 13)),
+        err => fail!("unexpected error {}", err)
+    }
+    match parse_error("a = \"\\U00110000\"") {
+        InvalidEscape(code, _) => assert_eq!(code, 0x110000),
+        err => fail!("unexpected error {}", err)
+    }
+    let value = parse_from_str("a = \"\\U0001F600\"").unwrap();
+    assert_eq!(value.lookup("a"), Some(&String("\U0001F600".to_string())));
+}
+
+#[test]
+fn test_bare_carriage_return() {
+    match parse_error("a = 1\rb = 2") {
+        BareCarriageReturn(span) => assert_eq!((span.start.line, span.start.col), (1, 6)),
+        err => fail!("unexpected error {}", err)
+    }
+    match parse_error("a = 1\r\nb = \"x\ry\"") {
+        BareCarriageReturn(span) => assert_eq!((span.start.line, span.start.col), (2, 7)),
+        err => fail!("unexpected error {}", err)
+    }
+    let value = parse_from_str("a = 1\r\nb = \"\"\"x\r\ny\"\"\"\r\n").unwrap();
+    assert_eq!(value.lookup("b"), Some(&String("x\ny".to_string())));
+}
+
+#[test]
+fn test_control_character() {
+    match parse_error("a = \"x\x01\"") {
+        ControlCharacter(code, span) => assert_eq!((code, span.start.col), (1, 7)),
+        err => fail!("unexpected error {}", err)
+    }
+    match parse_error("a = 'x\x7f'") {
+        ControlCharacter(code, _) => assert_eq!(code, 0x7f),
+        err => fail!("unexpected error {}", err)
+    }
+    assert_eq!(format!("{}", parse_error("a = \"\x1b\"")).as_slice(),
+               "line 1, column 6: unescaped control character U+001B in string");
+    assert!(parse_from_str("a = \"x\ty\"").is_ok());
+}
+
+fn parse_version(doc: &str, version: TomlVersion) -> Result<Value, Error> {
+    let mut rd = BufReader::new(doc.as_bytes());
+    parse_from_buffer_with(&mut rd, ParserOptions::new().version(version))
+}
+
+#[test]
+fn test_version() {
+    for doc in ["a = 'x'", "a = 1_000", "a = +1", "a = { b = 1 }", "\"a\" = 1"].iter() {
+        assert!(parse_version(*doc, V0_2).is_err());
+        assert!(parse_version(*doc, V0_4).is_ok());
+    }
+    assert_eq!(parse_version("a = 1_000", V0_4).unwrap().lookup("a"), Some(&Integer(1000)));
+
+    assert!(parse_version("a = inf", V0_4).is_err());
+    assert!(parse_version("a = inf", V1_0).is_ok());
+
+    // arrays of mixed types are only accepted by 1.0
+    match parse_version("a = [1, \"x\"]", V0_4) {
+        Err(MixedArrayTypes(span)) => assert_eq!((span.start.col, span.end.col), (5, 12)),
+        res => fail!("unexpected result {}", res)
+    }
+    assert!(parse_from_str("a = [1, \"x\"]").is_err());
+    assert_eq!(parse_version("a = [1, \"x\"]", V1_0).unwrap().lookup("a"),
+               Some(&Array(vec!(Integer(1), String("x".to_string())))));
+}
+
+#[test]
+fn test_version_datetimes() {
+    assert!(parse_version("a = 1979-05-27T07:32:00Z", V0_2).is_ok());
+    for doc in ["a = 1979-05-27T07:32:00.5Z", "a = 1979-05-27T07:32:00-07:00"].iter() {
+        assert!(parse_version(*doc, V0_2).is_err());
+        assert!(parse_version(*doc, V0_4).is_ok());
+    }
+
+    // local datetimes, `inf` and arrays of mixed types came with 1.0
+    let local = "a = 1979-05-27T07:32:00";
+    match parse_version(local, V0_2) {
+        Err(InvalidDatetime(span)) => assert_eq!((span.start.col, span.end.col), (5, 24)),
+        res => fail!("unexpected result {}", res)
+    }
+    assert!(parse_version(local, V0_4).is_err());
+    assert!(parse_version(local, V1_0).unwrap().lookup("a").unwrap().get_datetime().is_some());
+    assert_eq!(parse_version("a = inf", V0_2).err().map(|e| e.to_str()),
+               Some("line 1, column 5: `inf` requires TOML 1.0".to_string()));
+    match parse_version("a = [1, 1.0]", V0_2) {
+        Err(MixedArrayTypes(_)) => {}
+        res => fail!("unexpected result {}", res)
+    }
+}
+
+#[test]
+fn test_duplicates() {
+    match parse_error("[a]\nx = 1\nx = 2\n") {
+        DuplicateKey(key, Some(span), Some(first)) => {
+            assert_eq!(key.as_slice(), "a.x");
+            assert_eq!((span.start.line, first.start.line), (3, 2));
+        }
+        err => fail!("unexpected error {}", err)
+    }
+    let err = parse_error("[a]\n[b]\n[a]\n");
+    assert_eq!(err.to_str().as_slice(),
+               "line 3, column 1: duplicate section `a`, first defined at line 1");
+    match err {
+        DuplicateSection(path, Some(span), Some(_)) => {
+            assert_eq!(path.as_slice(), "a");
+            assert_eq!(span.start.line, 3);
+        }
+        err => fail!("unexpected error {}", err)
+    }
+    // a key may not be redefined as a section either
+    match parse_error("a = 1\n[a]\n") {
+        DuplicateSection(path, _, _) => assert_eq!(path.as_slice(), "a"),
+        err => fail!("unexpected error {}", err)
+    }
+}
+
+#[test]
+fn test_extend_inline_table() {
+    let err = parse_error("a = { b = 1 }\n[a]\nc = 2\n");
+    assert_eq!(err.to_str().as_slice(),
+               "line 2, column 1: duplicate section `a`, first defined at line 1");
+    match err {
+        DuplicateSection(path, Some(span), _) => {
+            assert_eq!(path.as_slice(), "a");
+            assert_eq!(span.start.line, 2);
+        }
+        err => fail!("unexpected error {}", err)
+    }
+    // neither may the tables nested in it be extended
+    match parse_error("a = { b = { c = 1 } }\n[a.b.d]\n") {
+        DuplicateSection(path, _, None) => assert_eq!(path.as_slice(), "a.b.d"),
+        err => fail!("unexpected error {}", err)
+    }
+    // an inline table in one element of a table array doesn't affect the next
+    let value = parse_from_str("[[t]]\nx = { y = 1 }\n[[t]]\n[t.x]\ny = 2\n").unwrap();
+    assert_eq!(value.lookup("t.0.x.y"), Some(&Integer(1)));
+    assert_eq!(value.lookup("t.1.x.y"), Some(&Integer(2)));
+}
+
+#[test]
+fn test_expected_end_of_line() {
+    let value = parse_from_str("[a] # header\nb = 1\t# value\n").unwrap();
+    assert_eq!(value.lookup("a.b"), Some(&Integer(1)));
+
+    let err = parse_error("[a] junk\n");
+    assert_eq!(err.to_str().as_slice(), "line 1, column 5: expected end of line");
+    match err {
+        ExpectedEndOfLine(span) => {
+            assert_eq!((span.start.line, span.start.col, span.end.col), (1, 5, 9));
+        }
+        err => fail!("unexpected error {}", err)
+    }
+    match parse_error("a = 1 b = 2") {
+        ExpectedEndOfLine(span) => assert_eq!((span.start.col, span.end.col), (7, 12)),
+        err => fail!("unexpected error {}", err)
+    }
+}
+
+#[test]
+fn test_error_span_offsets() {
+    // offsets count bytes, columns characters
+    let span = parse_error("a = \"é\"\nb = é").span().unwrap();
+    assert_eq!((span.start.line, span.start.col, span.start.offset), (2, 5, 13));
+    assert_eq!((span.end.line, span.end.col, span.end.offset), (2, 6, 15));
+    assert_eq!(MissingField("a".to_string(), None).span(), None);
+}
+
+#[test]
+fn test_render() {
+    let source = "a = 1\nport 8080\n";
+    assert_eq!(parse_error(source).render(source).as_slice(),
+               "line 2, column 6: expected `=` after key\n  |\n2 | port 8080\n  |      ^");
+    // tabs are kept, and the whole span is underlined
+    let source = "a\t= 9223372036854775808";
+    let rendered = parse_error(source).render(source);
+    let expected = format!("line 1, column 5: integer out of range\n  |\n1 | {}\n  |  \t  {}",
+                           source, "^".repeat(19));
+    assert_eq!(rendered, expected);
+    assert_eq!(MissingField("a".to_string(), None).render(source).as_slice(), "missing field `a`");
+}
+
+#[test]
+fn test_recover() {
+    let doc = "a = 1\nb = ?\n[t\nx = 1\n[u]\nc = 2 3\nd = 4\ne = ";
+    let mut rd = BufReader::new(doc.as_bytes());
+    let output = parse_from_buffer_detailed(&mut rd, ParserOptions::new().recover(true)).unwrap();
+    let lines: Vec<uint> = output.errors.iter().map(|e| e.span().unwrap().start.line).collect();
+    assert_eq!(lines, vec!(2, 3, 6, 8));
+    // the body of the broken section is skipped along with its header
+    assert_eq!(output.value.lookup("a"), Some(&Integer(1)));
+    assert_eq!(output.value.lookup("x"), None);
+    assert_eq!(output.value.lookup("t"), None);
+    assert_eq!(output.value.lookup("u.d"), Some(&Integer(4)));
+
+    // without recovery, the first error is returned
+    match parse_error(doc) {
+        UnexpectedChar('?', _, span) => assert_eq!(span.start.line, 2),
+        err => fail!("unexpected error {}", err)
+    }
+}
+
+#[test]
+fn test_expected_in_errors() {
+    fn message(doc: &str) -> String { parse_error(doc).to_str() }
+    assert_eq!(message("a = [1, 2").as_slice(),
+               "line 1, column 10: expected `]` to close array, found end of input");
+    assert_eq!(message("[a\nb = 1").as_slice(),
+               "line 1, column 3: expected `]` to close section header, found end of line");
+    assert_eq!(message("a = \x01").as_slice(), "line 1, column 5: expected a value, found U+0001");
+    assert_eq!(message("a = ?").as_slice(), "line 1, column 5: expected a value, found `?`");
+    match parse_error("[[a]\n") {
+        UnexpectedChar('\n', expected, _) => assert_eq!(expected, "`]]` to close section header"),
+        err => fail!("unexpected error {}", err)
+    }
+}
+
+#[test]
+fn test_error_display() {
+    fn message(err: Error) -> String { format!("{}", err) }
+    assert_eq!(message(ParseError).as_slice(), "parse error");
+    assert_eq!(message(ParseErrorInField("a.b".to_string())).as_slice(),
+               "parse error in field `a.b`");
+    assert_eq!(message(Cancelled).as_slice(), "parsing cancelled");
+    let e = io::standard_error(io::EndOfFile);
+    assert_eq!(message(IOError(e.clone())), format!("I/O error: {}", e));
+    let found = "a string".to_string();
+    assert_eq!(message(TypeMismatch(String::new(), "an integer", found.clone())).as_slice(),
+               "expected an integer, found a string");
+    assert_eq!(message(TypeMismatch("port".to_string(), "an integer", found)).as_slice(),
+               "expected an integer, found a string for field `port`");
+    // errors with a span are prefixed by its start
+    assert_eq!(message(parse_error("a = 1\nb = \"x")).as_slice(),
+               "line 2, column 5: unterminated string");
+}
+
+#[test]
+fn test_duplicate_definition_sites() {
+    assert_eq!(parse_error("[server]\nports = [80]\nports = [443]\n").to_str().as_slice(),
+               "line 3, column 9: duplicate key `server.ports`, first defined at line 2");
+    // within a table array, the earlier definition is looked up in the
+    // same element
+    match parse_error("[[s]]\nx = 1\n[[s]]\nx = 2\nx = 3\n") {
+        DuplicateKey(key, Some(span), Some(first)) => {
+            assert_eq!(key.as_slice(), "s.x");
+            assert_eq!((span.start.line, first.start.line), (5, 4));
+        }
+        err => fail!("unexpected error {}", err)
+    }
+    // without spans, only the path is reported
+    let mut builder = ValueBuilder::new();
+    assert!(builder.pair("a".to_string(), Integer(1)));
+    assert!(!builder.pair("a".to_string(), Integer(2)));
+    let err = builder.take_error().unwrap();
+    assert_eq!(err.to_str().as_slice(), "duplicate key `a`");
+}
+
+#[test]
+fn test_io_error_in_file() {
+    let dir = TempDir::new("toml-tests").unwrap();
+    let missing = dir.path().join("missing.toml");
+    match parse_from_path(&missing) {
+        Err(IOErrorInFile(path, e)) => {
+            assert!(path == missing);
+            assert_eq!(e.kind, io::FileNotFound);
+            assert_eq!(format!("{}", IOErrorInFile(path.clone(), e.clone())),
+                       format!("I/O error reading {}: {}", path.display(), e));
+        }
+        res => fail!("unexpected result {}", res)
+    }
+    assert!(match parse_from_file(missing.as_str().unwrap()) {
+        Err(IOErrorInFile(ref path, _)) => *path == missing,
+        _ => false
+    });
+
+    // errors other than I/O errors are passed on as they are
+    let path = dir.path().join("broken.toml");
+    File::create(&path).write_str("a = ?").unwrap();
+    match parse_from_path(&path) {
+        Err(UnexpectedChar('?', _, _)) => {}
+        res => fail!("unexpected result {}", res)
+    }
+}
+
+#[test]
+fn test_invalid_utf8() {
+    let mut doc = "a = 1\nb = \"x".as_bytes().to_vec();
+    doc.push(0xff);
+    doc.push_all("\"\n".as_bytes());
+    match parse_from_bytes(doc.as_slice()) {
+        Err(InvalidUtf8(span)) => {
+            assert_eq!((span.start.line, span.start.col, span.start.offset), (2, 7, 12));
+            assert_eq!(span.end.offset, 13);
+        }
+        res => fail!("unexpected result {}", res)
+    }
+    // a leading byte order mark is skipped
+    let mut doc = vec!(0xefu8, 0xbb, 0xbf);
+    doc.push_all("a = 1".as_bytes());
+    assert_eq!(parse_from_bytes(doc.as_slice()).unwrap().lookup("a"), Some(&Integer(1)));
+}
+
+#[deriving(Decodable)]
+struct Letter {
+    c: char
+}
+
+#[test]
+fn test_malformed_input_fails_cleanly() {
+    for doc in ["[]", "[a.]", "[.a]", "[a..b]", "\"\" = 1", "[[a]]\n[a]", "[a]\n[[a]]",
+                "[a.b.c]\n[a.b]\n[a.b]", "a = 1\n[a.b]", "a = [1, 2", "a = [[1], ["].iter() {
+        assert!(parse_from_str(*doc).is_err(), "{} parsed", doc);
+    }
+    // chars are decoded from single character strings of any length in bytes
+    let letter: DecodeResult<Letter> = from_toml(parse_from_str("c = \"é\"").unwrap());
+    assert_eq!(letter.ok().map(|l| l.c), Some('é'));
+    let letter: DecodeResult<Letter> = from_toml(parse_from_str("c = \"ab\"").unwrap());
+    assert!(letter.is_err());
+}
+
+#[test]
+fn test_max_depth() {
+    fn parse_depth(doc: &str, max_depth: uint) -> Result<Value, Error> {
+        let mut rd = BufReader::new(doc.as_bytes());
+        parse_from_buffer_with(&mut rd, ParserOptions::new().max_depth(max_depth))
+    }
+    assert!(parse_depth("a = [[1]]\nb = { c = [1] }\n[x.y]", 2).is_ok());
+    match parse_depth("a = [[[1]]]", 2) {
+        Err(NestingTooDeep(span)) => assert_eq!(span.start.col, 7),
+        res => fail!("unexpected result {}", res)
+    }
+    assert!(parse_depth("a = { b = { c = [] } }", 2).is_err());
+    match parse_depth("[x.y.z]", 2) {
+        Err(NestingTooDeep(span)) => assert_eq!((span.start.col, span.end.col), (1, 8)),
+        res => fail!("unexpected result {}", res)
+    }
+
+    // deeply nested documents fail with the default limit, instead of
+    // overflowing the stack
+    let doc = format!("a = {}", "[".repeat(100000));
+    assert!(match parse_from_str(doc.as_slice()) { Err(NestingTooDeep(_)) => true, _ => false });
+}
+
+#[test]
+fn test_parse_value_str() {
+    assert_eq!(parse_value_str("[1, 2]"), Ok(Array(vec!(Integer(1), Integer(2)))));
+    assert_eq!(parse_value_str("  \"text\"  # comment\n"), Ok(String("text".to_string())));
+    assert_eq!(parse_value_str("{ a = 1 }").unwrap().lookup("a"), Some(&Integer(1)));
+    match parse_value_str("1 2") {
+        Err(UnexpectedChar('2', expected, span)) => {
+            assert_eq!((expected, span.start.col), ("the end of the value", 3));
+        }
+        res => fail!("unexpected result {}", res)
+    }
+    assert!(match parse_value_str("") { Err(UnexpectedEof("a value", _)) => true, _ => false });
+    assert!(match parse_value_str("a = 1") { Err(UnknownValue(..)) => true, _ => false });
+}
+
+#[test]
+fn test_lookup_mut() {
+    let mut value = parse_from_str("a = 1\nxs = [1]\n[t]\ns = \"x\"\n[[h]]\nn = 1").unwrap();
+    *value.lookup_mut("a").unwrap() = Integer(2);
+    value.lookup_mut("t.s").unwrap().get_str_mut().unwrap().push_str("y");
+    value.lookup_mut("xs").unwrap().get_vec_mut().unwrap().push(Integer(2));
+    *value.lookup_mut("xs").unwrap().lookup_vec_mut(0).unwrap() = Integer(0);
+    *value.lookup_mut("h.0.n").unwrap() = Integer(3);
+    value.get_table_mut().unwrap().insert("b".to_string(), Integer(4));
+    assert_eq!(value.lookup("a"), Some(&Integer(2)));
+    assert_eq!(value.lookup("t.s"), Some(&String("xy".to_string())));
+    assert_eq!(value.lookup("xs"), Some(&Array(vec!(Integer(0), Integer(2)))));
+    assert_eq!(value.lookup("h.0.n"), Some(&Integer(3)));
+    assert_eq!(value.lookup("b"), Some(&Integer(4)));
+    assert_eq!(value.lookup_mut("h").unwrap().get_table_array_mut().map(|h| h.len()), Some(1));
+
+    // paths and getters which don't match the value
+    assert!(value.lookup_mut("a.b").is_none());
+    assert!(value.lookup_mut("h.1").is_none());
+    assert!(value.lookup_mut("xs").unwrap().lookup_vec_mut(2).is_none());
+    assert!(value.lookup_mut("a").unwrap().get_str_mut().is_none());
+    assert!(value.lookup_mut("t").unwrap().get_vec_mut().is_none());
+    assert!(value.lookup_mut("xs").unwrap().get_table_mut().is_none());
+    assert!(value.lookup_mut("t").unwrap().get_table_array_mut().is_none());
+}
+
+#[test]
+fn test_remove_elements_and_pruning() {
+    let doc = "xs = [1, 2, 3]\n[[h]]\nn = 1\n[[h]]\nn = 2\n[a.b]\nc = 1\n[a]\nd = 2\n[e.f]\n";
+    let mut value = parse_from_str(doc).unwrap();
+    assert_eq!(value.remove("xs.-1"), Some(Integer(3)));
+    assert_eq!(value.remove("xs.5"), None);
+    assert_eq!(value.lookup("xs"), Some(&Array(vec!(Integer(1), Integer(2)))));
+    let first = value.remove("h.0").unwrap();
+    assert_eq!(first.lookup("n"), Some(&Integer(1)));
+    assert_eq!(value.lookup("h.0.n"), Some(&Integer(2)));
+
+    // pruning stops at tables which are not empty, and never removes
+    // elements of table arrays
+    assert_eq!(value.remove_pruned("a.b.c"), Some(Integer(1)));
+    assert_eq!(value.lookup("a.b"), None);
+    assert_eq!(value.lookup("a.d"), Some(&Integer(2)));
+    assert_eq!(value.remove_pruned("h.0.n"), Some(Integer(2)));
+    assert!(value.lookup("h.0").is_some());
+    // nothing is pruned if there is nothing to remove
+    assert_eq!(value.remove_pruned("e.f.x"), None);
+    assert!(value.lookup("e.f").is_some());
+    assert_eq!(value.remove(""), None);
+}
+
+#[test]
+fn test_numeric_coercion() {
+    assert_eq!(Integer(5).as_f64(), Some(5.0));
+    assert_eq!(Float(1.5).as_f64(), Some(1.5));
+    assert_eq!(String("5".to_string()).as_f64(), None);
+
+    assert_eq!(Integer(-3).as_i64(), Some(-3));
+    assert_eq!(Float(3.0).as_i64(), Some(3));
+    assert_eq!(Float(-9223372036854775808.0).as_i64(), Some(::std::i64::MIN));
+    assert_eq!(Float(3.5).as_i64(), None);
+    assert_eq!(Float(9223372036854775808.0).as_i64(), None);
+    assert_eq!(Float(::std::f64::NAN).as_i64(), None);
+    assert_eq!(Boolean(true).as_i64(), None);
+
+    assert_eq!(Integer(7).as_u64(), Some(7));
+    assert_eq!(Float(2.0).as_u64(), Some(2));
+    assert_eq!(Integer(-1).as_u64(), None);
+    assert_eq!(Float(-2.0).as_u64(), None);
+}
+
+#[deriving(Decodable)]
+struct Switch {
+    on: bool
+}
+
+#[test]
+fn test_type_mismatch_describes_value() {
+    fn found(doc: &str) -> String {
+        let res: DecodeResult<Switch> = from_toml(parse_from_str(doc).unwrap());
+        match res {
+            Err(TypeMismatch(path, "boolean", found)) => {
+                assert_eq!(path.as_slice(), "on");
+                found
+            }
+            Err(err) => fail!("unexpected error {}", err),
+            Ok(_) => fail!("{} decoded", doc)
+        }
+    }
+    assert_eq!(found("on = 1").as_slice(), "integer 1");
+    assert_eq!(found("on = 1.5").as_slice(), "float 1.5");
+    assert_eq!(found("on = \"a\\tb\"").as_slice(), "string \"a\\tb\"");
+    assert_eq!(found("on = 1979-05-27T07:32:00Z").as_slice(), "datetime 1979-05-27T07:32:00Z");
+    assert_eq!(found("on = [true]").as_slice(), "array");
+    assert_eq!(found("[on]").as_slice(), "table");
+
+    let res: DecodeResult<Switch> = from_toml(parse_from_str("on = 1").unwrap());
+    assert_eq!(format!("{}", res.err().unwrap()).as_slice(),
+               "expected boolean, found integer 1 for field `on`");
+}
+
+#[test]
+fn test_special_floats_by_version() {
+    match parse_version("x = inf", V0_4) {
+        Err(UnsupportedInVersion("inf", V1_0, span)) => {
+            assert_eq!((span.start.col, span.end.col), (5, 8));
+        }
+        res => fail!("unexpected result {}", res)
+    }
+    assert_eq!(format!("{}", parse_version("x = nan", V0_4).err().unwrap()).as_slice(),
+               "line 1, column 5: `nan` requires TOML 1.0");
+    // only the keywords of the selected version are suggested
+    assert_eq!(parse_version("x = inff", V0_4).err(), Some(UnknownValue("inff".to_string(), None)));
+    assert_eq!(parse_from_str("x = inff").err(),
+               Some(UnknownValue("inff".to_string(), Some("inf".to_string()))));
+}
@@ -0,0 +1,236 @@
+// Visitor combinators.
+//
+// Each combinator wraps another `Visitor`, transforming the contents of a
+// document on their way to it, e.g. to load only part of a document:
+//
+//     let mut visitor = Filter::new(ValueBuilder::new(), |path| path.starts_with("server"));
+//     try!(parse_with_visitor(&mut rd, &mut visitor));
+//     let value = visitor.unwrap().into_value();
+//
+// Combinators can be nested, like `Lowercase::new(Filter::new(...))`.
+
+use super::{Value, Visitor, Error};
+use super::span::Span;
+
+use std::ascii::StrAsciiExt;
+
+/// Passes on only those sections and key/value pairs for which a predicate,
+/// called with their dotted path, returns `true`. The pairs of a section
+/// which is dropped are dropped as well.
+pub struct Filter<'a, V> {
+    inner: V,
+    pred: |&str|:'a -> bool,
+    // the current section, and whether it is dropped
+    current: String,
+    skipping: bool
+}
+
+impl<'a, V: Visitor> Filter<'a, V> {
+    pub fn new(inner: V, pred: |&str|:'a -> bool) -> Filter<'a, V> {
+        Filter { inner: inner, pred: pred, current: String::new(), skipping: false }
+    }
+
+    /// Returns the wrapped visitor
+    pub fn unwrap(self) -> V {
+        self.inner
+    }
+
+    fn keep_section(&mut self, path: &[String]) -> bool {
+        self.current = path.connect(".");
+        self.skipping = !(self.pred)(self.current.as_slice());
+        !self.skipping
+    }
+
+    fn keep_pair(&mut self, key: &str) -> bool {
+        if self.skipping { return false }
+        let path = if self.current.is_empty() { key.to_string() }
+                   else { format!("{}.{}", self.current, key) };
+        (self.pred)(path.as_slice())
+    }
+}
+
+impl<'a, V: Visitor> Visitor for Filter<'a, V> {
+    fn section(&mut self, path: Vec<String>, is_array: bool) -> bool {
+        if !self.keep_section(path.as_slice()) { return true }
+        self.inner.section(path, is_array)
+    }
+
+    fn pair(&mut self, key: String, val: Value) -> bool {
+        if !self.keep_pair(key.as_slice()) { return true }
+        self.inner.pair(key, val)
+    }
+
+    fn section_at(&mut self, path: Vec<String>, is_array: bool, span: Span) -> bool {
+        if !self.keep_section(path.as_slice()) { return true }
+        self.inner.section_at(path, is_array, span)
+    }
+
+    fn pair_at(&mut self, key: String, val: Value, span: Span) -> bool {
+        if !self.keep_pair(key.as_slice()) { return true }
+        self.inner.pair_at(key, val, span)
+    }
+
+    fn nested_at(&mut self, key: &str, path: &[String], span: Span) {
+        if self.keep_pair(key) { self.inner.nested_at(key, path, span) }
+    }
+
+    fn take_error(&mut self) -> Option<Error> {
+        self.inner.take_error()
+    }
+}
+
+/// Renames keys, including each segment of section names, using a function
+/// mapping an old key to the new one.
+pub struct Rename<'a, V> {
+    inner: V,
+    rename: |&str|:'a -> String
+}
+
+impl<'a, V: Visitor> Rename<'a, V> {
+    pub fn new(inner: V, rename: |&str|:'a -> String) -> Rename<'a, V> {
+        Rename { inner: inner, rename: rename }
+    }
+
+    /// Returns the wrapped visitor
+    pub fn unwrap(self) -> V {
+        self.inner
+    }
+
+    fn rename_section(&mut self, path: Vec<String>) -> Vec<String> {
+        path.iter().map(|part| (self.rename)(part.as_slice())).collect()
+    }
+}
+
+impl<'a, V: Visitor> Visitor for Rename<'a, V> {
+    fn section(&mut self, path: Vec<String>, is_array: bool) -> bool {
+        let path = self.rename_section(path);
+        self.inner.section(path, is_array)
+    }
+
+    fn pair(&mut self, key: String, val: Value) -> bool {
+        let key = (self.rename)(key.as_slice());
+        self.inner.pair(key, val)
+    }
+
+    fn section_at(&mut self, path: Vec<String>, is_array: bool, span: Span) -> bool {
+        let path = self.rename_section(path);
+        self.inner.section_at(path, is_array, span)
+    }
+
+    fn pair_at(&mut self, key: String, val: Value, span: Span) -> bool {
+        let key = (self.rename)(key.as_slice());
+        self.inner.pair_at(key, val, span)
+    }
+
+    // Only `key` is renamed, as the value is passed on unchanged
+    fn nested_at(&mut self, key: &str, path: &[String], span: Span) {
+        let key = (self.rename)(key);
+        self.inner.nested_at(key.as_slice(), path, span)
+    }
+
+    fn take_error(&mut self) -> Option<Error> {
+        self.inner.take_error()
+    }
+}
+
+/// Lowercases (ASCII) all keys and section names
+pub struct Lowercase<V> {
+    inner: V
+}
+
+fn lower_path(path: Vec<String>) -> Vec<String> {
+    path.iter().map(|part| part.as_slice().to_ascii_lower()).collect()
+}
+
+impl<V: Visitor> Lowercase<V> {
+    pub fn new(inner: V) -> Lowercase<V> {
+        Lowercase { inner: inner }
+    }
+
+    /// Returns the wrapped visitor
+    pub fn unwrap(self) -> V {
+        self.inner
+    }
+}
+
+impl<V: Visitor> Visitor for Lowercase<V> {
+    fn section(&mut self, path: Vec<String>, is_array: bool) -> bool {
+        self.inner.section(lower_path(path), is_array)
+    }
+
+    fn pair(&mut self, key: String, val: Value) -> bool {
+        self.inner.pair(key.as_slice().to_ascii_lower(), val)
+    }
+
+    fn section_at(&mut self, path: Vec<String>, is_array: bool, span: Span) -> bool {
+        self.inner.section_at(lower_path(path), is_array, span)
+    }
+
+    fn pair_at(&mut self, key: String, val: Value, span: Span) -> bool {
+        self.inner.pair_at(key.as_slice().to_ascii_lower(), val, span)
+    }
+
+    fn nested_at(&mut self, key: &str, path: &[String], span: Span) {
+        self.inner.nested_at(key.to_ascii_lower().as_slice(), path, span)
+    }
+
+    fn take_error(&mut self) -> Option<Error> {
+        self.inner.take_error()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::{Error, Integer, Visitor, ValueBuilder, SpanRecorder, DuplicateKey};
+    use super::super::parse_with_visitor;
+    use super::{Filter, Rename, Lowercase};
+
+    use std::io::BufReader;
+
+    fn parse<V: Visitor>(doc: &str, visitor: &mut V) -> Result<(), Error> {
+        let mut rd = BufReader::new(doc.as_bytes());
+        parse_with_visitor(&mut rd, visitor)
+    }
+
+    #[test]
+    fn test_filter() {
+        let doc = "name = \"x\"\n[server]\nport = 80\n[server.tls]\non = true\n[client]\nport = 8080\n";
+        let mut visitor = Filter::new(ValueBuilder::new(), |path| path.starts_with("server"));
+        assert!(parse(doc, &mut visitor).is_ok());
+        let value = visitor.unwrap().into_value();
+        assert_eq!(value.paths(), vec!("server".to_string(), "server.port".to_string(),
+                                       "server.tls".to_string(), "server.tls.on".to_string()));
+    }
+
+    #[test]
+    fn test_rename_and_lowercase() {
+        let doc = "Port = 80\n[Server.Main]\nHost_Name = \"a\"\n";
+        let mut visitor = Lowercase::new(Rename::new(ValueBuilder::new(), |key| key.replace("_", "-")));
+        assert!(parse(doc, &mut visitor).is_ok());
+        let value = visitor.unwrap().unwrap().into_value();
+        assert_eq!(value.paths(), vec!("port".to_string(), "server".to_string(),
+                                       "server.main".to_string(), "server.main.host-name".to_string()));
+    }
+
+    #[test]
+    fn test_lowercase_duplicates() {
+        let mut visitor = Lowercase::new(ValueBuilder::new());
+        match parse("Port = 80\nport = 8080", &mut visitor) {
+            Err(DuplicateKey(key, Some(span), _)) => {
+                assert_eq!(key.as_slice(), "port");
+                assert_eq!(span.start.line, 2);
+            }
+            res => fail!("unexpected result {}", res)
+        }
+    }
+
+    #[test]
+    fn test_spans_through_combinators() {
+        let mut visitor = Lowercase::new(SpanRecorder::new(ValueBuilder::new()));
+        assert!(parse("XS = [1, 2]\n[T]\nP = { Q = 1 }\n", &mut visitor).is_ok());
+        let (builder, spans) = visitor.unwrap().unwrap();
+        assert_eq!(builder.into_value().lookup("t.p.Q"), Some(&Integer(1)));
+        assert_eq!(spans.get("xs.1").map(|span| span.start.col), Some(10));
+        assert_eq!(spans.get("t.p.Q").map(|span| span.start.col), Some(11));
+    }
+}
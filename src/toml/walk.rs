@@ -0,0 +1,98 @@
+// Iteration over all values of a Value tree.
+//
+// `Value::walk` yields every value below the root along with its dotted
+// path, a table or array before its contents. Keys of a table are visited in
+// sorted order, elements of arrays and table arrays by index, which is how
+// they are addressed in the path (`products.1.name`).
+
+use super::{Value, Array, TableArray, Append, TableInner, Table};
+
+/// An iterator over the values of a tree, see `Value::walk`
+pub struct Walk<'a> {
+    // values still to visit, the next one last
+    stack: Vec<(String, &'a Value)>
+}
+
+fn join(path: &str, key: &str) -> String {
+    if path.is_empty() { key.to_string() } else { format!("{}.{}", path, key) }
+}
+
+impl<'a> Walk<'a> {
+    // Schedules the children of `value` at `path` to be visited next
+    fn push_children(&mut self, path: &str, value: &'a Value) {
+        match *value {
+            Array(ref vec) | TableArray(ref vec) | Append(ref vec) => {
+                for (idx, elm) in vec.iter().enumerate().rev() {
+                    self.stack.push((join(path, idx.to_str().as_slice()), elm));
+                }
+            }
+            Table(ref map) | TableInner(ref map) => {
+                let mut keys: Vec<&String> = map.keys().collect();
+                keys.sort();
+                for key in keys.iter().rev() {
+                    self.stack.push((join(path, key.as_slice()), map.get(*key)));
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl<'a> Iterator<(String, &'a Value)> for Walk<'a> {
+    fn next(&mut self) -> Option<(String, &'a Value)> {
+        match self.stack.pop() {
+            Some((path, value)) => {
+                self.push_children(path.as_slice(), value);
+                Some((path, value))
+            }
+            None => None
+        }
+    }
+}
+
+impl Value {
+    /// Returns an iterator over all values below this one, yielding the
+    /// dotted path of each along with the value. Tables and arrays are
+    /// yielded as well, before their contents; use `is_leaf` to skip them.
+    pub fn walk<'a>(&'a self) -> Walk<'a> {
+        let mut walk = Walk { stack: vec!() };
+        walk.push_children("", self);
+        walk
+    }
+
+    /// Returns true for values other than tables, arrays and table arrays
+    pub fn is_leaf(&self) -> bool {
+        match *self {
+            Array(_) | TableArray(_) | Append(_) | Table(_) | TableInner(_) => false,
+            _ => true
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::{Integer, parse_from_str};
+
+    fn strings(v: &[&str]) -> Vec<String> {
+        v.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_walk() {
+        let doc = "b = 1\nxs = [1, [2]]\n[a]\nc = \"x\"\n[[t]]\nn = 1\n[[t]]\n";
+        let value = parse_from_str(doc).unwrap();
+        let paths: Vec<String> = value.walk().map(|(path, _)| path).collect();
+        assert_eq!(paths, strings(&["a", "a.c", "b", "t", "t.0", "t.0.n", "t.1",
+                                    "xs", "xs.0", "xs.1", "xs.1.0"]));
+        let leaves: Vec<String> = value.walk().filter(|&(_, v)| v.is_leaf())
+                                       .map(|(path, _)| path).collect();
+        assert_eq!(leaves, strings(&["a.c", "b", "t.0.n", "xs.0", "xs.1.0"]));
+        assert_eq!(value.walk().find(|&(ref path, _)| path.as_slice() == "t.0.n").map(|(_, v)| v),
+                   Some(&Integer(1)));
+
+        // a leaf has nothing below it
+        assert!(Integer(1).is_leaf());
+        assert_eq!(Integer(1).walk().count(), 0);
+        assert_eq!(parse_from_str("").unwrap().walk().count(), 0);
+    }
+}
@@ -0,0 +1,219 @@
+extern crate toml = "github.com/mneumann/rust-toml#toml";
+
+use std::os;
+
+// Generates Rust struct definitions matching a sample TOML document, for use
+// with `toml::from_toml`:
+//
+//     ./toml2rust config.toml [RootName] > config.rs
+//
+// Tables become structs, named after their key in CamelCase, table arrays
+// become vectors of structs. A key missing from some elements of a table
+// array becomes an `Option`. Integers are typed `i64`.
+
+// The inferred type of a value
+#[deriving(Clone,PartialEq)]
+enum Shape {
+    BoolShape,
+    IntShape,
+    FloatShape,
+    StrShape,
+    DatetimeShape,
+    // the element type of an empty array is unknown
+    UnknownShape,
+    ListShape(Box<Shape>),
+    // fields with their shape, and whether they are optional
+    StructShape(Vec<(String, Shape, bool)>)
+}
+
+fn shape_of(value: &toml::Value) -> Shape {
+    match *value {
+        toml::NoValue => UnknownShape,
+        toml::Boolean(_) => BoolShape,
+        toml::Integer(_) => IntShape,
+        toml::Float(_) => FloatShape,
+        toml::String(_) => StrShape,
+        toml::Datetime(_) => DatetimeShape,
+        toml::Array(ref vec) | toml::TableArray(ref vec) | toml::Append(ref vec) => {
+            let elm = vec.iter().fold(UnknownShape, |shape, elm| unify(shape, shape_of(elm)));
+            ListShape(box elm)
+        }
+        toml::Table(ref map) | toml::TableInner(ref map) => {
+            let mut keys: Vec<&String> = map.keys().collect();
+            keys.sort();
+            StructShape(keys.iter().map(|k| (k.to_string(), shape_of(map.get(*k)), false)).collect())
+        }
+    }
+}
+
+// Combines the shapes of two elements of the same array
+fn unify(a: Shape, b: Shape) -> Shape {
+    match (a, b) {
+        (UnknownShape, b) => b,
+        (a, UnknownShape) => a,
+        (IntShape, FloatShape) | (FloatShape, IntShape) => FloatShape,
+        (ListShape(a), ListShape(b)) => ListShape(box unify(*a, *b)),
+        (StructShape(a), StructShape(b)) => {
+            let mut fields = vec!();
+            for &(ref name, ref shape, optional) in a.iter() {
+                match b.iter().find(|&&(ref n, _, _)| n == name) {
+                    Some(&(_, ref other, other_optional)) => {
+                        fields.push((name.clone(), unify(shape.clone(), other.clone()),
+                                     optional || other_optional));
+                    }
+                    None => fields.push((name.clone(), shape.clone(), true))
+                }
+            }
+            for &(ref name, ref shape, _) in b.iter() {
+                if !a.iter().any(|&(ref n, _, _)| n == name) {
+                    fields.push((name.clone(), shape.clone(), true));
+                }
+            }
+            fields.sort_by(|&(ref a, _, _), &(ref b, _, _)| a.cmp(b));
+            StructShape(fields)
+        }
+        (a, _) => a
+    }
+}
+
+fn camel_case(key: &str) -> String {
+    let mut name = String::new();
+    let mut upper = true;
+    for c in key.chars() {
+        if c.is_alphanumeric() {
+            name.push_char(if upper { c.to_uppercase() } else { c });
+            upper = false;
+        } else {
+            upper = true;
+        }
+    }
+    if name.is_empty() || name.as_slice().char_at(0).is_digit() {
+        name = format!("T{}", name);
+    }
+    name
+}
+
+fn is_identifier(key: &str) -> bool {
+    !key.is_empty() && !key.char_at(0).is_digit() &&
+        key.chars().all(|c| c.is_alphanumeric() || c == '_')
+}
+
+struct Generator {
+    // struct definitions generated so far
+    defs: Vec<String>,
+    names: Vec<String>
+}
+
+impl Generator {
+    // Returns the Rust type for `shape`, generating struct definitions as
+    // needed. `key` is the key the value was found under.
+    fn type_of(&mut self, shape: &Shape, key: &str) -> String {
+        match *shape {
+            BoolShape => "bool".to_string(),
+            IntShape => "i64".to_string(),
+            FloatShape => "f64".to_string(),
+            StrShape | UnknownShape => "String".to_string(),
+            DatetimeShape => "String".to_string(),
+            ListShape(ref elm) => format!("Vec<{}>", self.type_of(&**elm, key)),
+            StructShape(ref fields) => self.gen_struct(camel_case(key), fields.as_slice())
+        }
+    }
+
+    fn gen_struct(&mut self, name: String, fields: &[(String, Shape, bool)]) -> String {
+        let mut name = name;
+        while self.names.contains(&name) {
+            name.push_char('_');
+        }
+        self.names.push(name.clone());
+        let idx = self.defs.len();
+        self.defs.push(String::new());
+
+        let mut def = format!("\\#[deriving(Show,Decodable)]\nstruct {} \\{\n", name);
+        for &(ref key, ref shape, optional) in fields.iter() {
+            if !is_identifier(key.as_slice()) {
+                def.push_str(format!("    // key `{}` is not a valid field name\n", key).as_slice());
+                continue;
+            }
+            if *shape == DatetimeShape {
+                def.push_str(format!("    // datetime `{}` cannot be decoded\n", key).as_slice());
+                continue;
+            }
+            let typ = self.type_of(shape, key.as_slice());
+            let typ = if optional { format!("Option<{}>", typ) } else { typ };
+            def.push_str(format!("    {}: {},\n", key, typ).as_slice());
+        }
+        def.push_str("}\n");
+        *self.defs.get_mut(idx) = def;
+        name
+    }
+}
+
+fn main() {
+    let args = os::args();
+    if args.len() < 2 {
+        println!("usage: ./toml2rust input-file [root-struct-name]");
+        os::set_exit_status(1);
+        return;
+    }
+    let value = match toml::parse_from_file(args.get(1).as_slice()) {
+        Ok(v) => v,
+        Err(e) => {
+            println!("error: {}", e);
+            os::set_exit_status(1);
+            return;
+        }
+    };
+    let root = if args.len() > 2 { args.get(2).as_slice() } else { "Config" };
+
+    let mut gen = Generator { defs: vec!(), names: vec!() };
+    match shape_of(&value) {
+        StructShape(ref fields) => { gen.gen_struct(root.to_string(), fields.as_slice()); }
+        _ => unreachable!()
+    }
+
+    // the crate including the definitions needs `extern crate serialize`
+    println!("// Generated by toml2rust from {}\n", args.get(1));
+    for def in gen.defs.iter() {
+        println!("{}", def);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Generator, StructShape, shape_of, camel_case};
+
+    fn generate(doc: &str) -> String {
+        let value = ::toml::parse_from_str(doc).unwrap();
+        let mut gen = Generator { defs: vec!(), names: vec!() };
+        match shape_of(&value) {
+            StructShape(ref fields) => { gen.gen_struct("Config".to_string(), fields.as_slice()); }
+            _ => fail!("not a table")
+        }
+        gen.defs.concat()
+    }
+
+    #[test]
+    fn test_camel_case() {
+        assert_eq!(camel_case("http-server").as_slice(), "HttpServer");
+        assert_eq!(camel_case("2fa").as_slice(), "T2fa");
+        assert_eq!(camel_case("").as_slice(), "T");
+    }
+
+    #[test]
+    fn test_generate() {
+        let doc = "name = \"x\"\nratio = [1.5, 2.5]\n\
+                   [[servers]]\nhost = \"a\"\n[[servers]]\nhost = \"b\"\nport = 80\n";
+        assert_eq!(generate(doc).as_slice(),
+                   "#[deriving(Show,Decodable)]\nstruct Config {\n    name: String,\n    \
+                    ratio: Vec<f64>,\n    servers: Vec<Servers>,\n}\n\
+                    #[deriving(Show,Decodable)]\nstruct Servers {\n    host: String,\n    \
+                    port: Option<i64>,\n}\n");
+    }
+
+    #[test]
+    fn test_generate_skips_invalid_fields() {
+        assert_eq!(generate("\"a b\" = 1\nwhen = 1979-05-27T07:32:00Z\n").as_slice(),
+                   "#[deriving(Show,Decodable)]\nstruct Config {\n    \
+                    // key `a b` is not a valid field name\n    // datetime `when` cannot be decoded\n}\n");
+    }
+}
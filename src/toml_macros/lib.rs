@@ -0,0 +1,138 @@
+#![crate_id = "github.com/mneumann/rust-toml#toml_macros"]
+#![desc = "Compile-time TOML inclusion for rust-toml"]
+#![license = "MIT"]
+#![crate_type = "dylib"]
+#![feature(macro_registrar, managed_boxes)]
+
+/// The `include_toml!` syntax extension.
+///
+/// `include_toml!("defaults.toml")` parses the named file, relative to the
+/// source file containing the invocation, while compiling, and expands into
+/// an expression constructing the parsed `toml::Value`. A missing file or a
+/// syntax error fails the build, so documents embedded this way are always
+/// valid:
+///
+/// ```rust
+/// #![feature(phase)]
+/// #[phase(syntax)] extern crate toml_macros = "github.com/mneumann/rust-toml#toml_macros";
+/// extern crate toml = "github.com/mneumann/rust-toml#toml";
+///
+/// fn defaults() -> toml::Value { include_toml!("defaults.toml") }
+/// ```
+
+extern crate syntax;
+extern crate toml = "github.com/mneumann/rust-toml#toml";
+
+use std::path::Path;
+
+use syntax::ast::{Name, TokenTree};
+use syntax::codemap::Span;
+use syntax::ext::base::{ExtCtxt, MacResult, MacExpr, DummyResult, NormalTT,
+                        BasicMacroExpander, SyntaxExtension, get_single_str_from_tts};
+use syntax::parse;
+use syntax::parse::token;
+
+#[macro_registrar]
+pub fn macro_registrar(register: |Name, SyntaxExtension|) {
+    register(token::intern("include_toml"),
+             NormalTT(box BasicMacroExpander { expander: expand_include_toml, span: None }, None));
+}
+
+fn expand_include_toml(cx: &mut ExtCtxt, sp: Span, tts: &[TokenTree]) -> Box<MacResult> {
+    let file = match get_single_str_from_tts(cx, sp, tts, "include_toml!") {
+        Some(file) => file,
+        None => return DummyResult::expr(sp)
+    };
+
+    // resolve relative to the directory of the invoking source file
+    let mut path = Path::new(cx.codemap().span_to_filename(sp));
+    path.pop();
+    let path = path.join(file.as_slice());
+
+    let value = match toml::parse_from_path(&path) {
+        Ok(value) => value,
+        Err(e) => {
+            cx.span_err(sp, format!("couldn't parse {}: {}", path.display(), e).as_slice());
+            return DummyResult::expr(sp);
+        }
+    };
+
+    let source = value_expr(&value);
+    let expr = parse::parse_expr_from_source_str(format!("<include_toml!(\"{}\")>", file),
+                                                 source, cx.cfg(), cx.parse_sess());
+    MacExpr::new(expr)
+}
+
+// Returns the source of an expression constructing `value`
+fn value_expr(value: &toml::Value) -> String {
+    match *value {
+        toml::NoValue => "::toml::NoValue".to_string(),
+        toml::Boolean(b) => format!("::toml::Boolean({})", b),
+        toml::Integer(n) if n == ::std::i64::MIN => "::toml::Integer(::std::i64::MIN)".to_string(),
+        toml::Integer(n) => format!("::toml::Integer({}i64)", n),
+        toml::Float(f) => format!("::toml::Float({})", float_expr(f)),
+        toml::String(ref s) => {
+            format!("::toml::String(\"{}\".to_string())", s.as_slice().escape_default())
+        }
+        toml::Datetime(ref dt) => {
+            format!("::toml::Datetime(from_str::<::toml::Datetime>(\"{}\").unwrap())", dt)
+        }
+        toml::Array(ref vec) => format!("::toml::Array({})", vec_expr(vec)),
+        toml::TableArray(ref vec) => format!("::toml::TableArray({})", vec_expr(vec)),
+        toml::Append(ref vec) => format!("::toml::Append({})", vec_expr(vec)),
+        toml::Table(ref map) => format!("::toml::Table({})", map_expr(&**map)),
+        toml::TableInner(ref map) => format!("::toml::TableInner({})", map_expr(&**map))
+    }
+}
+
+fn float_expr(f: f64) -> String {
+    if f.is_nan() {
+        "::std::f64::NAN".to_string()
+    } else if f.is_infinite() {
+        if f > 0.0 { "::std::f64::INFINITY" } else { "::std::f64::NEG_INFINITY" }.to_string()
+    } else {
+        format!("{}f64", f)
+    }
+}
+
+fn vec_expr(vec: &Vec<toml::Value>) -> String {
+    let mut s = "{ let mut v = ::std::vec::Vec::new(); ".to_string();
+    for elm in vec.iter() {
+        s.push_str(format!("v.push({}); ", value_expr(elm)).as_slice());
+    }
+    s.push_str("v }");
+    s
+}
+
+fn map_expr(map: &::std::collections::hashmap::HashMap<String, toml::Value>) -> String {
+    let mut s = "{ let mut t = ::std::collections::hashmap::HashMap::new(); ".to_string();
+    for (key, val) in map.iter() {
+        s.push_str(format!("t.insert(\"{}\".to_string(), {}); ",
+                           key.as_slice().escape_default(), value_expr(val)).as_slice());
+    }
+    s.push_str("box t }");
+    s
+}
+
+#[cfg(test)]
+mod test {
+    use super::{value_expr, float_expr};
+
+    #[test]
+    fn test_value_expr() {
+        assert_eq!(value_expr(&::toml::Integer(-3)).as_slice(), "::toml::Integer(-3i64)");
+        assert_eq!(value_expr(&::toml::Integer(::std::i64::MIN)).as_slice(),
+                   "::toml::Integer(::std::i64::MIN)");
+        assert_eq!(value_expr(&::toml::String("a\"b\n".to_string())).as_slice(),
+                   "::toml::String(\"a\\\"b\\n\".to_string())");
+        assert_eq!(value_expr(&::toml::Array(vec!(::toml::Boolean(true)))).as_slice(),
+                   "::toml::Array({ let mut v = ::std::vec::Vec::new(); v.push(::toml::Boolean(true)); v })");
+    }
+
+    #[test]
+    fn test_float_expr() {
+        assert_eq!(float_expr(1.5).as_slice(), "1.5f64");
+        assert_eq!(float_expr(::std::f64::NAN).as_slice(), "::std::f64::NAN");
+        assert_eq!(float_expr(::std::f64::NEG_INFINITY).as_slice(), "::std::f64::NEG_INFINITY");
+    }
+}